@@ -36,15 +36,29 @@ fn main() -> Result<()> {
     //Command::new("make").args(&["command"]).status().unwrap();
 
     generate_grpc_code()?;
+    emit_build_info();
 
     Ok(())
 }
 
+/// Describes the build for Observe.Status as AURAED_BUILD. Release pipelines set AURAED_COMMIT to the commit being
+/// built.
+fn emit_build_info() {
+    let profile = std::env::var("PROFILE").unwrap_or_default();
+    let target = std::env::var("TARGET").unwrap_or_default();
+    let mut build = format!("{} {}", profile, target);
+    if let Ok(commit) = std::env::var("AURAED_COMMIT") {
+        build = format!("{} {}", build, commit);
+    }
+    println!("cargo:rustc-env=AURAED_BUILD={}", build);
+    println!("cargo:rerun-if-env-changed=AURAED_COMMIT");
+}
+
 fn generate_grpc_code() -> Result<()> {
     let mut tonic_builder = tonic_build::configure();
 
     // Generated services use unwrap. Add them here to suppress the warning.
-    for service in ["meta", "observe", "runtime", "schedule"] {
+    for service in ["image", "meta", "observe", "runtime", "schedule"] {
         tonic_builder = tonic_builder
             .server_mod_attribute(service, "#[allow(clippy::unwrap_used)]");
     }
@@ -55,6 +69,7 @@ fn generate_grpc_code() -> Result<()> {
         "meta.ProcessMeta",
         "runtime.Executable",
         "runtime.ExecutableStatus",
+        "runtime.ExecutableUsage",
     ] {
         tonic_builder = tonic_builder.type_attribute(
             message,
@@ -62,12 +77,34 @@ fn generate_grpc_code() -> Result<()> {
         );
     }
 
-    tonic_builder.compile(
+    // Messages of plain fields that manifests of the schedule subsystem spell out as they are.
+    for message in [
+        "runtime.ExecutableUser",
+        "runtime.ExecutableResources",
+        "runtime.ExecutableNamespaces",
+        "runtime.ExecutableIdMapping",
+        "runtime.TcpProbe",
+        "runtime.HttpProbe",
+    ] {
+        tonic_builder = tonic_builder.type_attribute(
+            message,
+            "#[derive(serde::Deserialize)] #[serde(default, deny_unknown_fields)]",
+        );
+    }
+
+    // Variants of oneofs that are much larger than the others. They are boxed, so that every value of the oneof
+    // does not take up the space of the largest one.
+    let mut prost_config = prost_build::Config::new();
+    let _ = prost_config.boxed(".runtime.ExecInteractiveRequest.request.start");
+
+    tonic_builder.compile_with_config(
+        prost_config,
         &[
             "stdlib/v0/meta.proto",
             "stdlib/v0/runtime.proto",
             "stdlib/v0/schedule.proto",
             "stdlib/v0/observe.proto",
+            "stdlib/v0/image.proto",
         ],
         &["stdlib/v0/"],
     )?;
@@ -62,6 +62,12 @@ struct AuraedOptions {
     #[clap(short, long, value_parser, default_value = auraed::AURAE_SOCK)]
     socket: String,
 
+    #[clap(long, value_parser, default_value = auraed::AURAE_DATA_DIR)]
+    data_dir: String,
+
+    #[clap(long, value_parser, default_value = auraed::AURAE_MANIFESTS_DIR)]
+    manifests_dir: String,
+
     #[clap(short, long)]
     verbose: bool,
 }
@@ -93,19 +99,21 @@ async fn daemon() -> i32 {
         server_key: PathBuf::from(options.server_key),
         ca_crt: PathBuf::from(options.ca_crt),
         socket: PathBuf::from(options.socket),
+        data_dir: PathBuf::from(options.data_dir),
+        manifests_dir: PathBuf::from(options.manifests_dir),
+    };
+
+    let (exit_code, action) = match runtime.run().await {
+        Ok(action) => (EXIT_OKAY, action),
+        Err(e) => {
+            error!("{:?}", e);
+            (EXIT_ERROR, init::PowerAction::PowerOff)
+        }
     };
 
-    let e = runtime.run().await;
-    if e.is_err() {
-        error!("{:?}", e);
-    }
-
-    // Return
-    if e.is_err() {
-        EXIT_ERROR
-    } else {
-        EXIT_OKAY
-    }
+    // PID 1 does not exit, it takes the machine down instead.
+    init::power(action);
+    exit_code
 }
 
 #[tokio::main]
@@ -0,0 +1,123 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+tonic::include_proto!("image");
+
+use crate::image::image_server::Image;
+use std::path::PathBuf;
+use tonic::{Request, Response, Status};
+
+mod oci;
+mod store;
+mod unpack;
+
+pub(crate) use store::{ImageError, ImageStore, RootfsUse, StoredImage};
+#[cfg(test)]
+pub(crate) use {store::tests::archive, unpack::tests::layer};
+
+#[derive(Debug, Default, Clone)]
+pub struct ImageService {
+    store: ImageStore,
+}
+
+impl ImageService {
+    pub(crate) fn new(store: ImageStore) -> Self {
+        Self { store }
+    }
+
+    /// Runs `f` with the store on the blocking thread pool.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(ImageStore) -> Result<T, ImageError> + Send + 'static,
+    ) -> Result<T, Status> {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(store))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::from)
+    }
+}
+
+#[tonic::async_trait]
+impl Image for ImageService {
+    async fn import_image(
+        &self,
+        request: Request<ImportImageRequest>,
+    ) -> Result<Response<ImportImageResponse>, Status> {
+        let path = PathBuf::from(request.into_inner().path);
+        let images = self.blocking(move |store| store.import(&path)).await?;
+        let images = images.iter().map(StoredImage::info).collect();
+        Ok(Response::new(ImportImageResponse { images }))
+    }
+
+    async fn list_images(
+        &self,
+        _request: Request<ListImagesRequest>,
+    ) -> Result<Response<ListImagesResponse>, Status> {
+        let images = self.blocking(|store| store.list()).await?;
+        let images = images.iter().map(StoredImage::info).collect();
+        Ok(Response::new(ListImagesResponse { images }))
+    }
+
+    async fn inspect_image(
+        &self,
+        request: Request<InspectImageRequest>,
+    ) -> Result<Response<InspectImageResponse>, Status> {
+        let reference = request.into_inner().reference;
+        let (image, config) = self
+            .blocking(move |store| {
+                let image = store.get(&reference)?;
+                let config = store.config(&image)?;
+                Ok((image, config))
+            })
+            .await?;
+        let config = config.config.unwrap_or_default();
+        Ok(Response::new(InspectImageResponse {
+            image: Some(image.info()),
+            config: Some(ImageConfig {
+                entrypoint: config.entrypoint.unwrap_or_default(),
+                cmd: config.cmd.unwrap_or_default(),
+                env: config.env.unwrap_or_default(),
+                working_dir: config.working_dir,
+                user: config.user,
+            }),
+        }))
+    }
+
+    async fn remove_image(
+        &self,
+        request: Request<RemoveImageRequest>,
+    ) -> Result<Response<RemoveImageResponse>, Status> {
+        let reference = request.into_inner().reference;
+        let image =
+            self.blocking(move |store| store.remove(&reference)).await?;
+        Ok(Response::new(RemoveImageResponse { image: Some(image.info()) }))
+    }
+}
@@ -0,0 +1,803 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::image::oci::{self, Descriptor};
+use crate::image::unpack::unpack_layer;
+use crate::image::ImageInfo;
+use crate::meta;
+use crate::AURAE_DATA_DIR;
+use flate2::read::GzDecoder;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::Status;
+
+/// The only digest algorithm images are stored with.
+const SHA256: &str = "sha256";
+
+/// Where the images of the store are recorded, below the root of the store.
+const IMAGES_FILE: &str = "images.json";
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ImageError {
+    #[error("image {reference} not found")]
+    NotFound { reference: String },
+    #[error("invalid image layout: {0}")]
+    InvalidLayout(String),
+    #[error("unsupported digest {digest}, only sha256 is supported")]
+    UnsupportedDigest { digest: String },
+    #[error("digest mismatch for blob {digest}: content hashes to {actual}")]
+    DigestMismatch { digest: String, actual: String },
+    #[error(
+        "size mismatch for blob {digest}: expected {expected}, found {actual}"
+    )]
+    SizeMismatch { digest: String, expected: u64, actual: u64 },
+    #[error("blob {digest} is missing")]
+    MissingBlob { digest: String },
+    #[error("unsupported layer media type {0}")]
+    UnsupportedMediaType(String),
+    #[error("image {reference} is used by running containers")]
+    InUse { reference: String },
+    #[error("failed to {context}: {source}")]
+    Io { context: String, source: io::Error },
+    #[error("invalid {context}: {source}")]
+    Json { context: String, source: serde_json::Error },
+}
+
+impl From<ImageError> for Status {
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::NotFound { .. } => Status::not_found(e.to_string()),
+            ImageError::Io { ref source, .. }
+                if source.kind() == io::ErrorKind::NotFound =>
+            {
+                Status::not_found(e.to_string())
+            }
+            ImageError::Io { .. } => Status::internal(e.to_string()),
+            ImageError::InUse { .. } => {
+                Status::failed_precondition(e.to_string())
+            }
+            ImageError::InvalidLayout(_)
+            | ImageError::UnsupportedDigest { .. }
+            | ImageError::DigestMismatch { .. }
+            | ImageError::SizeMismatch { .. }
+            | ImageError::MissingBlob { .. }
+            | ImageError::UnsupportedMediaType(_)
+            | ImageError::Json { .. } => {
+                Status::invalid_argument(e.to_string())
+            }
+        }
+    }
+}
+
+/// Wraps an io::Error with what was being done when it happened.
+pub(crate) fn io_error(
+    context: impl Into<String>,
+) -> impl FnOnce(io::Error) -> ImageError {
+    let context = context.into();
+    move |source| ImageError::Io { context, source }
+}
+
+/// StoredImage is an image as it is recorded by the store.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredImage {
+    pub reference: String,
+    pub digest: String,
+    pub config_digest: String,
+    pub layers: Vec<StoredLayer>,
+    pub size: u64,
+    pub architecture: String,
+    pub os: String,
+    pub imported: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StoredLayer {
+    pub digest: String,
+    pub media_type: String,
+}
+
+impl StoredImage {
+    pub fn info(&self) -> ImageInfo {
+        ImageInfo {
+            meta: Some(meta::AuraeMeta {
+                name: self.reference.clone(),
+                message: "-".to_string(),
+            }),
+            reference: self.reference.clone(),
+            digest: self.digest.clone(),
+            config_digest: self.config_digest.clone(),
+            layers: self.layers.iter().map(|l| l.digest.clone()).collect(),
+            size: self.size,
+            architecture: self.architecture.clone(),
+            os: self.os.clone(),
+            imported: self.imported,
+        }
+    }
+
+    /// The digests of the blobs the image consists of.
+    fn blobs(&self) -> impl Iterator<Item = &str> {
+        [self.digest.as_str(), self.config_digest.as_str()]
+            .into_iter()
+            .chain(self.layers.iter().map(|l| l.digest.as_str()))
+    }
+}
+
+/// ImageStore keeps images as content addressed blobs below its root, the
+/// way an OCI image layout does, along with the root filesystems unpacked
+/// from them.
+///
+/// All of its methods block, and are meant to be called from the blocking
+/// thread pool.
+#[derive(Debug, Clone)]
+pub(crate) struct ImageStore {
+    root: PathBuf,
+    /// Lock serializes the changes to the store, and counts the users of
+    /// the root filesystem of every image by its digest.
+    lock: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// RootfsUse keeps the root filesystem of an image from being removed while
+/// a container uses it.
+#[derive(Debug)]
+pub(crate) struct RootfsUse {
+    lock: Arc<Mutex<HashMap<String, usize>>>,
+    digest: String,
+}
+
+impl Drop for RootfsUse {
+    fn drop(&mut self) {
+        let mut users = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = users.get_mut(&self.digest) {
+            *count -= 1;
+            if *count == 0 {
+                let _ = users.remove(&self.digest);
+            }
+        }
+    }
+}
+
+impl Default for ImageStore {
+    fn default() -> Self {
+        Self::new(Path::new(AURAE_DATA_DIR).join("images"))
+    }
+}
+
+impl ImageStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root, lock: Arc::default() }
+    }
+
+    /// Imports the images of the OCI image-layout archive at `path`. The
+    /// archive may be compressed with gzip.
+    pub fn import(&self, path: &Path) -> Result<Vec<StoredImage>, ImageError> {
+        let file = File::open(path)
+            .map_err(io_error(format!("open {}", path.display())))?;
+        let mut reader = BufReader::new(file);
+        let head = reader
+            .fill_buf()
+            .map_err(io_error(format!("read {}", path.display())))?;
+        let reader: Box<dyn Read> = match head.starts_with(&[0x1f, 0x8b]) {
+            true => Box::new(GzDecoder::new(reader)),
+            false => Box::new(reader),
+        };
+
+        let _guard = self.lock();
+        let mut written = Vec::new();
+        let imported = self.read_archive(reader, &mut written);
+        // Blobs of the archive that no image consists of, because the import
+        // failed or because they are for other platforms, are not kept.
+        let removed = self.read_images().and_then(|images| {
+            self.remove_unused(&images, written.iter().map(String::as_str))
+        });
+        let imported = imported?;
+        removed?;
+        Ok(imported)
+    }
+
+    /// Stores the blobs of the archive in `reader`, recording their digests
+    /// in `written`, and records the images in it.
+    fn read_archive(
+        &self,
+        reader: Box<dyn Read>,
+        written: &mut Vec<String>,
+    ) -> Result<Vec<StoredImage>, ImageError> {
+        let mut layout = None;
+        let mut index = None;
+        let mut archive = tar::Archive::new(reader);
+        let entries = archive.entries().map_err(io_error("read archive"))?;
+        for entry in entries {
+            let mut entry = entry.map_err(io_error("read archive"))?;
+            let name = entry
+                .path()
+                .map_err(io_error("read archive"))?
+                .components()
+                .filter_map(|c| c.as_os_str().to_str().map(String::from))
+                .filter(|c| c != ".")
+                .collect::<Vec<_>>();
+            match name.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                ["oci-layout"] => {
+                    layout = Some(read_json::<oci::Layout>(
+                        &mut entry,
+                        "oci-layout",
+                    )?)
+                }
+                ["index.json"] => {
+                    index =
+                        Some(read_json::<oci::Index>(&mut entry, "index.json")?)
+                }
+                ["blobs", algorithm, encoded] => {
+                    self.write_blob(algorithm, encoded, &mut entry)?;
+                    written.push(format!("{}:{}", algorithm, encoded));
+                }
+                _ => {}
+            }
+        }
+        let layout = layout.ok_or_else(|| {
+            ImageError::InvalidLayout("oci-layout is missing".into())
+        })?;
+        if !layout.image_layout_version.starts_with("1.") {
+            return Err(ImageError::InvalidLayout(format!(
+                "unsupported image layout version {}",
+                layout.image_layout_version
+            )));
+        }
+        let index = index.ok_or_else(|| {
+            ImageError::InvalidLayout("index.json is missing".into())
+        })?;
+
+        let mut imported = Vec::new();
+        for (descriptor, reference) in self.manifests(&index, None)? {
+            imported.push(self.image(&descriptor, reference)?);
+        }
+        if imported.is_empty() {
+            return Err(ImageError::InvalidLayout(
+                "no image for this platform".into(),
+            ));
+        }
+
+        let mut images = self.read_images()?;
+        for image in &imported {
+            info!("Imported image {} ({})", image.reference, image.digest);
+            images.retain(|i| i.reference != image.reference);
+            images.push(image.clone());
+        }
+        self.write_images(&images)?;
+        Ok(imported)
+    }
+
+    pub fn list(&self) -> Result<Vec<StoredImage>, ImageError> {
+        let _guard = self.lock();
+        self.read_images()
+    }
+
+    /// Finds an image by its reference or by its digest.
+    pub fn get(&self, reference: &str) -> Result<StoredImage, ImageError> {
+        let _guard = self.lock();
+        self.find(&self.read_images()?, reference).cloned()
+    }
+
+    pub fn config(
+        &self,
+        image: &StoredImage,
+    ) -> Result<oci::ImageConfig, ImageError> {
+        let config = self.read_blob(&image.config_digest)?;
+        parse_json(&config, "image config")
+    }
+
+    /// Removes an image, along with its blobs and root filesystem as far as
+    /// no other image shares them.
+    /// Images whose root filesystem is used by containers are not removed.
+    pub fn remove(&self, reference: &str) -> Result<StoredImage, ImageError> {
+        let users = self.lock();
+        let mut images = self.read_images()?;
+        let image = self.find(&images, reference)?.clone();
+        images.retain(|i| i != &image);
+        let last = !images.iter().any(|i| i.digest == image.digest);
+        if last && users.contains_key(&image.digest) {
+            return Err(ImageError::InUse { reference: image.reference });
+        }
+        self.write_images(&images)?;
+
+        self.remove_unused(&images, image.blobs())?;
+        if last {
+            let rootfs = self.rootfs_path(&image)?;
+            if rootfs.exists() {
+                fs::remove_dir_all(&rootfs).map_err(io_error(format!(
+                    "remove {}",
+                    rootfs.display()
+                )))?;
+            }
+        }
+        info!("Removed image {} ({})", image.reference, image.digest);
+        Ok(image)
+    }
+
+    /// Returns the root filesystem of an image, and unpacks its layers into
+    /// it first if that has not happened yet. The root filesystem is kept
+    /// for a container that uses it until the returned RootfsUse is dropped.
+    pub fn use_rootfs(
+        &self,
+        image: &StoredImage,
+    ) -> Result<(PathBuf, RootfsUse), ImageError> {
+        let mut users = self.lock();
+        // The image may have been removed since it was looked up.
+        let _ = self.find(&self.read_images()?, &image.digest)?;
+        let rootfs = self.unpack(image)?;
+        *users.entry(image.digest.clone()).or_default() += 1;
+        let used =
+            RootfsUse { lock: self.lock.clone(), digest: image.digest.clone() };
+        Ok((rootfs, used))
+    }
+
+    /// Unpacks the root filesystem of an image if needed. The lock has to
+    /// be held.
+    fn unpack(&self, image: &StoredImage) -> Result<PathBuf, ImageError> {
+        let rootfs = self.rootfs_path(image)?;
+        if rootfs.exists() {
+            return Ok(rootfs);
+        }
+        // Layers are unpacked next to the root filesystem, so that a failed
+        // unpack never leaves a partial root filesystem behind.
+        let partial = rootfs.with_extension("partial");
+        if partial.exists() {
+            fs::remove_dir_all(&partial)
+                .map_err(io_error(format!("remove {}", partial.display())))?;
+        }
+        fs::create_dir_all(&partial)
+            .map_err(io_error(format!("create {}", partial.display())))?;
+        for layer in &image.layers {
+            if layer.media_type.contains("zstd") {
+                return Err(ImageError::UnsupportedMediaType(
+                    layer.media_type.clone(),
+                ));
+            }
+            let path = self.blob_path(&layer.digest)?;
+            let file = File::open(&path)
+                .map_err(io_error(format!("open {}", path.display())))?;
+            let mut reader = BufReader::new(file);
+            let head = reader
+                .fill_buf()
+                .map_err(io_error(format!("read {}", path.display())))?;
+            match head.starts_with(&[0x1f, 0x8b]) {
+                true => unpack_layer(GzDecoder::new(reader), &partial)?,
+                false => unpack_layer(reader, &partial)?,
+            }
+        }
+        fs::rename(&partial, &rootfs)
+            .map_err(io_error(format!("create {}", rootfs.display())))?;
+        info!("Unpacked image {} into {}", image.reference, rootfs.display());
+        Ok(rootfs)
+    }
+
+    /// Prepares an empty writable layer for the container `name`, to be
+    /// stacked on the root filesystem of its image with overlayfs. Returns
+    /// the directory of the layer, with the "upper" and "work" directories
+    /// of overlayfs and the "rootfs" directory to mount it on below it. The
+    /// layer of a container is kept after it stops, until the next
+    /// container of the same name starts.
+    pub fn container_layer(&self, name: &str) -> Result<PathBuf, ImageError> {
+        let _guard = self.lock();
+        let layer = self.root.join("containers").join(name);
+        if layer.exists() {
+            fs::remove_dir_all(&layer)
+                .map_err(io_error(format!("remove {}", layer.display())))?;
+        }
+        for dir in ["upper", "work", "rootfs"] {
+            let dir = layer.join(dir);
+            fs::create_dir_all(&dir)
+                .map_err(io_error(format!("create {}", dir.display())))?;
+        }
+        Ok(layer)
+    }
+
+    /// Removes the blobs of `digests` that none of `images` consists of.
+    fn remove_unused<'a>(
+        &self,
+        images: &[StoredImage],
+        digests: impl Iterator<Item = &'a str>,
+    ) -> Result<(), ImageError> {
+        let kept: HashSet<&str> =
+            images.iter().flat_map(|i| i.blobs()).collect();
+        for digest in digests.filter(|d| !kept.contains(d)) {
+            let path = self.blob_path(digest)?;
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(io_error(format!("remove {}", path.display()))(
+                        e,
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn find<'a>(
+        &self,
+        images: &'a [StoredImage],
+        reference: &str,
+    ) -> Result<&'a StoredImage, ImageError> {
+        images
+            .iter()
+            .find(|i| i.reference == reference)
+            .or_else(|| images.iter().find(|i| i.digest == reference))
+            .ok_or_else(|| ImageError::NotFound {
+                reference: reference.to_string(),
+            })
+    }
+
+    /// The manifests of the images in `index` for the platform of the node,
+    /// with the references they were given. Nested indexes are followed.
+    fn manifests(
+        &self,
+        index: &oci::Index,
+        reference: Option<&str>,
+    ) -> Result<Vec<(Descriptor, Option<String>)>, ImageError> {
+        let mut manifests = Vec::new();
+        for descriptor in &index.manifests {
+            if !matches_platform(descriptor) {
+                continue;
+            }
+            let reference =
+                descriptor.reference().or(reference).map(String::from);
+            if descriptor.is_index() {
+                let nested = self.read_descriptor(descriptor)?;
+                let nested: oci::Index = parse_json(&nested, "image index")?;
+                manifests
+                    .extend(self.manifests(&nested, reference.as_deref())?);
+            } else {
+                manifests.push((descriptor.clone(), reference));
+            }
+        }
+        Ok(manifests)
+    }
+
+    /// Reads the manifest `descriptor` points to, and checks that every blob
+    /// of the image is in the store.
+    fn image(
+        &self,
+        descriptor: &Descriptor,
+        reference: Option<String>,
+    ) -> Result<StoredImage, ImageError> {
+        let manifest = self.read_descriptor(descriptor)?;
+        let manifest: oci::Manifest = parse_json(&manifest, "image manifest")?;
+        let config = self.read_descriptor(&manifest.config)?;
+        let config: oci::ImageConfig = parse_json(&config, "image config")?;
+        let mut size = manifest.config.size;
+        for layer in &manifest.layers {
+            let path = self.blob_path(&layer.digest)?;
+            let actual = fs::metadata(&path)
+                .map_err(|_| ImageError::MissingBlob {
+                    digest: layer.digest.clone(),
+                })?
+                .len();
+            if actual != layer.size {
+                return Err(ImageError::SizeMismatch {
+                    digest: layer.digest.clone(),
+                    expected: layer.size,
+                    actual,
+                });
+            }
+            size += layer.size;
+        }
+        Ok(StoredImage {
+            reference: reference.unwrap_or_else(|| descriptor.digest.clone()),
+            digest: descriptor.digest.clone(),
+            config_digest: manifest.config.digest,
+            layers: manifest
+                .layers
+                .into_iter()
+                .map(|l| StoredLayer {
+                    digest: l.digest,
+                    media_type: l.media_type,
+                })
+                .collect(),
+            size,
+            architecture: config.architecture,
+            os: config.os,
+            imported: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as i64)
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Stores the blob in `reader` if its content matches its digest.
+    fn write_blob(
+        &self,
+        algorithm: &str,
+        encoded: &str,
+        reader: &mut impl Read,
+    ) -> Result<(), ImageError> {
+        let digest = format!("{}:{}", algorithm, encoded);
+        let path = self.blob_path(&digest)?;
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)
+            .map_err(io_error(format!("create {}", dir.display())))?;
+        let partial = path.with_extension("partial");
+        let mut file = File::create(&partial)
+            .map_err(io_error(format!("create {}", partial.display())))?;
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf).map_err(io_error("read archive"))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            file.write_all(&buf[..n])
+                .map_err(io_error(format!("write {}", partial.display())))?;
+        }
+        let actual = hex::encode(hasher.finalize());
+        if actual != encoded {
+            let _ = fs::remove_file(&partial);
+            return Err(ImageError::DigestMismatch {
+                digest,
+                actual: format!("{}:{}", SHA256, actual),
+            });
+        }
+        fs::rename(&partial, &path)
+            .map_err(io_error(format!("create {}", path.display())))
+    }
+
+    fn read_blob(&self, digest: &str) -> Result<Vec<u8>, ImageError> {
+        let path = self.blob_path(digest)?;
+        fs::read(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                ImageError::MissingBlob { digest: digest.to_string() }
+            }
+            _ => io_error(format!("read {}", path.display()))(e),
+        })
+    }
+
+    fn read_descriptor(
+        &self,
+        descriptor: &Descriptor,
+    ) -> Result<Vec<u8>, ImageError> {
+        let blob = self.read_blob(&descriptor.digest)?;
+        if blob.len() as u64 != descriptor.size {
+            return Err(ImageError::SizeMismatch {
+                digest: descriptor.digest.clone(),
+                expected: descriptor.size,
+                actual: blob.len() as u64,
+            });
+        }
+        Ok(blob)
+    }
+
+    fn blob_path(&self, digest: &str) -> Result<PathBuf, ImageError> {
+        let unsupported =
+            || ImageError::UnsupportedDigest { digest: digest.to_string() };
+        let encoded = digest.strip_prefix("sha256:").ok_or_else(unsupported)?;
+        if encoded.len() != 64
+            || !encoded.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        {
+            return Err(unsupported());
+        }
+        Ok(self.root.join("blobs").join(SHA256).join(encoded))
+    }
+
+    fn rootfs_path(&self, image: &StoredImage) -> Result<PathBuf, ImageError> {
+        let blob = self.blob_path(&image.digest)?;
+        let encoded = blob.file_name().unwrap_or_default();
+        Ok(self.root.join("rootfs").join(encoded))
+    }
+
+    fn read_images(&self) -> Result<Vec<StoredImage>, ImageError> {
+        let path = self.root.join(IMAGES_FILE);
+        match fs::read(&path) {
+            Ok(images) => parse_json(&images, IMAGES_FILE),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(io_error(format!("read {}", path.display()))(e)),
+        }
+    }
+
+    fn write_images(&self, images: &[StoredImage]) -> Result<(), ImageError> {
+        let path = self.root.join(IMAGES_FILE);
+        let partial = path.with_extension("partial");
+        let images = serde_json::to_vec_pretty(images).map_err(|source| {
+            ImageError::Json { context: IMAGES_FILE.into(), source }
+        })?;
+        fs::create_dir_all(&self.root)
+            .and_then(|_| fs::write(&partial, images))
+            .and_then(|_| fs::rename(&partial, &path))
+            .map_err(io_error(format!("write {}", path.display())))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, usize>> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether an image for `descriptor` runs on this node. Descriptors without
+/// a platform are taken to match.
+fn matches_platform(descriptor: &Descriptor) -> bool {
+    let platform = match &descriptor.platform {
+        Some(platform) => platform,
+        None => return true,
+    };
+    let architecture = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    };
+    platform.os == std::env::consts::OS && platform.architecture == architecture
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(
+    reader: &mut impl Read,
+    context: &str,
+) -> Result<T, ImageError> {
+    let mut buf = Vec::new();
+    let _ = reader
+        .read_to_end(&mut buf)
+        .map_err(io_error(format!("read {}", context)))?;
+    parse_json(&buf, context)
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(
+    buf: &[u8],
+    context: &str,
+) -> Result<T, ImageError> {
+    serde_json::from_slice(buf).map_err(|source| ImageError::Json {
+        context: context.to_string(),
+        source,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::image::unpack::tests::layer;
+    use serde_json::json;
+
+    fn digest(blob: &[u8]) -> String {
+        format!("sha256:{}", hex::encode(Sha256::digest(blob)))
+    }
+
+    /// An image layout archive with a single image of one layer.
+    pub(crate) fn archive(layer: &[u8], tamper: bool) -> Vec<u8> {
+        let config = json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {"Cmd": ["/bin/sh"], "WorkingDir": "/srv"}
+        })
+        .to_string();
+        let manifest = json!({
+            "schemaVersion": 2,
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": digest(config.as_bytes()),
+                "size": config.len()
+            },
+            "layers": [{
+                "mediaType": "application/vnd.oci.image.layer.v1.tar",
+                "digest": digest(layer),
+                "size": layer.len()
+            }]
+        })
+        .to_string();
+        let index = json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": digest(manifest.as_bytes()),
+                "size": manifest.len(),
+                "annotations": {"org.opencontainers.image.ref.name": "app:1"}
+            }]
+        })
+        .to_string();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |path: String, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).expect("append");
+        };
+        append("oci-layout".into(), br#"{"imageLayoutVersion":"1.0.0"}"#);
+        append("index.json".into(), index.as_bytes());
+        for blob in [config.as_bytes(), manifest.as_bytes(), layer] {
+            let path = digest(blob).replace(':', "/");
+            let blob = match tamper && blob == layer {
+                true => b"tampered".as_slice(),
+                false => blob,
+            };
+            append(format!("blobs/{}", path), blob);
+        }
+        builder.into_inner().expect("archive")
+    }
+
+    #[test]
+    fn test_import_unpack_and_remove() {
+        let dir = std::env::temp_dir()
+            .join(format!("auraed-images-{}", std::process::id()));
+        let store = ImageStore::new(dir.join("store"));
+        fs::create_dir_all(&dir).expect("dir");
+        let layer = layer(&[("srv/", None), ("srv/hello", Some("hi"))]);
+
+        fs::write(dir.join("tampered.tar"), archive(&layer, true))
+            .expect("write");
+        assert!(matches!(
+            store.import(&dir.join("tampered.tar")),
+            Err(ImageError::DigestMismatch { .. })
+        ));
+        // The blobs stored before the tampered one are removed again.
+        let blobs = dir.join("store/blobs").join(SHA256);
+        assert_eq!(fs::read_dir(&blobs).expect("blobs").count(), 0);
+
+        fs::write(dir.join("image.tar"), archive(&layer, false))
+            .expect("write");
+        let imported = store.import(&dir.join("image.tar")).expect("import");
+        assert_eq!(imported.len(), 1);
+        assert_eq!(store.list().expect("list"), imported);
+
+        let image = store.get("app:1").expect("get");
+        let config = store.config(&image).expect("config");
+        let config = config.config.expect("container config");
+        assert_eq!(config.working_dir, "/srv");
+        let (rootfs, _) = store.use_rootfs(&image).expect("rootfs");
+        assert_eq!(
+            fs::read_to_string(rootfs.join("srv/hello")).expect("hello"),
+            "hi"
+        );
+
+        let layer = store.container_layer("box").expect("layer");
+        fs::write(layer.join("upper/changed"), "").expect("change");
+        let layer = store.container_layer("box").expect("layer");
+        assert!(layer.join("rootfs").is_dir());
+        assert_eq!(
+            fs::read_dir(layer.join("upper")).expect("upper").count(),
+            0
+        );
+
+        let (_, used) = store.use_rootfs(&image).expect("use rootfs");
+        assert!(matches!(
+            store.remove(&image.digest),
+            Err(ImageError::InUse { .. })
+        ));
+        drop(used);
+        let removed = store.remove(&image.digest).expect("remove");
+        assert_eq!(removed, image);
+        assert!(!rootfs.exists());
+        assert!(store.list().expect("list").is_empty());
+        assert!(matches!(store.get("app:1"), Err(ImageError::NotFound { .. })));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
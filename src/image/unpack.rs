@@ -0,0 +1,254 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::image::store::{io_error, ImageError};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Prefix of the entries of a layer that delete a path of the layers below.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Entry of a layer that hides everything the layers below put into its
+/// directory.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Applies the layer in `reader`, an uncompressed tar archive, on top of the
+/// layers already unpacked into `rootfs`.
+pub(crate) fn unpack_layer(
+    reader: impl Read,
+    rootfs: &Path,
+) -> Result<(), ImageError> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(true);
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(false);
+    archive.set_overwrite(true);
+
+    // Paths written by this layer, which an opaque whiteout in the same
+    // layer does not hide.
+    let mut written = HashSet::new();
+    let entries = archive.entries().map_err(io_error("read layer"))?;
+    for entry in entries {
+        let mut entry = entry.map_err(io_error("read layer"))?;
+        let path = entry.path().map_err(io_error("read layer"))?;
+        let path = match relative(&path) {
+            Some(path) => path,
+            None => {
+                return Err(ImageError::InvalidLayout(format!(
+                    "layer entry {} leaves the root filesystem",
+                    path.display()
+                )))
+            }
+        };
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+
+        if name == OPAQUE_WHITEOUT {
+            let dir = rootfs.join(parent);
+            if !contained(rootfs, &dir) {
+                continue;
+            }
+            let children = match fs::read_dir(&dir) {
+                Ok(children) => children,
+                Err(_) => continue,
+            };
+            for child in children.flatten() {
+                if !written.contains(&parent.join(child.file_name())) {
+                    remove(&child.path())?;
+                }
+            }
+            continue;
+        }
+        if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            if matches!(hidden, "" | "." | "..") || hidden.contains('/') {
+                return Err(ImageError::InvalidLayout(format!(
+                    "layer entry {} is not a valid whiteout",
+                    path.display()
+                )));
+            }
+            let target = rootfs.join(parent).join(hidden);
+            if contained(rootfs, &target) {
+                remove(&target)?;
+            }
+            continue;
+        }
+
+        // A lower layer may have put a different kind of file at the path.
+        let target = rootfs.join(&path);
+        if let Ok(existing) = fs::symlink_metadata(&target) {
+            if !(existing.is_dir() && entry.header().entry_type().is_dir())
+                && contained(rootfs, &target)
+            {
+                remove(&target)?;
+            }
+        }
+        let _ = entry
+            .unpack_in(rootfs)
+            .map_err(io_error(format!("unpack {}", path.display())))?;
+        let _ = written.insert(path);
+    }
+    Ok(())
+}
+
+/// The normalized relative path of a layer entry, or None if it points
+/// outside of the root filesystem.
+fn relative(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// Whether `path` resolves to a path strictly inside `rootfs`, with a parent
+/// that does, so that removing `path` can not follow a symlink out of it.
+fn contained(rootfs: &Path, path: &Path) -> bool {
+    let (parent, name) = match (path.parent(), path.components().next_back()) {
+        (Some(parent), Some(Component::Normal(name))) => (parent, name),
+        _ => return false,
+    };
+    match (rootfs.canonicalize(), parent.canonicalize()) {
+        (Ok(rootfs), Ok(parent)) => {
+            let target = parent.join(name);
+            target.starts_with(&rootfs) && target != rootfs
+        }
+        _ => false,
+    }
+}
+
+/// Removes whatever is at `path`, without following symlinks.
+fn remove(path: &Path) -> Result<(), ImageError> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    result.map_err(io_error(format!("remove {}", path.display())))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn layer(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            match content {
+                Some(content) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(content.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_cksum();
+                    builder
+                        .append_data(&mut header, path, content.as_bytes())
+                        .expect("append file");
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    header.set_mode(0o755);
+                    header.set_cksum();
+                    builder
+                        .append_data(&mut header, path, io::empty())
+                        .expect("append dir");
+                }
+            }
+        }
+        builder.into_inner().expect("layer")
+    }
+
+    #[test]
+    fn test_unpack_layers_with_whiteouts() {
+        let rootfs = std::env::temp_dir()
+            .join(format!("auraed-unpack-{}", std::process::id()));
+        fs::create_dir_all(&rootfs).expect("rootfs");
+
+        let lower = layer(&[
+            ("etc/", None),
+            ("etc/keep", Some("lower")),
+            ("etc/gone", Some("lower")),
+            ("var/", None),
+            ("var/cache/", None),
+            ("var/cache/old", Some("lower")),
+        ]);
+        let upper = layer(&[
+            ("etc/.wh.gone", Some("")),
+            ("var/cache/new", Some("upper")),
+            ("var/cache/.wh..wh..opq", Some("")),
+            ("etc/keep", Some("upper")),
+        ]);
+        unpack_layer(&lower[..], &rootfs).expect("lower");
+        unpack_layer(&upper[..], &rootfs).expect("upper");
+
+        let read = |path: &str| fs::read_to_string(rootfs.join(path)).ok();
+        assert_eq!(read("etc/keep").as_deref(), Some("upper"));
+        assert_eq!(read("etc/gone"), None);
+        assert_eq!(read("var/cache/old"), None);
+        assert_eq!(read("var/cache/new").as_deref(), Some("upper"));
+        assert!(!rootfs.join("etc/.wh.gone").exists());
+        let _ = fs::remove_dir_all(&rootfs);
+    }
+
+    #[test]
+    fn test_whiteouts_stay_in_rootfs() {
+        let dir = std::env::temp_dir()
+            .join(format!("auraed-whiteout-{}", std::process::id()));
+        let rootfs = dir.join("rootfs");
+        fs::create_dir_all(rootfs.join("a")).expect("rootfs");
+        fs::write(dir.join("outside"), "kept").expect("outside");
+        fs::write(rootfs.join("a/inside"), "kept").expect("inside");
+
+        for whiteout in [".wh...", "a/.wh...", "a/.wh..", "a/.wh."] {
+            let upper = layer(&[(whiteout, Some(""))]);
+            assert!(matches!(
+                unpack_layer(&upper[..], &rootfs),
+                Err(ImageError::InvalidLayout(_))
+            ));
+        }
+        assert!(dir.join("outside").exists());
+        assert!(rootfs.join("a/inside").exists());
+        assert!(!contained(&rootfs, &rootfs.join("a/..")));
+        assert!(!contained(&rootfs, &rootfs.join(".")));
+        assert!(contained(&rootfs, &rootfs.join("a/inside")));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
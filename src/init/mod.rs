@@ -38,7 +38,7 @@ use log::Level;
 mod fileio;
 mod fs;
 mod logging;
-mod network;
+pub(crate) mod network;
 mod power;
 mod system_runtime;
 
@@ -60,6 +60,26 @@ pub(crate) enum InitError {
     Fs(#[from] FsError),
 }
 
+/// What happens to the machine once auraed stops as PID 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    PowerOff,
+    Reboot,
+}
+
+/// Powers the machine off or reboots it if auraed runs as PID 1, as the
+/// kernel panics once PID 1 exits. Returns otherwise, or if the machine could
+/// not be powered off.
+pub fn power(action: PowerAction) {
+    if std::process::id() != 1 {
+        return;
+    }
+    match action {
+        PowerAction::PowerOff => power::power_off(),
+        PowerAction::Reboot => power::reboot(),
+    }
+}
+
 pub async fn init(logger_level: Level) {
     let res = match std::process::id() {
         0 => unreachable!(
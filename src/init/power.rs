@@ -34,9 +34,10 @@ use std::{fs::OpenOptions, io::Read, mem, path::Path, slice};
 
 extern crate libc;
 
-#[allow(dead_code)]
 pub(crate) fn syscall_reboot(action: i32) {
     unsafe {
+        // reboot does not write back the caches of filesystems.
+        libc::sync();
         libc::reboot(action);
     }
 }
@@ -45,7 +46,6 @@ pub(crate) fn power_off() {
     syscall_reboot(libc::LINUX_REBOOT_CMD_POWER_OFF);
 }
 
-#[allow(dead_code)]
 pub(crate) fn reboot() {
     syscall_reboot(libc::LINUX_REBOOT_CMD_RESTART);
 }
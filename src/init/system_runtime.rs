@@ -171,6 +171,7 @@ impl SystemRuntime for Pid1SystemRuntime {
         trace!("Configure filesystem");
         fs::mount_vfs("none", "/dev", "devtmpfs")?;
         fs::mount_vfs("none", "/sys", "sysfs")?;
+        fs::mount_vfs("cgroup2", "/sys/fs/cgroup", "cgroup2")?;
         fs::mount_vfs("proc", "/proc", "proc")?;
 
         trace!("configure network");
@@ -33,27 +33,32 @@
 use anyhow::anyhow;
 use anyhow::Context;
 use log::*;
-use sea_orm::ConnectOptions;
-use sea_orm::ConnectionTrait;
-use sea_orm::Database;
-use sea_orm::Statement;
-use std::borrow::Cow;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
 use tokio::net::UnixListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
 use tokio_stream::wrappers::UnixListenerStream;
 use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 
+use crate::image::image_server::ImageServer;
+use crate::image::{ImageService, ImageStore};
+use crate::init::PowerAction;
+use crate::observe::events::Events;
 use crate::observe::observe_server::ObserveServer;
 use crate::observe::ObserveService;
 use crate::runtime::runtime_server::RuntimeServer;
-use crate::runtime::RuntimeService;
+use crate::runtime::{LogStore, RuntimeService};
 use crate::schedule::schedule_executable_server::ScheduleExecutableServer;
-use crate::schedule::ScheduleExecutableService;
+use crate::schedule::schedule_server::ScheduleServer;
+use crate::schedule::{
+    ScheduleExecutableService, ScheduleService, ScheduleStore, Scheduler,
+};
 
+mod image;
 pub mod init;
 mod meta;
 mod observe;
@@ -61,6 +66,8 @@ mod runtime;
 mod schedule;
 
 pub const AURAE_SOCK: &str = "/var/run/aurae/aurae.sock";
+pub const AURAE_DATA_DIR: &str = "/var/lib/aurae";
+pub const AURAE_MANIFESTS_DIR: &str = "/etc/aurae/manifests";
 
 #[derive(Debug)]
 pub struct AuraedRuntime {
@@ -70,10 +77,18 @@ pub struct AuraedRuntime {
     pub server_crt: PathBuf,
     pub server_key: PathBuf,
     pub socket: PathBuf,
+
+    // Images and other state of auraed are kept below the data dir.
+    pub data_dir: PathBuf,
+
+    // Manifests of the executables to schedule, kept in sync while running.
+    pub manifests_dir: PathBuf,
 }
 
 impl AuraedRuntime {
-    pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Serves until auraed is asked to shut down, and returns what to do
+    /// with the machine then.
+    pub async fn run(&self) -> Result<PowerAction, Box<dyn std::error::Error>> {
         // Manage the socket permission/groups first\
         let _ = fs::remove_file(&self.socket);
         let sock_path = Path::new(&self.socket)
@@ -87,6 +102,8 @@ impl AuraedRuntime {
         })?;
         trace!("{:#?}", self);
 
+        runtime::init();
+
         let server_crt =
             tokio::fs::read(&self.server_crt).await.with_context(|| {
                 format!(
@@ -95,7 +112,6 @@ impl AuraedRuntime {
                 )
             })?;
         let server_key = tokio::fs::read(&self.server_key).await?;
-        let db_key = server_key.clone();
         let server_identity = Identity::from_pem(server_crt, server_key);
         info!("Register Server SSL Identity");
 
@@ -111,74 +127,202 @@ impl AuraedRuntime {
         let sock = UnixListener::bind(&self.socket)?;
         let sock_stream = UnixListenerStream::new(sock);
 
+        let images = ImageStore::new(self.data_dir.join("images"));
+
+        // SQLite
+        tokio::fs::create_dir_all(&self.data_dir).await.with_context(|| {
+            format!(
+                "Failed to create data directory: {}",
+                self.data_dir.display()
+            )
+        })?;
+        let db_path = self.data_dir.join("aurae.db");
+        info!("Database Location: {}", db_path.display());
+        let db = schedule::connect(&db_path).await?;
+        let store = ScheduleStore::new(db);
+        let logs = LogStore::new(self.data_dir.join("logs"));
+        let events = Events::open(store.clone()).await?;
+        let scheduler = Scheduler::new(store, logs, events.clone());
+        info!("Manifests Location: {}", self.manifests_dir.display());
+        let _ = schedule::reconcile(&scheduler, &self.manifests_dir).await?;
+        scheduler.hydrate().await?;
+        schedule::watch_manifests(
+            scheduler.clone(),
+            self.manifests_dir.clone(),
+        );
+
         // Run the server concurrently
+        let (stopping, stopped) = oneshot::channel();
+        let services = scheduler.clone();
         let handle = tokio::spawn(async {
             Server::builder()
                 .tls_config(tls)?
-                .add_service(RuntimeServer::new(RuntimeService::default()))
-                .add_service(ObserveServer::new(ObserveService::default()))
+                .add_service(RuntimeServer::new(RuntimeService::new(
+                    images.clone(),
+                    events,
+                )))
+                .add_service(ImageServer::new(ImageService::new(
+                    images.clone(),
+                )))
+                .add_service(ObserveServer::new(ObserveService::new(
+                    services.clone(),
+                    images,
+                )))
+                .add_service(ScheduleServer::new(ScheduleService::new(
+                    services.clone(),
+                )))
                 .add_service(ScheduleExecutableServer::new(
-                    ScheduleExecutableService::default(),
+                    ScheduleExecutableService::new(services),
                 ))
-                .serve_with_incoming(sock_stream)
+                .serve_with_incoming_shutdown(sock_stream, async {
+                    let _ = stopping.send(shutdown_signal().await);
+                })
                 .await
         });
 
-        trace!("Setting socket mode {} -> 766", &self.socket.display());
+        let served: Result<PowerAction, Box<dyn std::error::Error>> = async {
+            trace!("Setting socket mode {} -> 766", &self.socket.display());
 
-        // We set the mode to 766 for the Unix domain socket.
-        // This is what allows non-root users to dial the socket
-        // and authenticate with mTLS.
-        fs::set_permissions(&self.socket, fs::Permissions::from_mode(0o766))?;
-        info!("User Access Socket Created: {}", self.socket.display());
+            // We set the mode to 766 for the Unix domain socket.
+            // This is what allows non-root users to dial the socket
+            // and authenticate with mTLS.
+            fs::set_permissions(
+                &self.socket,
+                fs::Permissions::from_mode(0o766),
+            )?;
+            info!("User Access Socket Created: {}", self.socket.display());
 
-        // SQLite
-        info!("Database Location:  /var/lib/aurae.db");
-        info!("Unlocking SQLite Database with Key: {:?}", self.server_key);
-        let mut opt =
-            ConnectOptions::new("sqlite:/var/lib/aurae.db".to_owned());
-        opt.sqlx_logging(false).sqlcipher_key(Cow::from(format!(
-            "{:?}",
-            db_key.to_ascii_lowercase()
-        )));
-
-        // Pragma initial connection
-        let mut opt = ConnectOptions::new("sqlite::memory:".to_owned());
-        opt.sqlx_logging(false); // TODO add sqlcipher_key
-        let db = Database::connect(opt).await?;
-        let x = db
-            .execute(Statement::from_string(
-                db.get_database_backend(),
-                "PRAGMA database_list;".to_string(),
-            ))
-            .await?;
-        info!("Initializing: SQLite: {:?}", x);
-
-        //runtime::hydrate(&db).await?;
-
-        // Event loop
-        handle.await??;
-        info!("gRPC server exited successfully");
-
-        Ok(())
+            // Event loop
+            handle.await??;
+            info!("gRPC server exited successfully");
+            Ok(stopped.await.unwrap_or(PowerAction::PowerOff))
+        }
+        .await;
+
+        // Dependents stop before what they depend on, however the server
+        // exited.
+        let shutdown = scheduler.shutdown().await;
+        let action = served?;
+        shutdown?;
+        Ok(action)
     }
 }
 
+/// Resolves once auraed is asked to shut down with SIGTERM or SIGINT, with
+/// what to do with the machine if auraed runs as PID 1. SIGTERM powers it
+/// off. SIGINT, which the kernel sends PID 1 for Ctrl-Alt-Del once that is
+/// disabled, reboots it.
+async fn shutdown_signal() -> PowerAction {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                let _ = terminate.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    let action = tokio::select! {
+        _ = terminate => PowerAction::PowerOff,
+        _ = tokio::signal::ctrl_c() => PowerAction::Reboot,
+    };
+    info!("Shutting down");
+    action
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CommandParseError {
+    #[error("empty base command string")]
+    Empty,
+    #[error("unterminated {0} quote in command string")]
+    UnterminatedQuote(char),
+    #[error("trailing backslash in command string")]
+    TrailingBackslash,
+}
+
 pub fn command_from_string(cmd: &str) -> Result<Command, anyhow::Error> {
-    let mut entries = cmd.split(' ');
+    let mut entries = split_command(cmd)?.into_iter();
     let base = match entries.next() {
         Some(base) => base,
         None => {
-            return Err(anyhow!("empty base command string"));
+            return Err(anyhow!(CommandParseError::Empty));
         }
     };
     let mut command = Command::new(base);
-    for ent in entries {
-        if ent != base {
-            command.arg(ent);
+    let _ = command.args(entries);
+    Ok(command)
+}
+
+/// Splits a command string into its arguments the way a shell would, without
+/// any expansion. Whitespace separates arguments, single quotes preserve
+/// everything up to the closing quote, and a backslash escapes the next
+/// character. Inside double quotes a backslash only escapes `"`, `\`, `$`
+/// and `` ` ``.
+pub fn split_command(cmd: &str) -> Result<Vec<String>, CommandParseError> {
+    let mut args = Vec::new();
+    // An argument is started by any character, including an empty quote.
+    let mut arg: Option<String> = None;
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => {
+                            return Err(CommandParseError::UnterminatedQuote(
+                                '\'',
+                            ))
+                        }
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => {
+                                return Err(
+                                    CommandParseError::UnterminatedQuote('"'),
+                                )
+                            }
+                        },
+                        Some(c) => arg.push(c),
+                        None => {
+                            return Err(CommandParseError::UnterminatedQuote(
+                                '"',
+                            ))
+                        }
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+                None => return Err(CommandParseError::TrailingBackslash),
+            },
+            c => arg.get_or_insert_with(String::new).push(c),
         }
     }
-    Ok(command)
+    if let Some(arg) = arg {
+        args.push(arg);
+    }
+    Ok(args)
 }
 
 #[cfg(test)]
@@ -189,4 +333,48 @@ mod tests {
     fn test_socket_path() {
         assert_eq!(AURAE_SOCK, "/var/run/aurae/aurae.sock");
     }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(
+            split_command(r#"sh -c "echo hi""#),
+            Ok(vec!["sh".into(), "-c".into(), "echo hi".into()])
+        );
+        assert_eq!(
+            split_command("  echo   echo\techo  "),
+            Ok(vec!["echo".into(), "echo".into(), "echo".into()])
+        );
+        assert_eq!(
+            split_command(r#"ls '/tmp/with space' "" a\ b 'it'\''s'"#),
+            Ok(vec![
+                "ls".into(),
+                "/tmp/with space".into(),
+                "".into(),
+                "a b".into(),
+                "it's".into()
+            ])
+        );
+        assert_eq!(
+            split_command(r#"printf "\"\n\$""#),
+            Ok(vec!["printf".into(), "\"\\n$".into()])
+        );
+        assert_eq!(split_command(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_split_command_errors() {
+        assert_eq!(
+            split_command("echo 'hi"),
+            Err(CommandParseError::UnterminatedQuote('\''))
+        );
+        assert_eq!(
+            split_command(r#"echo "hi"#),
+            Err(CommandParseError::UnterminatedQuote('"'))
+        );
+        assert_eq!(
+            split_command("echo hi\\"),
+            Err(CommandParseError::TrailingBackslash)
+        );
+        assert!(command_from_string("   ").is_err());
+    }
 }
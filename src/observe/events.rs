@@ -0,0 +1,257 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+//! Lifecycle events of executables, published by the runtime and schedule
+//! subsystems and streamed by Observe.WatchEvents.
+
+use crate::meta;
+use crate::observe::{event, Event, OomKilled, ProcessExited, ProcessStarted};
+use crate::runtime::{unix_timestamp, ExecutableExit, ExecutableStatus};
+use crate::schedule::{ScheduleError, ScheduleStore};
+use log::warn;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::{broadcast, watch};
+
+/// Number of past events kept for clients that resume a stream.
+const HISTORY: usize = 4096;
+
+/// Events buffered for a stream before a slow client misses events.
+const WATCH_BUFFER: usize = 1024;
+
+/// Sequence numbers reserved in the store at a time. Numbers are reserved
+/// before they are handed out, so that they keep increasing across restarts
+/// of auraed, even when it did not stop cleanly.
+const RESERVED_SEQUENCES: u64 = 4096;
+
+/// Events numbers the events it is handed, keeps the most recent ones and
+/// passes them on to the watchers. Clones share the same events.
+#[derive(Debug, Clone)]
+pub(crate) struct Events {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    history: Mutex<History>,
+    watchers: broadcast::Sender<Event>,
+    /// Reservations is handed the sequence numbers reserved up to, to
+    /// record them in the store. None when the sequence is not kept.
+    reservations: Option<watch::Sender<u64>>,
+}
+
+#[derive(Debug)]
+struct History {
+    next: u64,
+    /// Reserved is the first sequence number not reserved yet.
+    reserved: u64,
+    events: VecDeque<Event>,
+}
+
+impl Default for Events {
+    /// Events that number from 1 on every time, without a store to keep the
+    /// sequence in.
+    fn default() -> Self {
+        Self::new(1, None)
+    }
+}
+
+impl Events {
+    /// Events that continue the sequence kept in `store`.
+    pub async fn open(store: ScheduleStore) -> Result<Self, ScheduleError> {
+        let next = store.reserved_events().await?.max(1);
+        let reserved = next + RESERVED_SEQUENCES;
+        store.reserve_events(reserved).await?;
+
+        let (tx, mut rx) = watch::channel(reserved);
+        drop(tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                let reserved = *rx.borrow_and_update();
+                if let Err(e) = store.reserve_events(reserved).await {
+                    warn!("Failed to reserve event sequence numbers: {}", e);
+                }
+            }
+        }));
+        let events = Self::new(next, Some(tx));
+        events.lock().reserved = reserved;
+        Ok(events)
+    }
+
+    fn new(next: u64, reservations: Option<watch::Sender<u64>>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                history: Mutex::new(History {
+                    next,
+                    reserved: u64::MAX,
+                    events: VecDeque::with_capacity(HISTORY),
+                }),
+                watchers: broadcast::channel(WATCH_BUFFER).0,
+                reservations,
+            }),
+        }
+    }
+
+    /// Publishes `event` of the executable `name`.
+    pub fn publish(&self, name: &str, event: event::Event) {
+        let mut history = self.lock();
+        let event = Event {
+            sequence: history.next,
+            timestamp: unix_timestamp(),
+            name: name.to_string(),
+            event: Some(event),
+        };
+        history.next += 1;
+        // More is reserved while half of the reservation is left, which is
+        // plenty of time for the store to record it.
+        if history.next + RESERVED_SEQUENCES / 2 >= history.reserved {
+            history.reserved += RESERVED_SEQUENCES;
+            if let Some(reservations) = &self.shared.reservations {
+                let _ = reservations.send_replace(history.reserved);
+            }
+        }
+        if history.events.len() == HISTORY {
+            let _ = history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+        // Sent under the lock, so that watchers see events in order.
+        let _ = self.shared.watchers.send(event);
+    }
+
+    pub fn publish_start(&self, name: &str, proc: meta::ProcessMeta) {
+        let started = ProcessStarted { proc: Some(proc) };
+        self.publish(name, event::Event::Started(started));
+    }
+
+    /// Publishes the exit of a process of the executable `name`, and the OOM
+    /// kills that came with it.
+    pub fn publish_exit(
+        &self,
+        name: &str,
+        exited: ProcessExited,
+        oom_kills: u64,
+    ) {
+        let proc = exited.proc.clone();
+        self.publish(name, event::Event::Exited(exited));
+        if oom_kills > 0 {
+            let kills = OomKilled { proc, kills: oom_kills };
+            self.publish(name, event::Event::OomKilled(kills));
+        }
+    }
+
+    /// Watches the events that follow the event `after`, the ones still kept
+    /// first. Without `after` only new events are watched.
+    pub fn watch(
+        &self,
+        after: Option<u64>,
+    ) -> (Vec<Event>, broadcast::Receiver<Event>) {
+        let history = self.lock();
+        // Subscribed under the lock, so that no event falls in between.
+        let watcher = self.shared.watchers.subscribe();
+        let past = match after {
+            Some(after) => history
+                .events
+                .iter()
+                .filter(|e| e.sequence > after)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (past, watcher)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, History> {
+        self.shared.history.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl From<&ExecutableStatus> for ProcessExited {
+    fn from(status: &ExecutableStatus) -> Self {
+        ProcessExited {
+            proc: status.proc.clone(),
+            status: status.status,
+            exit_code: status.exit_code,
+            signal: status.signal,
+            core_dumped: status.core_dumped,
+        }
+    }
+}
+
+impl From<&ExecutableExit> for ProcessExited {
+    fn from(exit: &ExecutableExit) -> Self {
+        ProcessExited {
+            proc: exit.proc.clone(),
+            status: exit.status,
+            exit_code: exit.exit_code,
+            signal: exit.signal,
+            core_dumped: exit.core_dumped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observe::ExecutableEnabled;
+    use crate::schedule::store;
+
+    #[test]
+    fn test_watch_resumes() {
+        let events = Events::default();
+        let (_, mut watcher) = events.watch(None);
+        events.publish("first", event::Event::Enabled(ExecutableEnabled {}));
+        let first = watcher.try_recv().expect("first");
+        events.publish("second", event::Event::Enabled(ExecutableEnabled {}));
+
+        let (past, _) = events.watch(Some(first.sequence));
+        assert_eq!(past.len(), 1);
+        assert_eq!(past[0].name, "second");
+        assert_eq!(past[0].sequence, first.sequence + 1);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_continues_after_restart() {
+        let store = store().await;
+        let events = Events::open(store.clone()).await.expect("open");
+        for _ in 0..RESERVED_SEQUENCES {
+            events.publish("web", event::Event::Enabled(ExecutableEnabled {}));
+        }
+        let (_, mut watcher) = events.watch(None);
+        events.publish("web", event::Event::Enabled(ExecutableEnabled {}));
+        let last = watcher.try_recv().expect("last").sequence;
+        // The reservation is recorded in the background.
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        // Whether or not auraed stopped cleanly, numbers are not reused.
+        let restarted = Events::open(store).await.expect("reopen");
+        let (_, mut watcher) = restarted.watch(None);
+        restarted.publish("web", event::Event::Enabled(ExecutableEnabled {}));
+        assert!(watcher.try_recv().expect("first").sequence > last);
+    }
+}
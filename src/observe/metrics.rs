@@ -0,0 +1,427 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+//! Resource metrics of the host, read from /proc and /sys.
+
+use crate::init::network::get_links;
+use crate::observe::{
+    CpuUsage, FilesystemUsage, HostMetrics, LoadAverage, MemoryUsage,
+    NetworkCounters, Pressure, PressureStall,
+};
+use crate::runtime::unix_timestamp;
+use log::warn;
+use std::ffi::CString;
+use std::io;
+use std::path::{Path, PathBuf};
+use tonic::Status;
+
+const NET_ROOT: &str = "/sys/class/net";
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum MetricsError {
+    #[error("failed to read {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to parse {path}")]
+    Invalid { path: PathBuf },
+    #[error("sampling failed: {0}")]
+    Sampling(#[from] tokio::task::JoinError),
+}
+
+impl From<MetricsError> for Status {
+    fn from(e: MetricsError) -> Self {
+        Status::internal(e.to_string())
+    }
+}
+
+/// Takes the metrics of the host. CPU usage is measured between two samples,
+/// so the sampler keeps the CPU times of the last one.
+pub(crate) struct Sampler {
+    cpus: Vec<CpuTimes>,
+}
+
+impl Sampler {
+    pub fn new() -> Result<Self, MetricsError> {
+        Ok(Self { cpus: cpu_times()? })
+    }
+
+    /// Samples the host, with the CPU usage since the last sample.
+    pub async fn sample(&mut self) -> Result<HostMetrics, MetricsError> {
+        let interfaces = interfaces().await;
+        let previous = std::mem::take(&mut self.cpus);
+        let (metrics, cpus) = tokio::task::spawn_blocking(move || {
+            let cpus = cpu_times()?;
+            let metrics = HostMetrics {
+                timestamp: unix_timestamp(),
+                cpus: cpu_usage(&previous, &cpus),
+                memory: Some(memory()?),
+                load: Some(load()?),
+                filesystems: filesystems(),
+                networks: networks(&interfaces),
+                cpu_pressure: pressure("cpu"),
+                memory_pressure: pressure("memory"),
+                io_pressure: pressure("io"),
+            };
+            Ok::<_, MetricsError>((metrics, cpus))
+        })
+        .await??;
+        self.cpus = cpus;
+        Ok(metrics)
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> Result<String, MetricsError> {
+    let path = path.as_ref();
+    std::fs::read_to_string(path)
+        .map_err(|source| MetricsError::Read { path: path.into(), source })
+}
+
+fn invalid(path: &str) -> MetricsError {
+    MetricsError::Invalid { path: PathBuf::from(path) }
+}
+
+/// The time a CPU spent in each state since boot, in clock ticks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct CpuTimes {
+    name: String,
+    /// user, nice, system, idle, iowait, irq, softirq and steal.
+    ticks: [u64; 8],
+}
+
+fn cpu_times() -> Result<Vec<CpuTimes>, MetricsError> {
+    parse_cpu_times(&read("/proc/stat")?).ok_or_else(|| invalid("/proc/stat"))
+}
+
+fn parse_cpu_times(stat: &str) -> Option<Vec<CpuTimes>> {
+    let mut cpus = Vec::new();
+    for line in stat.lines().filter(|line| line.starts_with("cpu")) {
+        let mut fields = line.split_whitespace();
+        let name = fields.next()?.to_string();
+        let mut ticks = [0; 8];
+        // Older kernels have fewer columns, which stay zero.
+        for (tick, field) in ticks.iter_mut().zip(fields) {
+            *tick = field.parse().ok()?;
+        }
+        cpus.push(CpuTimes { name, ticks });
+    }
+    (!cpus.is_empty()).then_some(cpus)
+}
+
+/// The usage of every CPU between two samples of its times. CPUs that came
+/// online in between are measured since boot.
+fn cpu_usage(previous: &[CpuTimes], current: &[CpuTimes]) -> Vec<CpuUsage> {
+    current
+        .iter()
+        .map(|cpu| {
+            let before = previous.iter().find(|p| p.name == cpu.name);
+            let mut delta = [0.0; 8];
+            for (i, d) in delta.iter_mut().enumerate() {
+                let before = before.map(|b| b.ticks[i]).unwrap_or_default();
+                *d = cpu.ticks[i].saturating_sub(before) as f64;
+            }
+            let total: f64 = delta.iter().sum();
+            let share = |ticks: f64| {
+                if total > 0.0 {
+                    ticks * 100.0 / total
+                } else {
+                    0.0
+                }
+            };
+            CpuUsage {
+                name: cpu.name.clone(),
+                user: share(delta[0]),
+                nice: share(delta[1]),
+                system: share(delta[2]),
+                idle: share(delta[3]),
+                iowait: share(delta[4]),
+                irq: share(delta[5]),
+                softirq: share(delta[6]),
+                steal: share(delta[7]),
+                busy: share(total - delta[3] - delta[4]),
+            }
+        })
+        .collect()
+}
+
+fn memory() -> Result<MemoryUsage, MetricsError> {
+    Ok(parse_meminfo(&read("/proc/meminfo")?))
+}
+
+fn parse_meminfo(meminfo: &str) -> MemoryUsage {
+    let mut memory = MemoryUsage::default();
+    for line in meminfo.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some(field) => field,
+            None => continue,
+        };
+        // Values are in kibibytes.
+        let bytes = value
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse::<u64>()
+            .unwrap_or_default()
+            * 1024;
+        match key {
+            "MemTotal" => memory.total = bytes,
+            "MemFree" => memory.free = bytes,
+            "MemAvailable" => memory.available = bytes,
+            "Buffers" => memory.buffers = bytes,
+            "Cached" => memory.cached = bytes,
+            "SwapTotal" => memory.swap_total = bytes,
+            "SwapFree" => memory.swap_free = bytes,
+            _ => {}
+        }
+    }
+    memory
+}
+
+fn load() -> Result<LoadAverage, MetricsError> {
+    parse_loadavg(&read("/proc/loadavg")?)
+        .ok_or_else(|| invalid("/proc/loadavg"))
+}
+
+fn parse_loadavg(loadavg: &str) -> Option<LoadAverage> {
+    let mut fields = loadavg.split_whitespace();
+    let mut average = || fields.next()?.parse().ok();
+    let (one, five, fifteen) = (average()?, average()?, average()?);
+    let (running, total) = fields.next()?.split_once('/')?;
+    Some(LoadAverage {
+        one,
+        five,
+        fifteen,
+        running: running.parse().ok()?,
+        total: total.parse().ok()?,
+    })
+}
+
+/// The usage of the mounted filesystems that have blocks. Filesystems that
+/// can not be queried are left out.
+fn filesystems() -> Vec<FilesystemUsage> {
+    let mounts = match read("/proc/self/mounts") {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            warn!("{}", e);
+            return Vec::new();
+        }
+    };
+    parse_mounts(&mounts)
+        .into_iter()
+        .filter_map(|(device, mount_point, fs_type)| {
+            let usage = statvfs(&mount_point)?;
+            Some(FilesystemUsage { device, mount_point, fs_type, ..usage })
+        })
+        .filter(|fs| fs.total > 0)
+        .collect()
+}
+
+/// The device, mount point and type of every mount.
+fn parse_mounts(mounts: &str) -> Vec<(String, String, String)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().map(unescape);
+            Some((fields.next()?, fields.next()?, fields.next()?))
+        })
+        .collect()
+}
+
+/// Decodes the octal escapes of spaces and the like in a mounts field.
+fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+            u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok()
+        });
+        match octal {
+            Some(byte) if bytes[i] == b'\\' => {
+                out.push(byte);
+                i += 4;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn statvfs(mount_point: &str) -> Option<FilesystemUsage> {
+    let path = CString::new(mount_point).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some(FilesystemUsage {
+        total: stat.f_blocks as u64 * block,
+        free: stat.f_bfree as u64 * block,
+        available: stat.f_bavail as u64 * block,
+        inodes: stat.f_files as u64,
+        inodes_free: stat.f_ffree as u64,
+        ..Default::default()
+    })
+}
+
+/// The names of the network interfaces, as get_links finds them.
+async fn interfaces() -> Vec<String> {
+    let (connection, handle, _) = match rtnetlink::new_connection() {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("Failed to connect to netlink: {}", e);
+            return Vec::new();
+        }
+    };
+    tokio::spawn(connection);
+    match get_links(&handle).await {
+        Ok(links) => {
+            let mut names: Vec<String> = links.into_values().collect();
+            names.sort();
+            names
+        }
+        Err(e) => {
+            warn!("Failed to list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn networks(interfaces: &[String]) -> Vec<NetworkCounters> {
+    interfaces
+        .iter()
+        .map(|name| {
+            let statistics = Path::new(NET_ROOT).join(name).join("statistics");
+            let counter = |counter: &str| {
+                std::fs::read_to_string(statistics.join(counter))
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .unwrap_or_default()
+            };
+            NetworkCounters {
+                name: name.clone(),
+                rx_bytes: counter("rx_bytes"),
+                rx_packets: counter("rx_packets"),
+                rx_errors: counter("rx_errors"),
+                rx_dropped: counter("rx_dropped"),
+                tx_bytes: counter("tx_bytes"),
+                tx_packets: counter("tx_packets"),
+                tx_errors: counter("tx_errors"),
+                tx_dropped: counter("tx_dropped"),
+            }
+        })
+        .collect()
+}
+
+/// The pressure stall information of `resource`, if the kernel has it.
+fn pressure(resource: &str) -> Option<PressureStall> {
+    let path = Path::new("/proc/pressure").join(resource);
+    parse_pressure(&std::fs::read_to_string(path).ok()?)
+}
+
+fn parse_pressure(pressure: &str) -> Option<PressureStall> {
+    let mut stall = PressureStall::default();
+    for line in pressure.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next()?;
+        let mut parsed = Pressure::default();
+        for field in fields {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "avg10" => parsed.avg10 = value.parse().ok()?,
+                "avg60" => parsed.avg60 = value.parse().ok()?,
+                "avg300" => parsed.avg300 = value.parse().ok()?,
+                "total" => parsed.total_us = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        match kind {
+            "some" => stall.some = Some(parsed),
+            "full" => stall.full = Some(parsed),
+            _ => {}
+        }
+    }
+    Some(stall)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_usage() {
+        let before = parse_cpu_times(
+            "cpu  100 0 100 700 100 0 0 0 0 0\ncpu0 100 0 100 700 100 0 0 0\nintr 5\n",
+        )
+        .expect("parse");
+        let after = parse_cpu_times(
+            "cpu  150 0 150 800 100 0 0 0 0 0\ncpu0 150 0 150 800 100 0 0 0\ncpu1 1 0 0 3\n",
+        )
+        .expect("parse");
+        let usage = cpu_usage(&before, &after);
+        assert_eq!(usage.len(), 3);
+        assert_eq!(
+            (usage[0].user, usage[0].idle, usage[0].busy),
+            (25.0, 50.0, 50.0)
+        );
+        assert_eq!((usage[2].name.as_str(), usage[2].busy), ("cpu1", 25.0));
+    }
+
+    #[test]
+    fn test_parse_proc() {
+        let memory = parse_meminfo(
+            "MemTotal:       16 kB\nMemAvailable:    8 kB\nSwapFree:        0 kB\nHugePages_Total: 0\n",
+        );
+        assert_eq!((memory.total, memory.available), (16384, 8192));
+
+        let load = parse_loadavg("0.50 0.25 0.10 2/345 6789\n").expect("load");
+        assert_eq!((load.one, load.running, load.total), (0.5, 2, 345));
+
+        let mounts = parse_mounts("/dev/sda1 /mnt/my\\040disk ext4 rw 0 0\n");
+        assert_eq!(mounts[0].1, "/mnt/my disk");
+
+        let stall = parse_pressure(
+            "some avg10=1.50 avg60=0.00 avg300=0.00 total=1234\nfull avg10=0.00 avg60=0.00 avg300=0.00 total=12\n",
+        )
+        .expect("pressure");
+        assert_eq!(stall.some.expect("some").avg10, 1.5);
+        assert_eq!(stall.full.expect("full").total_us, 12);
+    }
+
+    #[tokio::test]
+    async fn test_sample() {
+        let mut sampler = Sampler::new().expect("sampler");
+        let metrics = sampler.sample().await.expect("sample");
+        assert_eq!(metrics.cpus[0].name, "cpu");
+        assert!(metrics.memory.expect("memory").total > 0);
+        assert!(metrics.filesystems.iter().all(|fs| fs.total > 0));
+    }
+}
@@ -30,24 +30,376 @@
 
 tonic::include_proto!("observe");
 
+use crate::image::ImageStore;
 use crate::meta;
+use crate::observe::metrics::Sampler;
 use crate::observe::observe_server::Observe;
+use crate::runtime::{
+    cgroups_available, unix_timestamp, LogError, LogQuery, LogRecord,
+};
+use crate::schedule::Scheduler;
+use std::io;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
-#[derive(Debug, Default, Clone)]
-pub struct ObserveService {}
+pub(crate) mod events;
+mod metrics;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Log entries buffered per stream before reading the logs blocks on a slow
+/// client.
+const LOG_STREAM_BUFFER: usize = 256;
+
+/// Events buffered per stream before a slow client holds up its stream.
+const EVENT_STREAM_BUFFER: usize = 256;
+
+const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_METRICS_INTERVAL: Duration = Duration::from_millis(100);
+/// The longest a single GetHostMetrics call measures for, as the call holds
+/// on to the request for as long.
+const MAX_METRICS_SAMPLE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct ObserveService {
+    scheduler: Scheduler,
+    images: ImageStore,
+}
+
+impl ObserveService {
+    pub(crate) fn new(scheduler: Scheduler, images: ImageStore) -> Self {
+        Self { scheduler, images }
+    }
+
+    async fn subsystems(&self) -> Vec<SubsystemHealth> {
+        vec![
+            runtime_health(),
+            self.schedule_health().await,
+            self.image_health(),
+        ]
+    }
+
+    async fn schedule_health(&self) -> SubsystemHealth {
+        let enabled = match self.scheduler.show(true, "").await {
+            Ok(enabled) => enabled,
+            Err(e) => {
+                return subsystem("schedule", Health::Unhealthy, e.to_string())
+            }
+        };
+        let failing: Vec<&str> = enabled
+            .iter()
+            .filter(|(_, status)| status.status() == meta::Status::Error)
+            .map(|(executable, _)| executable.name())
+            .collect();
+        if failing.is_empty() {
+            let message = format!("{} enabled", enabled.len());
+            subsystem("schedule", Health::Healthy, message)
+        } else {
+            let message = format!(
+                "{} of {} enabled failing: {}",
+                failing.len(),
+                enabled.len(),
+                failing.join(", ")
+            );
+            subsystem("schedule", Health::Degraded, message)
+        }
+    }
+
+    fn image_health(&self) -> SubsystemHealth {
+        match self.images.list() {
+            Ok(images) => {
+                let message = format!("{} images", images.len());
+                subsystem("image", Health::Healthy, message)
+            }
+            Err(e) => subsystem("image", Health::Unhealthy, e.to_string()),
+        }
+    }
+}
 
 #[tonic::async_trait]
 impl Observe for ObserveService {
+    type StreamHostMetricsStream = ReceiverStream<Result<HostMetrics, Status>>;
+    type GetLogsStream = ReceiverStream<Result<LogEntry, Status>>;
+    type WatchEventsStream = ReceiverStream<Result<Event, Status>>;
+
     async fn status(
         &self,
         _request: Request<StatusRequest>,
     ) -> Result<Response<StatusResponse>, Status> {
-        let meta = meta::AuraeMeta {
-            name: "UNKNOWN_NAME".to_string(),
-            message: "UNKNOWN_MESSAGE".to_string(),
+        let (hostname, kernel) = uname();
+        let boot_time = boot_time().unwrap_or_default();
+        let start_time = start_time(boot_time).unwrap_or_default();
+        let uptime_ms = (unix_timestamp() - start_time).max(0) / 1_000_000;
+        // The same choice of system runtime init::init makes.
+        let mode =
+            if std::process::id() == 1 { Mode::Pid1 } else { Mode::Nested };
+        let subsystems = self.subsystems().await;
+
+        let unhealthy: Vec<&str> = subsystems
+            .iter()
+            .filter(|s| s.health() != Health::Healthy)
+            .map(|s| s.name.as_str())
+            .collect();
+        let message = if unhealthy.is_empty() {
+            "healthy".to_string()
+        } else {
+            format!("not healthy: {}", unhealthy.join(", "))
+        };
+        let response = StatusResponse {
+            meta: Some(meta::AuraeMeta { name: hostname.clone(), message }),
+            hostname,
+            kernel,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            build: env!("AURAED_BUILD").to_string(),
+            boot_time,
+            start_time,
+            uptime_ms: uptime_ms as u64,
+            mode: mode as i32,
+            subsystems,
         };
-        let response = StatusResponse { meta: Some(meta) };
         Ok(Response::new(response))
     }
+
+    async fn get_host_metrics(
+        &self,
+        request: Request<HostMetricsRequest>,
+    ) -> Result<Response<HostMetrics>, Status> {
+        let interval =
+            metrics_interval(&request.into_inner()).min(MAX_METRICS_SAMPLE);
+        let mut sampler = Sampler::new()?;
+        tokio::time::sleep(interval).await;
+        Ok(Response::new(sampler.sample().await?))
+    }
+
+    async fn stream_host_metrics(
+        &self,
+        request: Request<HostMetricsRequest>,
+    ) -> Result<Response<Self::StreamHostMetricsStream>, Status> {
+        let interval = metrics_interval(&request.into_inner());
+        let mut sampler = Sampler::new()?;
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            // The first tick completes at once.
+            let _ = ticks.tick().await;
+            loop {
+                let _ = ticks.tick().await;
+                let metrics = sampler.sample().await.map_err(Status::from);
+                let failed = metrics.is_err();
+                // Stops once the client goes away.
+                if tx.send(metrics).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn get_logs(
+        &self,
+        request: Request<GetLogsRequest>,
+    ) -> Result<Response<Self::GetLogsStream>, Status> {
+        let r = request.into_inner();
+        if r.follow {
+            // Only executables the scheduler knows of are followed.
+            let _ = self.scheduler.store().get(&r.name).await?;
+        }
+        let logs = self.scheduler.logs().clone();
+        // Subscribed before reading, so that no line falls in between.
+        let mut followed = r.follow.then(|| logs.follow(&r.name));
+        let query = LogQuery { tail: r.tail as usize, since: r.since };
+        let name = r.name.clone();
+        let records =
+            tokio::task::spawn_blocking(move || logs.read(&name, query))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+        let records = match records {
+            // A followed executable may not have logged anything yet.
+            Err(LogError::NotFound { .. }) if r.follow => Vec::new(),
+            records => records?,
+        };
+
+        let (tx, rx) = mpsc::channel(LOG_STREAM_BUFFER);
+        tokio::spawn(async move {
+            let mut next = 0;
+            for record in records {
+                next = record.sequence + 1;
+                if tx.send(Ok(log_entry(record))).await.is_err() {
+                    return;
+                }
+            }
+            let followed = match followed.as_mut() {
+                Some(followed) => followed,
+                None => return,
+            };
+            loop {
+                let record = tokio::select! {
+                    record = followed.recv() => record,
+                    _ = tx.closed() => return,
+                };
+                match record {
+                    Ok(record) if record.sequence < next => continue,
+                    Ok(record) => {
+                        next = record.sequence + 1;
+                        if tx.send(Ok(log_entry(record))).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Skipped lines show as a gap in the sequence.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn watch_events(
+        &self,
+        request: Request<WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let r = request.into_inner();
+        let after = match r.after_sequence {
+            0 => None,
+            after => Some(after),
+        };
+        let (past, mut watcher) = self.scheduler.events().watch(after);
+        let (tx, rx) = mpsc::channel(EVENT_STREAM_BUFFER);
+        tokio::spawn(async move {
+            let mut next = 0;
+            for event in past {
+                next = event.sequence + 1;
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                let event = tokio::select! {
+                    event = watcher.recv() => event,
+                    _ = tx.closed() => return,
+                };
+                match event {
+                    Ok(event) if event.sequence < next => continue,
+                    Ok(event) => {
+                        next = event.sequence + 1;
+                        if tx.send(Ok(event)).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Missed events show as a gap in the sequence.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn log_entry(record: LogRecord) -> LogEntry {
+    LogEntry {
+        sequence: record.sequence,
+        timestamp: record.timestamp,
+        channel: record.channel as i32,
+        line: record.line,
+    }
+}
+
+fn metrics_interval(request: &HostMetricsRequest) -> Duration {
+    match request.interval_ms {
+        0 => DEFAULT_METRICS_INTERVAL,
+        ms => Duration::from_millis(ms).max(MIN_METRICS_INTERVAL),
+    }
+}
+
+fn subsystem(name: &str, health: Health, message: String) -> SubsystemHealth {
+    SubsystemHealth { name: name.to_string(), health: health as i32, message }
+}
+
+fn runtime_health() -> SubsystemHealth {
+    if cgroups_available() {
+        let message = "executables run in cgroups".to_string();
+        subsystem("runtime", Health::Healthy, message)
+    } else {
+        let message = "cgroup v2 is not available, resource limits are refused"
+            .to_string();
+        subsystem("runtime", Health::Degraded, message)
+    }
+}
+
+/// The hostname and the kernel release of the host.
+fn uname() -> (String, String) {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return Default::default();
+    }
+    let field = |field: &[libc::c_char]| {
+        let bytes: Vec<u8> =
+            field.iter().take_while(|c| **c != 0).map(|c| *c as u8).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    (field(&uts.nodename), field(&uts.release))
+}
+
+/// The time the host booted, in nanoseconds since the Unix epoch.
+fn boot_time() -> io::Result<i64> {
+    let stat = std::fs::read_to_string("/proc/stat")?;
+    parse_boot_time(&stat)
+        .map(|secs| secs * NANOS_PER_SEC)
+        .ok_or_else(|| invalid("no btime in /proc/stat"))
+}
+
+fn parse_boot_time(stat: &str) -> Option<i64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|secs| secs.trim().parse().ok())
+}
+
+/// The time auraed started, in nanoseconds since the Unix epoch.
+fn start_time(boot_time: i64) -> io::Result<i64> {
+    let stat = std::fs::read_to_string("/proc/self/stat")?;
+    let ticks = parse_start_ticks(&stat)
+        .ok_or_else(|| invalid("no starttime in /proc/self/stat"))?;
+    let per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if per_sec <= 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(boot_time + ticks * NANOS_PER_SEC / per_sec as i64)
+}
+
+/// The start time of a process in clock ticks after boot, which is the 22nd
+/// field of its stat file. The name in the 2nd field may hold spaces and
+/// parentheses itself, so fields are counted from its closing parenthesis.
+fn parse_start_ticks(stat: &str) -> Option<i64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc() {
+        let stat = "cpu  10 0 5 100 0 0 0 0 0 0\nintr 1\nbtime 1700000000\nprocesses 5\n";
+        assert_eq!(parse_boot_time(stat), Some(1_700_000_000));
+        assert_eq!(parse_boot_time("cpu 1\n"), None);
+
+        let stat = "42 (a b) c) S 1 42 42 0 -1 4194560 100 0 0 0 3 1 0 0 20 0 1 0 12345 1000 100";
+        assert_eq!(parse_start_ticks(stat), Some(12345));
+    }
+
+    #[test]
+    fn test_start_time() {
+        let boot = boot_time().expect("boot time");
+        let start = start_time(boot).expect("start time");
+        assert!(boot < start && start <= unix_timestamp());
+    }
 }
@@ -0,0 +1,382 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::ExecutableResources;
+use log::{info, warn};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Where the unified cgroup v2 hierarchy is mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Name of the cgroup the cgroups of executables are created in.
+const EXECUTABLES_CGROUP: &str = "auraed";
+
+/// Name of the cgroup auraed moves itself into when it was started in a
+/// delegated cgroup, since a cgroup with processes of its own can not
+/// distribute resources to its children.
+const DAEMON_CGROUP: &str = "daemon";
+
+/// Controllers enabled for the cgroups of executables, if available.
+const CONTROLLERS: [&str; 4] = ["cpu", "memory", "pids", "io"];
+
+/// Number of times the removal of a cgroup is attempted, while the killed
+/// processes in it are still exiting.
+const REMOVE_ATTEMPTS: u32 = 20;
+const REMOVE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The cgroup the cgroups of executables are created in. None when cgroup v2
+/// is not available on the host.
+static SUBTREE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Sequence number that keeps the cgroups of executables with the same name
+/// apart.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum CgroupError {
+    #[error("resource limits require cgroup v2, which is not available")]
+    Unavailable,
+    #[error("failed to set up cgroup {path}: {source}")]
+    SetupFailure { path: PathBuf, source: io::Error },
+    #[error("failed to set {file} to {value}: {source}")]
+    LimitFailure { file: String, value: String, source: io::Error },
+}
+
+/// Sets up the cgroup the executables of auraed are placed under. Without
+/// cgroup v2 executables run in the cgroup of auraed itself, and executables
+/// with resource limits are refused.
+pub(crate) fn init() -> Result<(), CgroupError> {
+    let subtree = match discover() {
+        Ok(Some(parent)) => Some(setup(&parent)?),
+        Ok(None) => {
+            warn!("cgroup v2 is not available, executables run without resource limits");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to find the cgroup of auraed: {}", e);
+            None
+        }
+    };
+    if let Some(subtree) = &subtree {
+        info!("Executables run in cgroups below {}", subtree.display());
+    }
+    let _ = SUBTREE.set(subtree);
+    Ok(())
+}
+
+/// Whether executables run in cgroups of their own, and can be given
+/// resource limits.
+pub(crate) fn available() -> bool {
+    matches!(SUBTREE.get(), Some(Some(_)))
+}
+
+/// Finds the cgroup v2 directory of auraed, if cgroup v2 is mounted.
+fn discover() -> io::Result<Option<PathBuf>> {
+    let root = Path::new(CGROUP_ROOT);
+    if !root.join("cgroup.controllers").exists() {
+        return Ok(None);
+    }
+    // The unified hierarchy is the entry with hierarchy ID 0.
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let own = cgroups.lines().find_map(|line| line.strip_prefix("0::"));
+    Ok(own.map(|own| root.join(own.trim_start_matches('/'))))
+}
+
+/// Prepares `parent`, the cgroup of auraed, to hold the cgroups of
+/// executables, and returns the cgroup they are created in.
+fn setup(parent: &Path) -> Result<PathBuf, CgroupError> {
+    if parent != Path::new(CGROUP_ROOT) {
+        let daemon = parent.join(DAEMON_CGROUP);
+        create(&daemon)
+            .and_then(|_| {
+                let pid = std::process::id().to_string();
+                fs::write(daemon.join("cgroup.procs"), pid)
+            })
+            .map_err(|source| setup_failure(&daemon, source))?;
+    }
+    enable_controllers(parent);
+
+    let subtree = parent.join(EXECUTABLES_CGROUP);
+    create(&subtree).map_err(|source| setup_failure(&subtree, source))?;
+    enable_controllers(&subtree);
+    // Cgroups left behind by a previous auraed are removed, as long as they
+    // are empty.
+    if let Ok(entries) = fs::read_dir(&subtree) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                let _ = fs::remove_dir(entry.path());
+            }
+        }
+    }
+    Ok(subtree)
+}
+
+fn setup_failure(path: &Path, source: io::Error) -> CgroupError {
+    CgroupError::SetupFailure { path: path.to_path_buf(), source }
+}
+
+fn create(path: &Path) -> io::Result<()> {
+    match fs::create_dir(path) {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(()),
+        result => result,
+    }
+}
+
+/// Enables the controllers executables are limited with for the children of
+/// `path`, as far as they are available to it.
+fn enable_controllers(path: &Path) {
+    let available =
+        fs::read_to_string(path.join("cgroup.controllers")).unwrap_or_default();
+    let available: Vec<&str> = available.split_whitespace().collect();
+    for controller in CONTROLLERS {
+        if !available.contains(&controller) {
+            warn!(
+                "cgroup controller {} is not available in {}",
+                controller,
+                path.display()
+            );
+            continue;
+        }
+        let control = path.join("cgroup.subtree_control");
+        if let Err(e) = fs::write(&control, format!("+{}", controller)) {
+            warn!(
+                "Failed to enable cgroup controller {} in {}: {}",
+                controller,
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Cgroup is the cgroup an executable runs in. The cgroup is killed and
+/// removed once it is dropped, so it has to be kept for as long as the
+/// executable runs.
+#[derive(Debug)]
+pub(crate) struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates a cgroup for the executable `name`, with `resources` applied.
+    /// Returns None if cgroup v2 is not available and no limits are asked
+    /// for.
+    pub fn create(
+        name: &str,
+        resources: Option<&ExecutableResources>,
+    ) -> Result<Option<Self>, CgroupError> {
+        let limits = resources.map(limits).unwrap_or_default();
+        let subtree = match SUBTREE.get() {
+            Some(Some(subtree)) => subtree,
+            _ if limits.is_empty() => return Ok(None),
+            _ => return Err(CgroupError::Unavailable),
+        };
+
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        let path = subtree.join(format!("{}.{}", sanitize(name), sequence));
+        fs::create_dir(&path).map_err(|source| setup_failure(&path, source))?;
+        // From here on a failure removes the cgroup again.
+        let cgroup = Self { path };
+        for (file, value) in limits {
+            fs::write(cgroup.path.join(file), &value).map_err(|source| {
+                CgroupError::LimitFailure {
+                    file: file.to_string(),
+                    value,
+                    source,
+                }
+            })?;
+        }
+        Ok(Some(cgroup))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The bytes read from and written to storage by the processes of the
+    /// cgroup, summed over all devices. None if io.stat is not available.
+    pub fn io_bytes(&self) -> Option<(u64, u64)> {
+        io_bytes(&self.path)
+    }
+
+    /// The number of processes of the cgroup that were killed for running
+    /// out of memory.
+    pub fn oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|line| {
+                    line.strip_prefix("oom_kill ")?.trim().parse().ok()
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Opens the file processes are moved into the cgroup with. A child
+    /// moves itself into the cgroup by writing "0" to it before it execs.
+    pub fn procs(&self) -> io::Result<File> {
+        OpenOptions::new().write(true).open(self.path.join("cgroup.procs"))
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        // Removing the cgroup waits for its processes to be reaped, which
+        // must not hold up a worker thread of the async runtime.
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(move || remove(&path))),
+            Err(_) => remove(&path),
+        }
+    }
+}
+
+/// The bytes read from and written to storage by the processes of the
+/// cgroup at `path`. None if io.stat is not available.
+pub(crate) fn io_bytes(path: &Path) -> Option<(u64, u64)> {
+    let stat = fs::read_to_string(path.join("io.stat")).ok()?;
+    Some(parse_io_stat(&stat))
+}
+
+/// Kills anything still running in the cgroup at `path`, and removes it.
+fn remove(path: &Path) {
+    // Processes that left the process group of the executable are killed
+    // as well.
+    let _ = fs::write(path.join("cgroup.kill"), "1");
+    for _ in 0..REMOVE_ATTEMPTS {
+        match fs::remove_dir(path) {
+            Ok(()) => return,
+            Err(e) if e.kind() == ErrorKind::NotFound => return,
+            // The cgroup is busy until the killed processes are reaped.
+            Err(_) => std::thread::sleep(REMOVE_INTERVAL),
+        }
+    }
+    warn!("Failed to remove cgroup {}", path.display());
+}
+
+/// The cgroup files and their values for the limits set in `resources`.
+fn limits(resources: &ExecutableResources) -> Vec<(&'static str, String)> {
+    let mut limits = Vec::new();
+    if resources.cpu_weight != 0 {
+        limits.push(("cpu.weight", resources.cpu_weight.to_string()));
+    }
+    if !resources.cpu_max.is_empty() {
+        limits.push(("cpu.max", resources.cpu_max.clone()));
+    }
+    if resources.memory_max != 0 {
+        limits.push(("memory.max", resources.memory_max.to_string()));
+    }
+    if resources.memory_high != 0 {
+        limits.push(("memory.high", resources.memory_high.to_string()));
+    }
+    if resources.memory_low != 0 {
+        limits.push(("memory.low", resources.memory_low.to_string()));
+    }
+    if resources.pids_max != 0 {
+        limits.push(("pids.max", resources.pids_max.to_string()));
+    }
+    if resources.io_weight != 0 {
+        limits.push(("io.weight", resources.io_weight.to_string()));
+    }
+    limits
+}
+
+fn parse_io_stat(stat: &str) -> (u64, u64) {
+    let (mut read, mut written) = (0, 0);
+    for field in stat.split_whitespace() {
+        let parse = |value: &str| value.parse::<u64>().unwrap_or_default();
+        match field.split_once('=') {
+            Some(("rbytes", value)) => read += parse(value),
+            Some(("wbytes", value)) => written += parse(value),
+            _ => {}
+        }
+    }
+    (read, written)
+}
+
+/// Turns the name of an executable into a valid cgroup name.
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .take(64)
+        .collect();
+    match name.as_str() {
+        "" => "executable".to_string(),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits() {
+        let resources = ExecutableResources {
+            cpu_max: "50000 100000".to_string(),
+            memory_max: 64 << 20,
+            pids_max: 32,
+            ..Default::default()
+        };
+        assert_eq!(
+            limits(&resources),
+            vec![
+                ("cpu.max", "50000 100000".to_string()),
+                ("memory.max", "67108864".to_string()),
+                ("pids.max", "32".to_string()),
+            ]
+        );
+        assert!(limits(&ExecutableResources::default()).is_empty());
+    }
+
+    #[test]
+    fn test_parse_io_stat() {
+        let stat = "8:0 rbytes=4096 wbytes=512 rios=1 wios=1 dbytes=0 dios=0\n\
+                    8:16 rbytes=1024 wbytes=0 rios=1 wios=0 dbytes=0 dios=0\n";
+        assert_eq!(parse_io_stat(stat), (5120, 512));
+        assert_eq!(parse_io_stat(""), (0, 0));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("web-1"), "web-1");
+        assert_eq!(sanitize("ls -la /"), "ls_-la__");
+        assert_eq!(sanitize(""), "executable");
+    }
+}
@@ -0,0 +1,1500 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::image::{ImageError, ImageStore, RootfsUse};
+use crate::meta;
+use crate::runtime::executable::check;
+use crate::runtime::namespace::decimal;
+use crate::runtime::oci::{self, Spec};
+use crate::runtime::process::{ProcessError, ProcessTable};
+use crate::runtime::{
+    Container, ContainerStatus, Executable, ExecutableIdMapping,
+    ExecutableNamespaces, ExecutableResources, ExecutableStatus,
+    ExecutableUser,
+};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::ptr;
+use tonic::Status;
+
+/// Devices of the host made available in the /dev of a container.
+const DEVICES: [&str; 6] = ["null", "zero", "full", "random", "urandom", "tty"];
+
+/// Links created in the /dev of a container.
+const DEVICE_LINKS: [(&str, &str); 5] = [
+    ("/proc/self/fd", "fd"),
+    ("/proc/self/fd/0", "stdin"),
+    ("/proc/self/fd/1", "stdout"),
+    ("/proc/self/fd/2", "stderr"),
+    ("pts/ptmx", "ptmx"),
+];
+
+/// PATH of containers of images that do not set one.
+const DEFAULT_PATH: &str =
+    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Mounts of containers of images, as (destination, type, options).
+const DEFAULT_MOUNTS: [(&str, &str, &[&str]); 7] = [
+    ("/proc", "proc", &["nosuid", "noexec", "nodev"]),
+    ("/dev", "tmpfs", &["nosuid", "strictatime", "mode=755", "size=65536k"]),
+    (
+        "/dev/pts",
+        "devpts",
+        &["nosuid", "noexec", "newinstance", "ptmxmode=0666", "mode=0620"],
+    ),
+    ("/dev/shm", "tmpfs", &["nosuid", "noexec", "nodev", "mode=1777"]),
+    ("/sys", "sysfs", &["nosuid", "noexec", "nodev", "ro"]),
+    ("/tmp", "tmpfs", &["nosuid", "nodev", "mode=1777"]),
+    ("/run", "tmpfs", &["nosuid", "nodev", "mode=755"]),
+];
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ContainerError {
+    #[error("container has no name, set name to track it")]
+    MissingName,
+    #[error("container {name} has neither a bundle nor an image")]
+    MissingBundle { name: String },
+    #[error("invalid container name {name}")]
+    InvalidName { name: String },
+    #[error("failed to read {path}: {source}")]
+    ReadConfig { path: PathBuf, source: io::Error },
+    #[error("invalid {path}: {source}")]
+    InvalidConfig { path: PathBuf, source: serde_json::Error },
+    #[error("unsupported bundle: {0}")]
+    Unsupported(String),
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error(transparent)]
+    Process(#[from] ProcessError),
+}
+
+impl From<ContainerError> for Status {
+    fn from(e: ContainerError) -> Self {
+        match e {
+            ContainerError::Image(e) => e.into(),
+            ContainerError::Process(e) => e.into(),
+            ContainerError::ReadConfig { ref source, .. }
+                if source.kind() == io::ErrorKind::NotFound =>
+            {
+                Status::not_found(e.to_string())
+            }
+            ContainerError::ReadConfig { .. } => {
+                Status::internal(e.to_string())
+            }
+            ContainerError::MissingName
+            | ContainerError::MissingBundle { .. }
+            | ContainerError::InvalidName { .. }
+            | ContainerError::InvalidConfig { .. }
+            | ContainerError::Unsupported(_) => {
+                Status::invalid_argument(e.to_string())
+            }
+        }
+    }
+}
+
+/// Starts the process of the bundle of `container`, or of its image in
+/// `images`, tracked in `containers` by the name of the container.
+pub(crate) fn start(
+    containers: &ProcessTable,
+    images: &ImageStore,
+    container: &Container,
+) -> Result<ContainerStatus, ContainerError> {
+    let name = match (container.name.as_str(), &container.meta) {
+        ("", Some(meta)) if !meta.name.is_empty() => meta.name.as_str(),
+        ("", _) => return Err(ContainerError::MissingName),
+        (name, _) => name,
+    };
+    if container.bundle.is_empty() && container.image.is_empty() {
+        return Err(ContainerError::MissingBundle { name: name.to_string() });
+    }
+    // The name is held until the container runs, so that the writable layer
+    // of a running container is never reset under it.
+    let starting = containers.reserve(name)?;
+    let bundle = match (container.bundle.as_str(), container.image.as_str()) {
+        ("", image) => Bundle::from_image(name, images, image)?,
+        (bundle, _) => Bundle::load(name, Path::new(bundle))?,
+    };
+    let status = starting.start_in(&bundle.executable, Some(bundle.root))?;
+    if let Some(image) = bundle.image {
+        let process = containers.get(name)?;
+        drop(tokio::spawn(async move {
+            let _ = process.exited().await;
+            drop(image);
+        }));
+    }
+    Ok(container_status(status))
+}
+
+/// The status of a container, taken from the status of its process.
+pub(crate) fn container_status(status: ExecutableStatus) -> ContainerStatus {
+    ContainerStatus {
+        meta: status.meta,
+        proc: status.proc,
+        status: status.status,
+        exit_code: status.exit_code,
+        signal: status.signal,
+    }
+}
+
+/// Bundle is a local OCI runtime bundle, translated into the executable that
+/// runs its process and the root filesystem that is set up for it.
+#[derive(Debug)]
+pub(crate) struct Bundle {
+    pub executable: Executable,
+    pub root: ContainerRoot,
+    /// Image keeps the image of a container of an image from being removed
+    /// while the container runs.
+    pub image: Option<RootfsUse>,
+}
+
+impl Bundle {
+    /// Loads the config.json of the bundle in `path`.
+    pub fn load(name: &str, path: &Path) -> Result<Self, ContainerError> {
+        let config = path.join("config.json");
+        let spec = std::fs::read(&config).map_err(|source| {
+            ContainerError::ReadConfig { path: config.clone(), source }
+        })?;
+        let spec: Spec = serde_json::from_slice(&spec).map_err(|source| {
+            ContainerError::InvalidConfig { path: config, source }
+        })?;
+        Self::from_spec(name, path, spec)
+    }
+
+    /// Builds a bundle for `reference` in `images`, with the process its
+    /// configuration asks for, and the namespaces and mounts containers of
+    /// images get by default. Containers of images share the network of the
+    /// node.
+    ///
+    /// The root filesystem of the image is shared by its containers, and
+    /// never written to. Each container gets a writable layer of its own on
+    /// top of it, that its mount points and changes go to.
+    pub fn from_image(
+        name: &str,
+        images: &ImageStore,
+        reference: &str,
+    ) -> Result<Self, ContainerError> {
+        if matches!(name, "." | "..") || name.contains('/') {
+            return Err(ContainerError::InvalidName { name: name.to_string() });
+        }
+        let image = images.get(reference)?;
+        let config = images.config(&image)?.config.unwrap_or_default();
+        let (lower, used) = images.use_rootfs(&image)?;
+        let layer = images.container_layer(name)?;
+        let (upper, work) = (layer.join("upper"), layer.join("work"));
+        // Overlayfs separates its options with commas, and lower
+        // directories with colons.
+        if [&lower, &upper, &work]
+            .iter()
+            .any(|dir| dir.to_string_lossy().contains([',', ':']))
+        {
+            return Err(ContainerError::Unsupported(format!(
+                "image store path {} contains ',' or ':'",
+                layer.display()
+            )));
+        }
+        let overlay = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            lower.display(),
+            upper.display(),
+            work.display()
+        );
+        let rootfs = layer.join("rootfs");
+
+        let args: Vec<String> =
+            config.entrypoint.into_iter().chain(config.cmd).flatten().collect();
+        let (uid, gid) = image_user(&config.user)?;
+        let mut env = config.env.unwrap_or_default();
+        if !env.iter().any(|var| var.starts_with("PATH=")) {
+            env.push(format!("PATH={}", DEFAULT_PATH));
+        }
+        let spec = Spec {
+            process: Some(oci::Process {
+                args,
+                env,
+                cwd: config.working_dir,
+                user: oci::User { uid, gid, ..Default::default() },
+                ..Default::default()
+            }),
+            root: Some(oci::Root {
+                path: rootfs.display().to_string(),
+                readonly: true,
+            }),
+            hostname: name.to_string(),
+            mounts: DEFAULT_MOUNTS
+                .iter()
+                .map(|(destination, kind, options)| oci::Mount {
+                    destination: destination.to_string(),
+                    kind: kind.to_string(),
+                    source: kind.to_string(),
+                    options: options.iter().map(|o| o.to_string()).collect(),
+                })
+                .collect(),
+            linux: Some(oci::Linux {
+                namespaces: ["pid", "mount", "ipc", "uts"]
+                    .iter()
+                    .map(|kind| oci::Namespace {
+                        kind: kind.to_string(),
+                        path: None,
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut bundle = Self::from_spec(name, &layer, spec)?;
+        bundle.root.overlay = Some(cstring(overlay)?);
+        bundle.image = Some(used);
+        Ok(bundle)
+    }
+
+    fn from_spec(
+        name: &str,
+        bundle: &Path,
+        spec: Spec,
+    ) -> Result<Self, ContainerError> {
+        let unsupported =
+            |reason: &str| ContainerError::Unsupported(reason.into());
+        let process = spec.process.ok_or_else(|| unsupported("no process"))?;
+        if process.terminal {
+            return Err(unsupported("process.terminal is not supported"));
+        }
+        let (command, args) = process
+            .args
+            .split_first()
+            .ok_or_else(|| unsupported("process.args is empty"))?;
+        let root = spec.root.ok_or_else(|| unsupported("no root"))?;
+        let linux = spec.linux.unwrap_or_default();
+        if linux.seccomp.is_some() {
+            return Err(unsupported("linux.seccomp is not supported"));
+        }
+        if !linux.devices.is_empty() {
+            return Err(unsupported("linux.devices is not supported"));
+        }
+
+        let mut cgroup_namespace = false;
+        let mut namespaces = ExecutableNamespaces {
+            hostname: spec.hostname,
+            uid_mappings: linux.uid_mappings.iter().map(id_mapping).collect(),
+            gid_mappings: linux.gid_mappings.iter().map(id_mapping).collect(),
+            ..Default::default()
+        };
+        for namespace in &linux.namespaces {
+            if namespace.path.is_some() {
+                return Err(ContainerError::Unsupported(format!(
+                    "joining the {} namespace by path is not supported",
+                    namespace.kind
+                )));
+            }
+            let enabled = match namespace.kind.as_str() {
+                "pid" => &mut namespaces.pid,
+                "mount" => &mut namespaces.mount,
+                "uts" => &mut namespaces.uts,
+                "ipc" => &mut namespaces.ipc,
+                "network" => &mut namespaces.net,
+                "user" => &mut namespaces.user,
+                "cgroup" => &mut cgroup_namespace,
+                kind => {
+                    return Err(ContainerError::Unsupported(format!(
+                        "unknown namespace {}",
+                        kind
+                    )))
+                }
+            };
+            *enabled = true;
+        }
+        if !namespaces.mount {
+            return Err(unsupported("a mount namespace is required"));
+        }
+
+        let executable = Executable {
+            meta: Some(meta::AuraeMeta {
+                name: name.to_string(),
+                message: String::new(),
+            }),
+            command: command.clone(),
+            args: args.to_vec(),
+            argv: true,
+            env: process
+                .env
+                .iter()
+                .map(|var| match var.split_once('=') {
+                    Some((key, value)) => (key.to_string(), value.to_string()),
+                    None => (var.to_string(), String::new()),
+                })
+                .collect::<HashMap<_, _>>(),
+            clear_env: true,
+            user: Some(ExecutableUser {
+                uid: process.user.uid,
+                gid: process.user.gid,
+                groups: process.user.additional_gids.clone(),
+            }),
+            umask: process
+                .user
+                .umask
+                .map(|umask| format!("{:o}", umask))
+                .unwrap_or_default(),
+            resources: linux.resources.as_ref().map(resources),
+            namespaces: Some(namespaces),
+            ..Default::default()
+        };
+
+        let rootfs = bundle.join(&root.path);
+        let mut mounts = Vec::new();
+        for mount in &spec.mounts {
+            mounts.push(PreparedMount::new(bundle, mount)?);
+        }
+        let mut links = Vec::new();
+        if spec
+            .mounts
+            .iter()
+            .any(|m| Path::new(&m.destination) == Path::new("/dev"))
+        {
+            for device in DEVICES {
+                let host = Path::new("/dev").join(device);
+                if host.exists() {
+                    mounts.push(PreparedMount::device(&host)?);
+                }
+            }
+            for (target, link) in DEVICE_LINKS {
+                links.push((
+                    cstring(target)?,
+                    RootPath::new(&format!("/dev/{}", link))?,
+                ));
+            }
+        }
+
+        let root = ContainerRoot {
+            rootfs: cstring(&rootfs)?,
+            overlay: None,
+            readonly: root.readonly,
+            mounts,
+            links,
+            cgroup_namespace,
+            masked_paths: linux
+                .masked_paths
+                .iter()
+                .map(cstring)
+                .collect::<Result<_, _>>()?,
+            readonly_paths: linux
+                .readonly_paths
+                .iter()
+                .map(cstring)
+                .collect::<Result<_, _>>()?,
+            cwd: cstring(match process.cwd.as_str() {
+                "" => "/",
+                cwd => cwd,
+            })?,
+            rlimits: process
+                .rlimits
+                .iter()
+                .map(|r| {
+                    let resource =
+                        rlimit_resource(&r.kind).ok_or_else(|| {
+                            ContainerError::Unsupported(format!(
+                                "unknown rlimit {}",
+                                r.kind
+                            ))
+                        })?;
+                    Ok((
+                        resource,
+                        libc::rlimit { rlim_cur: r.soft, rlim_max: r.hard },
+                    ))
+                })
+                .collect::<Result<_, ContainerError>>()?,
+            no_new_privileges: process.no_new_privileges,
+            capabilities: match &process.capabilities {
+                Some(capabilities) => Capabilities::new(capabilities)?,
+                None => Capabilities::default(),
+            },
+        };
+        Ok(Self { executable, root, image: None })
+    }
+}
+
+/// The user and group ids of the user of an image, given as "uid" or
+/// "uid:gid". Users given by name are not supported.
+fn image_user(user: &str) -> Result<(u32, u32), ContainerError> {
+    let invalid = || {
+        ContainerError::Unsupported(format!(
+            "image user {} is not numeric",
+            user
+        ))
+    };
+    let (uid, gid) = match user.split_once(':') {
+        _ if user.is_empty() => return Ok((0, 0)),
+        Some((uid, gid)) => (uid, gid),
+        None => (user, "0"),
+    };
+    Ok((
+        uid.parse().map_err(|_| invalid())?,
+        gid.parse().map_err(|_| invalid())?,
+    ))
+}
+
+fn id_mapping(mapping: &oci::IdMapping) -> ExecutableIdMapping {
+    ExecutableIdMapping {
+        container_id: mapping.container_id,
+        host_id: mapping.host_id,
+        size: mapping.size,
+    }
+}
+
+/// Translates the cgroup v1 style resources of the runtime spec into the
+/// cgroup v2 limits of an executable.
+fn resources(resources: &oci::Resources) -> ExecutableResources {
+    let mut limits = ExecutableResources::default();
+    if let Some(memory) = &resources.memory {
+        limits.memory_max = memory.limit.filter(|l| *l > 0).unwrap_or(0) as u64;
+        limits.memory_low =
+            memory.reservation.filter(|r| *r > 0).unwrap_or(0) as u64;
+    }
+    if let Some(cpu) = &resources.cpu {
+        // The conversion from shares to weight the OCI runtimes agree on.
+        if let Some(shares) = cpu.shares.filter(|s| *s >= 2) {
+            limits.cpu_weight = 1 + ((shares.min(262144) - 2) * 9999) / 262142;
+        }
+        if let Some(quota) = cpu.quota.filter(|q| *q > 0) {
+            limits.cpu_max =
+                format!("{} {}", quota, cpu.period.unwrap_or(100000));
+        }
+    }
+    if let Some(pids) = resources.pids.as_ref().filter(|p| p.limit > 0) {
+        limits.pids_max = pids.limit as u64;
+    }
+    limits
+}
+
+fn rlimit_resource(kind: &str) -> Option<libc::__rlimit_resource_t> {
+    Some(match kind {
+        "RLIMIT_AS" => libc::RLIMIT_AS,
+        "RLIMIT_CORE" => libc::RLIMIT_CORE,
+        "RLIMIT_CPU" => libc::RLIMIT_CPU,
+        "RLIMIT_DATA" => libc::RLIMIT_DATA,
+        "RLIMIT_FSIZE" => libc::RLIMIT_FSIZE,
+        "RLIMIT_MEMLOCK" => libc::RLIMIT_MEMLOCK,
+        "RLIMIT_NOFILE" => libc::RLIMIT_NOFILE,
+        "RLIMIT_NPROC" => libc::RLIMIT_NPROC,
+        "RLIMIT_STACK" => libc::RLIMIT_STACK,
+        _ => return None,
+    })
+}
+
+/// The capabilities of Linux, indexed by their number.
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// The capabilities containers get when their bundle names none, the
+/// default set of OCI runtimes.
+const DEFAULT_CAPABILITIES: [&str; 14] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FSETID",
+    "CAP_FOWNER",
+    "CAP_MKNOD",
+    "CAP_NET_RAW",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETFCAP",
+    "CAP_SETPCAP",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_SYS_CHROOT",
+    "CAP_KILL",
+    "CAP_AUDIT_WRITE",
+];
+
+/// Capabilities are the capability sets of the process of a container, as
+/// masks of capability numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Capabilities {
+    bounding: u64,
+    effective: u64,
+    permitted: u64,
+    inheritable: u64,
+    ambient: u64,
+}
+
+impl Capabilities {
+    fn new(capabilities: &oci::Capabilities) -> Result<Self, ContainerError> {
+        Ok(Self {
+            bounding: capability_mask(&capabilities.bounding)?,
+            effective: capability_mask(&capabilities.effective)?,
+            permitted: capability_mask(&capabilities.permitted)?,
+            inheritable: capability_mask(&capabilities.inheritable)?,
+            ambient: capability_mask(&capabilities.ambient)?,
+        })
+    }
+
+    /// Drops the capabilities that are not in the bounding set, for the
+    /// process and everything it execs. Has to run while the process is
+    /// still privileged, and keeps its permitted capabilities across the
+    /// change of its user.
+    unsafe fn bound(&self) -> io::Result<()> {
+        for cap in 0..u64::BITS {
+            if self.bounding & 1 << cap != 0 {
+                continue;
+            }
+            match check(libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0)) {
+                // Capabilities past the last one of the kernel do not exist.
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => break,
+                dropped => dropped?,
+            }
+        }
+        check(libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0))
+    }
+
+    /// Sets the effective, permitted, inheritable and ambient sets. Has to
+    /// run once the user of the process is set.
+    unsafe fn set(&self) -> io::Result<()> {
+        #[repr(C)]
+        struct Header {
+            version: u32,
+            pid: libc::c_int,
+        }
+        #[repr(C)]
+        struct Data {
+            effective: u32,
+            permitted: u32,
+            inheritable: u32,
+        }
+        const VERSION_3: u32 = 0x2008_0522;
+        let header = Header { version: VERSION_3, pid: 0 };
+        let data = [0, 32].map(|shift| Data {
+            effective: (self.effective >> shift) as u32,
+            permitted: (self.permitted >> shift) as u32,
+            inheritable: (self.inheritable >> shift) as u32,
+        });
+        if libc::syscall(libc::SYS_capset, &header, data.as_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for cap in 0..u64::BITS {
+            if self.ambient & 1 << cap != 0 {
+                check(libc::prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE,
+                    cap,
+                    0,
+                    0,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        let mask = DEFAULT_CAPABILITIES
+            .iter()
+            .filter_map(|name| CAPABILITIES.iter().position(|c| c == name))
+            .fold(0, |mask, cap| mask | 1 << cap);
+        Self {
+            bounding: mask,
+            effective: mask,
+            permitted: mask,
+            inheritable: 0,
+            ambient: 0,
+        }
+    }
+}
+
+fn capability_mask(names: &[String]) -> Result<u64, ContainerError> {
+    names.iter().try_fold(0, |mask, name| {
+        let cap =
+            CAPABILITIES.iter().position(|c| c == name).ok_or_else(|| {
+                ContainerError::Unsupported(format!(
+                    "unknown capability {}",
+                    name
+                ))
+            })?;
+        Ok(mask | 1 << cap)
+    })
+}
+
+fn cstring(path: impl AsRef<Path>) -> Result<CString, ContainerError> {
+    let path = path.as_ref();
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        ContainerError::Unsupported(format!("invalid path {:?}", path))
+    })
+}
+
+/// RootPath is a path in the root filesystem of a container. It is only
+/// ever resolved with the root filesystem as its root, so that symbolic
+/// links in the root filesystem cannot lead out of it.
+#[derive(Debug)]
+struct RootPath {
+    /// Path is relative to the root filesystem, "." for the root itself.
+    path: CString,
+    /// Parent is the path of the directory the path is in.
+    parent: CString,
+    /// Name is the last component of the path.
+    name: CString,
+}
+
+impl RootPath {
+    /// Refuses paths that would leave the root filesystem by themselves.
+    fn new(path: &str) -> Result<Self, ContainerError> {
+        let mut parts = Vec::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => parts.push(part),
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir | Component::Prefix(_) => {
+                    return Err(ContainerError::Unsupported(format!(
+                        "mount destination {} leaves the root filesystem",
+                        path
+                    )))
+                }
+            }
+        }
+        let join = |parts: &[&std::ffi::OsStr]| match parts.is_empty() {
+            true => PathBuf::from("."),
+            false => parts.iter().collect(),
+        };
+        let name = parts.last().copied().unwrap_or(".".as_ref());
+        Ok(Self {
+            path: cstring(join(&parts))?,
+            parent: cstring(join(&parts[..parts.len().saturating_sub(1)]))?,
+            name: cstring(name)?,
+        })
+    }
+
+    /// The paths of this path and of the directories it is in, parents
+    /// first.
+    fn ancestors(path: &str) -> Result<Vec<Self>, ContainerError> {
+        let mut ancestors: Vec<&Path> = Path::new(path)
+            .ancestors()
+            .filter(|p| p.file_name().is_some())
+            .collect();
+        ancestors.reverse();
+        ancestors
+            .into_iter()
+            .map(|p| RootPath::new(&p.to_string_lossy()))
+            .collect()
+    }
+
+    /// Opens the path below `root` with `flags`.
+    unsafe fn open(
+        &self,
+        root: libc::c_int,
+        flags: libc::c_int,
+    ) -> io::Result<libc::c_int> {
+        open_in_root(root, &self.path, flags)
+    }
+
+    /// Creates the directory at the path below `root`, unless there is
+    /// something there already.
+    unsafe fn mkdir(&self, root: libc::c_int) -> io::Result<()> {
+        let parent = open_in_root(root, &self.parent, DIRECTORY)?;
+        let ret = libc::mkdirat(parent, self.name.as_ptr(), 0o755);
+        let err = io::Error::last_os_error();
+        let _ = libc::close(parent);
+        match ret {
+            -1 if err.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+            -1 => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates an empty file at the path below `root`, unless there is
+    /// something there already.
+    unsafe fn touch(&self, root: libc::c_int) -> io::Result<()> {
+        let parent = open_in_root(root, &self.parent, DIRECTORY)?;
+        // An existing link is left alone, it is resolved in the root once
+        // the file is opened.
+        let fd = libc::openat(
+            parent,
+            self.name.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC,
+            0o644,
+        );
+        let err = io::Error::last_os_error();
+        let _ = libc::close(parent);
+        match fd {
+            -1 if err.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+            -1 => Err(err),
+            fd => {
+                let _ = libc::close(fd);
+                Ok(())
+            }
+        }
+    }
+
+    /// Creates a symbolic link to `target` at the path below `root`.
+    unsafe fn symlink(
+        &self,
+        root: libc::c_int,
+        target: &CString,
+    ) -> io::Result<()> {
+        let parent = open_in_root(root, &self.parent, DIRECTORY)?;
+        let ret = libc::symlinkat(target.as_ptr(), parent, self.name.as_ptr());
+        let err = io::Error::last_os_error();
+        let _ = libc::close(parent);
+        match ret {
+            -1 if err.raw_os_error() == Some(libc::EEXIST) => Ok(()),
+            -1 => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Flags a directory that is only resolved, and never read, is opened with.
+const DIRECTORY: libc::c_int = libc::O_PATH | libc::O_DIRECTORY;
+
+/// Opens `path` below the directory `root` with `flags`, resolving `..` and
+/// symbolic links as if `root` were the root of the filesystem.
+unsafe fn open_in_root(
+    root: libc::c_int,
+    path: &CString,
+    flags: libc::c_int,
+) -> io::Result<libc::c_int> {
+    let mut how: libc::open_how = std::mem::zeroed();
+    how.flags = (flags | libc::O_CLOEXEC) as u64;
+    how.resolve = libc::RESOLVE_IN_ROOT | libc::RESOLVE_NO_MAGICLINKS;
+    let fd = libc::syscall(
+        libc::SYS_openat2,
+        root,
+        path.as_ptr(),
+        ptr::addr_of!(how),
+        std::mem::size_of::<libc::open_how>(),
+    );
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd as libc::c_int)
+}
+
+/// The path of `fd` in /proc/self/fd, which mount follows to whatever the
+/// descriptor refers to.
+struct FdPath([u8; 32]);
+
+impl FdPath {
+    fn new(fd: libc::c_int) -> Self {
+        let mut digits = [0u8; 16];
+        let mut path = [0u8; 32];
+        let mut len = 0;
+        for part in [&b"/proc/self/fd/"[..], decimal(fd, &mut digits), b"\0"] {
+            path[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        Self(path)
+    }
+
+    fn as_ptr(&self) -> *const libc::c_char {
+        self.0.as_ptr().cast()
+    }
+}
+
+/// A mount of the runtime spec, prepared to be mounted in the child.
+#[derive(Debug)]
+struct PreparedMount {
+    source: CString,
+    /// Target is where the mount goes in the root filesystem.
+    target: RootPath,
+    fstype: Option<CString>,
+    flags: libc::c_ulong,
+    propagation: libc::c_ulong,
+    data: Option<CString>,
+    /// Directories created up to the target, parents first.
+    dirs: Vec<RootPath>,
+    /// File is set when a file is bind mounted, which needs a file to be
+    /// mounted on.
+    file: bool,
+    /// Optional mounts are skipped if they fail.
+    optional: bool,
+}
+
+impl PreparedMount {
+    fn new(bundle: &Path, mount: &oci::Mount) -> Result<Self, ContainerError> {
+        let target = RootPath::new(&mount.destination)?;
+        let mut dirs = RootPath::ancestors(&mount.destination)?;
+        let _ = dirs.pop();
+        let (flags, propagation, data) = mount_options(&mount.options);
+        let source = match flags & libc::MS_BIND {
+            0 => PathBuf::from(&mount.source),
+            _ => bundle.join(&mount.source),
+        };
+        let (fstype, optional) = match mount.kind.as_str() {
+            // There is only the unified hierarchy to offer.
+            "cgroup" => (Some("cgroup2"), true),
+            "" | "bind" | "none" => (None, false),
+            kind => (Some(kind), false),
+        };
+        let file = flags & libc::MS_BIND != 0 && !source.is_dir();
+        Ok(Self {
+            source: cstring(&source)?,
+            dirs,
+            target,
+            fstype: fstype.map(cstring).transpose()?,
+            flags,
+            propagation,
+            data: match data.is_empty() {
+                true => None,
+                false => Some(cstring(data.join(","))?),
+            },
+            file,
+            optional,
+        })
+    }
+
+    /// A device of the host, bind mounted into the /dev of the container.
+    fn device(host: &Path) -> Result<Self, ContainerError> {
+        Ok(Self {
+            source: cstring(host)?,
+            dirs: Vec::new(),
+            target: RootPath::new(&host.to_string_lossy())?,
+            fstype: None,
+            flags: libc::MS_BIND,
+            propagation: 0,
+            data: None,
+            file: true,
+            optional: false,
+        })
+    }
+
+    /// Creates the target of the mount below `root`, along with the
+    /// directories it is in, and opens it.
+    unsafe fn open_target(&self, root: libc::c_int) -> io::Result<libc::c_int> {
+        for dir in &self.dirs {
+            dir.mkdir(root)?;
+        }
+        if self.file {
+            self.target.touch(root)?;
+            self.target.open(root, libc::O_PATH)
+        } else {
+            self.target.mkdir(root)?;
+            self.target.open(root, DIRECTORY)
+        }
+    }
+
+    /// Mounts below `root`, which is the root filesystem of the container.
+    unsafe fn mount(&self, root: libc::c_int) -> io::Result<()> {
+        let target = self.open_target(root)?;
+        let data =
+            self.data.as_ref().map_or(ptr::null(), |d| d.as_ptr().cast());
+        let bind = self.flags & (libc::MS_BIND | libc::MS_REC);
+        let mounted = match bind {
+            0 => check(libc::mount(
+                self.source.as_ptr(),
+                FdPath::new(target).as_ptr(),
+                self.fstype.as_ref().map_or(ptr::null(), |t| t.as_ptr()),
+                self.flags,
+                data,
+            )),
+            _ => check(libc::mount(
+                self.source.as_ptr(),
+                FdPath::new(target).as_ptr(),
+                ptr::null(),
+                bind,
+                ptr::null(),
+            )),
+        };
+        let _ = libc::close(target);
+        mounted?;
+
+        // Flags other than the bind itself only apply on a remount. Both the
+        // remount and the propagation apply to the new mount, which only a
+        // fresh lookup of the target finds.
+        if bind != 0 && self.flags != bind {
+            self.remount(root, self.flags | libc::MS_REMOUNT)?;
+        }
+        if self.propagation != 0 {
+            self.remount(root, self.propagation)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn remount(
+        &self,
+        root: libc::c_int,
+        flags: libc::c_ulong,
+    ) -> io::Result<()> {
+        let target = self.target.open(root, libc::O_PATH)?;
+        let remounted = check(libc::mount(
+            ptr::null(),
+            FdPath::new(target).as_ptr(),
+            ptr::null(),
+            flags,
+            ptr::null(),
+        ));
+        let _ = libc::close(target);
+        remounted
+    }
+}
+
+/// Splits mount options into mount flags, propagation flags and the options
+/// passed on to the filesystem.
+fn mount_options(
+    options: &[String],
+) -> (libc::c_ulong, libc::c_ulong, Vec<&str>) {
+    let (mut flags, mut propagation, mut data) = (0, 0, Vec::new());
+    for option in options {
+        let (set, clear) = match option.as_str() {
+            "defaults" => (0, 0),
+            "ro" => (libc::MS_RDONLY, 0),
+            "rw" => (0, libc::MS_RDONLY),
+            "nosuid" => (libc::MS_NOSUID, 0),
+            "suid" => (0, libc::MS_NOSUID),
+            "nodev" => (libc::MS_NODEV, 0),
+            "dev" => (0, libc::MS_NODEV),
+            "noexec" => (libc::MS_NOEXEC, 0),
+            "exec" => (0, libc::MS_NOEXEC),
+            "sync" => (libc::MS_SYNCHRONOUS, 0),
+            "async" => (0, libc::MS_SYNCHRONOUS),
+            "noatime" => (libc::MS_NOATIME, 0),
+            "atime" => (0, libc::MS_NOATIME),
+            "nodiratime" => (libc::MS_NODIRATIME, 0),
+            "diratime" => (0, libc::MS_NODIRATIME),
+            "relatime" => (libc::MS_RELATIME, 0),
+            "norelatime" => (0, libc::MS_RELATIME),
+            "strictatime" => (libc::MS_STRICTATIME, 0),
+            "bind" => (libc::MS_BIND, 0),
+            "rbind" => (libc::MS_BIND | libc::MS_REC, 0),
+            "private" | "rprivate" | "shared" | "rshared" | "slave"
+            | "rslave" | "unbindable" | "runbindable" => {
+                propagation = match option.trim_start_matches('r') {
+                    "private" => libc::MS_PRIVATE,
+                    "shared" => libc::MS_SHARED,
+                    "slave" => libc::MS_SLAVE,
+                    _ => libc::MS_UNBINDABLE,
+                };
+                if option.starts_with('r') {
+                    propagation |= libc::MS_REC;
+                }
+                continue;
+            }
+            option => {
+                data.push(option);
+                continue;
+            }
+        };
+        flags = (flags | set) & !clear;
+    }
+    (flags, propagation, data)
+}
+
+/// ContainerRoot is the root filesystem of a container, prepared so that it
+/// can be set up in the child with async-signal-safe calls only.
+#[derive(Debug)]
+pub(crate) struct ContainerRoot {
+    rootfs: CString,
+    /// Overlay holds the options of the overlayfs mounted as the root
+    /// filesystem, if it is one.
+    overlay: Option<CString>,
+    readonly: bool,
+    mounts: Vec<PreparedMount>,
+    /// Links are symbolic links created once the mounts are in place, as
+    /// (target, link) pairs.
+    links: Vec<(CString, RootPath)>,
+    /// CgroupNamespace gives the container a cgroup namespace, rooted at the
+    /// cgroup of its process.
+    pub cgroup_namespace: bool,
+    masked_paths: Vec<CString>,
+    readonly_paths: Vec<CString>,
+    cwd: CString,
+    rlimits: Vec<(libc::__rlimit_resource_t, libc::rlimit)>,
+    no_new_privileges: bool,
+    capabilities: Capabilities,
+}
+
+impl ContainerRoot {
+    /// Mounts the root filesystem and switches the calling process into it.
+    /// Has to run in the namespaces of the container, after its mount
+    /// namespace has been made private.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called in a child process that has been forked to exec
+    /// the executable.
+    pub unsafe fn enter(&self) -> io::Result<()> {
+        // pivot_root needs the root filesystem to be a mount point.
+        check(match &self.overlay {
+            Some(overlay) => libc::mount(
+                c"overlay".as_ptr(),
+                self.rootfs.as_ptr(),
+                c"overlay".as_ptr(),
+                0,
+                overlay.as_ptr().cast(),
+            ),
+            None => libc::mount(
+                self.rootfs.as_ptr(),
+                self.rootfs.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                ptr::null(),
+            ),
+        })?;
+        let root =
+            libc::open(self.rootfs.as_ptr(), DIRECTORY | libc::O_CLOEXEC);
+        if root < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mounted = self.mount_all(root);
+        let _ = libc::close(root);
+        mounted?;
+
+        // The old root is stacked below the new one by pivot_root, and
+        // unmounted right away, so only the new root is left.
+        check(libc::chdir(self.rootfs.as_ptr()))?;
+        check(
+            libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr())
+                as libc::c_int,
+        )?;
+        check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+        check(libc::chdir(c"/".as_ptr()))?;
+
+        for path in &self.masked_paths {
+            let masked = match is_dir(path) {
+                Some(true) => libc::mount(
+                    c"tmpfs".as_ptr(),
+                    path.as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_RDONLY,
+                    ptr::null(),
+                ),
+                Some(false) => libc::mount(
+                    c"/dev/null".as_ptr(),
+                    path.as_ptr(),
+                    ptr::null(),
+                    libc::MS_BIND,
+                    ptr::null(),
+                ),
+                None => continue,
+            };
+            check(masked)?;
+        }
+        for path in &self.readonly_paths {
+            if is_dir(path).is_none() {
+                continue;
+            }
+            check(libc::mount(
+                path.as_ptr(),
+                path.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                ptr::null(),
+            ))?;
+            check(libc::mount(
+                ptr::null(),
+                path.as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                ptr::null(),
+            ))?;
+        }
+        if self.readonly {
+            check(libc::mount(
+                ptr::null(),
+                c"/".as_ptr(),
+                ptr::null(),
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                ptr::null(),
+            ))?;
+        }
+
+        for (resource, limit) in &self.rlimits {
+            check(libc::setrlimit(*resource, limit))?;
+        }
+        if self.no_new_privileges {
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+        }
+        self.capabilities.bound()?;
+        check(libc::chdir(self.cwd.as_ptr()))
+    }
+
+    /// Gives the calling process the capabilities of the container. Has to
+    /// run after enter, once the user of the container is set.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called in a child process that has been forked to exec
+    /// the executable.
+    pub unsafe fn set_capabilities(&self) -> io::Result<()> {
+        self.capabilities.set()
+    }
+
+    /// Mounts the mounts and creates the links of the container below
+    /// `root`.
+    unsafe fn mount_all(&self, root: libc::c_int) -> io::Result<()> {
+        for mount in &self.mounts {
+            match mount.mount(root) {
+                Err(_) if mount.optional => {}
+                result => result?,
+            }
+        }
+        for (target, link) in &self.links {
+            link.symlink(root, target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is a directory, or None if there is nothing at `path`.
+unsafe fn is_dir(path: &CString) -> Option<bool> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    if libc::stat(path.as_ptr(), stat.as_mut_ptr()) < 0 {
+        return None;
+    }
+    Some(stat.assume_init().st_mode & libc::S_IFMT == libc::S_IFDIR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image;
+    use crate::runtime::namespace::privileged;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_mount_options() {
+        let options: Vec<String> =
+            ["rbind", "ro", "nosuid", "rprivate", "mode=755"]
+                .iter()
+                .map(|o| o.to_string())
+                .collect();
+        let (flags, propagation, data) = mount_options(&options);
+        assert_eq!(
+            flags,
+            libc::MS_BIND | libc::MS_REC | libc::MS_RDONLY | libc::MS_NOSUID
+        );
+        assert_eq!(propagation, libc::MS_PRIVATE | libc::MS_REC);
+        assert_eq!(data, ["mode=755"]);
+    }
+
+    #[test]
+    fn test_resources() {
+        let limits = resources(&oci::Resources {
+            memory: Some(oci::Memory {
+                limit: Some(1 << 20),
+                reservation: Some(1 << 19),
+            }),
+            cpu: Some(oci::Cpu {
+                shares: Some(1024),
+                quota: Some(50000),
+                period: None,
+            }),
+            pids: Some(oci::Pids { limit: 16 }),
+        });
+        assert_eq!(limits.memory_max, 1 << 20);
+        assert_eq!(limits.memory_low, 1 << 19);
+        assert_eq!(limits.memory_high, 0);
+        assert_eq!(limits.cpu_weight, 39);
+        assert_eq!(limits.cpu_max, "50000 100000");
+        assert_eq!(limits.pids_max, 16);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let defaults = Capabilities::default();
+        assert_eq!(defaults.bounding.count_ones(), 14);
+        assert_eq!(defaults.ambient, 0);
+        let capabilities = Capabilities::new(&oci::Capabilities {
+            bounding: vec!["CAP_CHOWN".into(), "CAP_SYS_ADMIN".into()],
+            ambient: vec!["CAP_CHECKPOINT_RESTORE".into()],
+            ..Default::default()
+        })
+        .expect("capabilities");
+        assert_eq!(capabilities.bounding, 1 | 1 << 21);
+        assert_eq!(capabilities.ambient, 1 << 40);
+        assert!(Capabilities::new(&oci::Capabilities {
+            effective: vec!["CAP_EVERYTHING".into()],
+            ..Default::default()
+        })
+        .is_err());
+
+        let spec = |linux| Spec {
+            process: Some(oci::Process {
+                args: vec!["sh".into()],
+                ..Default::default()
+            }),
+            root: Some(oci::Root::default()),
+            linux: Some(oci::Linux {
+                namespaces: vec![oci::Namespace {
+                    kind: "mount".into(),
+                    path: None,
+                }],
+                ..linux
+            }),
+            ..Default::default()
+        };
+        let bundle = Path::new("/bundle");
+        let seccomp = oci::Linux {
+            seccomp: Some(json!({"defaultAction": "SCMP_ACT_ALLOW"})),
+            ..Default::default()
+        };
+        let devices = oci::Linux {
+            devices: vec![json!({"path": "/dev/fuse", "type": "c"})],
+            ..Default::default()
+        };
+        assert!(Bundle::from_spec("box", bundle, spec(seccomp)).is_err());
+        assert!(Bundle::from_spec("box", bundle, spec(devices)).is_err());
+        let bundle = Bundle::from_spec("box", bundle, spec(Default::default()))
+            .expect("bundle");
+        assert_eq!(bundle.root.capabilities, defaults);
+    }
+
+    #[test]
+    fn test_image_containers_do_not_write_to_image() {
+        if !privileged() {
+            eprintln!("skipping, mounting requires CAP_SYS_ADMIN");
+            return;
+        }
+        let dir = std::env::temp_dir()
+            .join(format!("auraed-image-root-{}", std::process::id()));
+        let store = ImageStore::new(dir.join("store"));
+        fs::create_dir_all(&dir).expect("dir");
+        let layer = image::layer(&[("srv/", None), ("srv/hello", Some("hi"))]);
+        fs::write(dir.join("image.tar"), image::archive(&layer, false))
+            .expect("write");
+        let _ = store.import(&dir.join("image.tar")).expect("import");
+        let bundle =
+            Bundle::from_image("box", &store, "app:1").expect("bundle");
+
+        // Sets the root up in a child, the way the runtime does before it
+        // execs the process of a container.
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe {
+                let entered = check(libc::unshare(libc::CLONE_NEWNS))
+                    .and_then(|_| {
+                        check(libc::mount(
+                            ptr::null(),
+                            c"/".as_ptr(),
+                            ptr::null(),
+                            libc::MS_REC | libc::MS_PRIVATE,
+                            ptr::null(),
+                        ))
+                    })
+                    .and_then(|_| bundle.root.enter());
+                libc::_exit(entered.is_err() as libc::c_int);
+            }
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+
+        let image = store.get("app:1").expect("image");
+        let (lower, _) = store.use_rootfs(&image).expect("rootfs");
+        let entries = |dir: &Path| {
+            let mut entries: Vec<String> = fs::read_dir(dir)
+                .expect("read dir")
+                .map(|e| e.expect("entry").file_name().to_string_lossy().into())
+                .collect();
+            entries.sort();
+            entries
+        };
+        let lower = entries(&lower);
+        let upper = entries(&dir.join("store/containers/box/upper"));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(status, 0);
+        assert_eq!(lower, ["srv"]);
+        assert!(upper.contains(&"dev".to_string()), "{:?}", upper);
+        assert!(upper.contains(&"proc".to_string()), "{:?}", upper);
+    }
+
+    #[test]
+    fn test_mount_targets_stay_in_root() {
+        let dir = std::env::temp_dir()
+            .join(format!("auraed-hostile-{}", std::process::id()));
+        let rootfs = dir.join("rootfs");
+        let host = dir.join("host");
+        fs::create_dir_all(&rootfs).expect("rootfs");
+        fs::create_dir_all(&host).expect("host");
+        // Links that point out of the root filesystem, absolute and relative.
+        std::os::unix::fs::symlink(&host, rootfs.join("dev")).expect("dev");
+        std::os::unix::fs::symlink("../../host", rootfs.join("run"))
+            .expect("run");
+
+        let pts = PreparedMount::new(
+            &rootfs,
+            &oci::Mount {
+                destination: "/dev/pts".to_string(),
+                kind: "devpts".to_string(),
+                source: "devpts".to_string(),
+                options: Vec::new(),
+            },
+        )
+        .expect("pts");
+        let null = PreparedMount::device(Path::new("/dev/null")).expect("null");
+        let lock = PreparedMount::new(
+            &rootfs,
+            &oci::Mount {
+                destination: "/run/lock".to_string(),
+                kind: "tmpfs".to_string(),
+                source: "tmpfs".to_string(),
+                options: Vec::new(),
+            },
+        )
+        .expect("lock");
+        let inside = rootfs.join(host.strip_prefix("/").expect("absolute"));
+        let root = cstring(&rootfs).expect("rootfs path");
+        let (missing, created) = unsafe {
+            let root = libc::open(root.as_ptr(), DIRECTORY | libc::O_CLOEXEC);
+            assert!(root >= 0);
+            // The links lead nowhere as long as their targets are missing in
+            // the root filesystem.
+            let missing = [&pts, &null, &lock]
+                .iter()
+                .all(|mount| mount.open_target(root).is_err());
+            fs::create_dir_all(&inside).expect("inside");
+            fs::create_dir_all(rootfs.join("host")).expect("inside");
+            let created = [&pts, &null, &lock].iter().all(|mount| {
+                mount.open_target(root).is_ok_and(|fd| libc::close(fd) == 0)
+            });
+            let _ = libc::close(root);
+            (missing, created)
+        };
+
+        let escaped = fs::read_dir(&host).expect("host").count();
+        let (pts, null) = (inside.join("pts"), inside.join("null"));
+        let (pts, null) = (pts.is_dir(), null.is_file());
+        let lock = rootfs.join("host/lock").is_dir();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(missing && created);
+        assert_eq!(escaped, 0);
+        assert!(pts && null && lock);
+    }
+
+    #[tokio::test]
+    async fn test_start_container_from_bundle() {
+        if !privileged() {
+            eprintln!("skipping, creating namespaces requires CAP_SYS_ADMIN");
+            return;
+        }
+        let bundle = std::env::temp_dir()
+            .join(format!("auraed-bundle-{}", std::process::id()));
+        let rootfs = bundle.join("rootfs");
+        fs::create_dir_all(rootfs.join("etc")).expect("rootfs");
+        fs::write(rootfs.join("etc/marker"), "inside\n").expect("marker");
+
+        let mut mounts = vec![
+            json!({"destination": "/proc", "type": "proc", "source": "proc"}),
+            json!({"destination": "/dev", "type": "tmpfs", "source": "tmpfs",
+                   "options": ["nosuid", "mode=755"]}),
+            json!({"destination": "/work", "type": "tmpfs", "source": "tmpfs"}),
+        ];
+        // The host provides the userland, in whatever layout it has.
+        for dir in ["usr", "bin", "sbin", "lib", "lib64"] {
+            let host = Path::new("/").join(dir);
+            match fs::read_link(&host) {
+                Ok(target) => {
+                    std::os::unix::fs::symlink(target, rootfs.join(dir))
+                        .expect("symlink")
+                }
+                Err(_) if host.is_dir() => mounts.push(json!({
+                    "destination": host, "type": "bind", "source": host,
+                    "options": ["rbind", "ro"]
+                })),
+                Err(_) => {}
+            }
+        }
+        let config = json!({
+            "ociVersion": "1.0.2",
+            "process": {
+                "args": ["sh", "-c",
+                         "echo $$; hostname; pwd; cat /etc/marker; test -c /dev/null && echo null; grep CapEff /proc/self/status"],
+                "env": ["PATH=/usr/bin:/bin"],
+                "cwd": "/work",
+                "user": {"uid": 0, "gid": 0},
+                "capabilities": {"bounding": ["CAP_KILL"], "effective": ["CAP_KILL"],
+                                 "permitted": ["CAP_KILL"]}
+            },
+            "root": {"path": "rootfs", "readonly": true},
+            "hostname": "box",
+            "mounts": mounts,
+            "linux": {"namespaces": [{"type": "pid"}, {"type": "mount"}, {"type": "uts"}]}
+        });
+        fs::write(bundle.join("config.json"), config.to_string())
+            .expect("config");
+
+        let containers = ProcessTable::default();
+        let container = Container {
+            name: "box".to_string(),
+            bundle: bundle.display().to_string(),
+            ..Default::default()
+        };
+        let started = start(&containers, &ImageStore::default(), &container)
+            .expect("start");
+        assert_eq!(started.status, meta::Status::Active as i32);
+        let finished = containers.wait("box").await.expect("wait");
+        let _ = fs::remove_dir_all(&bundle);
+
+        let stdout = String::from_utf8_lossy(&finished.stdout);
+        assert_eq!(finished.exit_code, 0, "{:?}", finished);
+        assert_eq!(
+            stdout,
+            "1\nbox\n/work\ninside\nnull\nCapEff:\t0000000000000020\n"
+        );
+    }
+}
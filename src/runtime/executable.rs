@@ -0,0 +1,254 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::cgroup::Cgroup;
+use crate::runtime::container::ContainerRoot;
+use crate::runtime::namespace::Namespaces;
+use crate::runtime::output::DEFAULT_MAX_OUTPUT_BYTES;
+use crate::runtime::{Executable, ExecutableUser};
+use crate::{command_from_string, CommandParseError};
+use anyhow::{anyhow, Context};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+
+/// ExecutableCommand is the command that runs an executable, along with the
+/// cgroup it runs in. The cgroup has to be kept until the executable exited.
+#[derive(Debug)]
+pub(crate) struct ExecutableCommand {
+    pub command: Command,
+    pub cgroup: Option<Cgroup>,
+}
+
+impl Executable {
+    /// The name the executable is tracked by, taken from its meta.
+    pub(crate) fn name(&self) -> &str {
+        self.meta.as_ref().map(|m| m.name.as_str()).unwrap_or_default()
+    }
+
+    /// The number of bytes of stdout and stderr kept for this executable.
+    pub(crate) fn max_output(&self) -> usize {
+        match self.max_output_bytes {
+            0 => DEFAULT_MAX_OUTPUT_BYTES,
+            bytes => usize::try_from(bytes).unwrap_or(usize::MAX),
+        }
+    }
+
+    /// How long this executable may run, if it is bounded at all.
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        match self.timeout_ms {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    /// Builds the command that runs this executable, with its environment,
+    /// working directory and identity applied, and creates the cgroup it
+    /// runs in.
+    pub(crate) fn to_command(&self) -> anyhow::Result<ExecutableCommand> {
+        self.to_command_in(None)
+    }
+
+    /// Builds the command like to_command, with `root` set up as the root
+    /// filesystem of the executable if one is given.
+    pub(crate) fn to_command_in(
+        &self,
+        root: Option<ContainerRoot>,
+    ) -> anyhow::Result<ExecutableCommand> {
+        let mut command = if !self.argv && self.args.is_empty() {
+            command_from_string(&self.command)?
+        } else if self.command.is_empty() {
+            return Err(anyhow!(CommandParseError::Empty));
+        } else {
+            let mut command = Command::new(&self.command);
+            let _ = command.args(&self.args);
+            command
+        };
+
+        if self.clear_env {
+            let _ = command.env_clear();
+        }
+        let _ = command.envs(&self.env);
+        if !self.cwd.is_empty() {
+            let _ = command.current_dir(&self.cwd);
+        }
+
+        let umask = match self.umask.as_str() {
+            "" => None,
+            umask => Some(parse_umask(umask)?),
+        };
+        let cgroup = Cgroup::create(self.name(), self.resources.as_ref())?;
+        let mut namespaces = match &self.namespaces {
+            Some(namespaces) => Namespaces::new(namespaces)?,
+            None => None,
+        };
+        if let (Some(namespaces), Some(root)) = (namespaces.as_mut(), &root) {
+            // The root filesystem brings its own mounts, /proc included.
+            namespaces.mount_proc = false;
+            if root.cgroup_namespace {
+                namespaces.add_cgroup();
+            }
+        }
+        let setup = ChildSetup {
+            user: self.user.clone(),
+            umask,
+            namespaces,
+            root,
+            cgroup_procs: cgroup.as_ref().map(Cgroup::procs).transpose()?,
+        };
+        unsafe {
+            let _ = command.pre_exec(move || setup.apply());
+        }
+        Ok(ExecutableCommand { command, cgroup })
+    }
+}
+
+/// ChildSetup is applied in the child process, after it has been forked and
+/// before the executable is exec'ed. Only async-signal-safe calls are allowed
+/// in there, so everything it needs is prepared upfront.
+struct ChildSetup {
+    user: Option<ExecutableUser>,
+    umask: Option<libc::mode_t>,
+    namespaces: Option<Namespaces>,
+    root: Option<ContainerRoot>,
+    /// CgroupProcs is the cgroup.procs file of the cgroup of the executable.
+    cgroup_procs: Option<File>,
+}
+
+impl ChildSetup {
+    fn apply(&self) -> io::Result<()> {
+        // The child moves into its cgroup before anything else, so that it
+        // is limited from the start and still privileged to do so.
+        if let Some(procs) = &self.cgroup_procs {
+            let pid = b"0";
+            let ret = unsafe {
+                libc::write(procs.as_raw_fd(), pid.as_ptr().cast(), pid.len())
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(umask) = self.umask {
+            let _ = unsafe { libc::umask(umask) };
+        }
+        // Namespaces are entered while we are still privileged, and before
+        // the user is set, whose ids then belong to a new user namespace.
+        if let Some(namespaces) = &self.namespaces {
+            unsafe { namespaces.enter()? };
+        }
+        if let Some(root) = &self.root {
+            unsafe { root.enter()? };
+        }
+        // Groups have to be set while we are still privileged, and the group
+        // before the user for the same reason.
+        if let Some(user) = &self.user {
+            check(unsafe {
+                libc::setgroups(user.groups.len(), user.groups.as_ptr())
+            })?;
+            check(unsafe { libc::setgid(user.gid) })?;
+            check(unsafe { libc::setuid(user.uid) })?;
+        }
+        if let Some(root) = &self.root {
+            unsafe { root.set_capabilities()? };
+        }
+        Ok(())
+    }
+}
+
+/// Turns the return value of a libc call into the errno it set, if any.
+pub(crate) fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_umask(umask: &str) -> anyhow::Result<libc::mode_t> {
+    let parsed = libc::mode_t::from_str_radix(umask, 8)
+        .with_context(|| format!("invalid umask {}", umask))?;
+    if parsed > 0o777 {
+        return Err(anyhow!("invalid umask {}", umask));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_to_command_applies_env_cwd_and_umask() {
+        let executable = Executable {
+            command: "sh".to_string(),
+            args: vec!["-c".into(), "echo $GREETING $HOME; pwd; umask".into()],
+            env: HashMap::from([("GREETING".into(), "hi".into())]),
+            clear_env: true,
+            cwd: "/tmp".to_string(),
+            umask: "027".to_string(),
+            ..Default::default()
+        };
+        let output = executable.to_command().expect("command").command.output();
+        let output = output.expect("output");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hi\n/tmp\n0027\n");
+    }
+
+    #[test]
+    fn test_to_command_argv_runs_command_as_is() {
+        let dir = std::env::temp_dir()
+            .join(format!("aurae argv {}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let program = dir.join("say hi");
+        let _ = std::fs::remove_file(&program);
+        std::os::unix::fs::symlink("/bin/echo", &program).expect("symlink");
+
+        let executable = Executable {
+            command: program.to_string_lossy().into_owned(),
+            argv: true,
+            ..Default::default()
+        };
+        let output = executable.to_command().expect("command").command.output();
+        let _ = std::fs::remove_dir_all(&dir);
+        let output = output.expect("output");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"\n");
+    }
+
+    #[test]
+    fn test_parse_umask() {
+        assert_eq!(parse_umask("022").ok(), Some(0o022));
+        assert!(parse_umask("0999").is_err());
+        assert!(parse_umask("1777").is_err());
+    }
+}
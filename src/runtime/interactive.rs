@@ -0,0 +1,274 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::observe::events::Events;
+use crate::runtime::cgroup::Cgroup;
+use crate::runtime::deadline::earliest;
+use crate::runtime::exec_interactive_request::Request as Input;
+use crate::runtime::executable::ExecutableCommand;
+use crate::runtime::output::{
+    finish_stream, forward_output, spawn_error_frame, unix_timestamp,
+    FrameSender,
+};
+use crate::runtime::process::kill_group;
+use crate::runtime::pty::{self, Pty};
+use crate::runtime::{
+    meta, ExecInteractiveRequest, ExecInteractiveStart, OutputChannel,
+    EXEC_STREAM_BUFFER,
+};
+use log::warn;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Stdio};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::{Status, Streaming};
+
+/// The end-of-file character of a terminal in its default mode (^D).
+const TERMINAL_EOF: u8 = 0x04;
+
+/// Number of stdin requests buffered before the call stops reading them.
+const STDIN_BUFFER: usize = 64;
+
+/// Session is an executable started for ExecInteractive, with the ends of
+/// its stdio kept by auraed.
+struct Session {
+    child: Child,
+    cgroup: Option<Cgroup>,
+    stdin: Box<dyn Write + Send>,
+    outputs: Vec<(Box<dyn Read + Send>, OutputChannel)>,
+    /// Terminal is the master side of the terminal of the executable, if it
+    /// runs on one.
+    terminal: Option<File>,
+}
+
+impl Session {
+    fn spawn(start: &ExecInteractiveStart) -> anyhow::Result<Self> {
+        let executable = start.executable.clone().unwrap_or_default();
+        let ExecutableCommand { command: mut cmd, cgroup } =
+            executable.to_command()?;
+        if !start.tty {
+            let mut child = cmd
+                .process_group(0)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let mut outputs: Vec<(Box<dyn Read + Send>, OutputChannel)> =
+                Vec::new();
+            if let Some(stdout) = child.stdout.take() {
+                outputs.push((Box::new(stdout), OutputChannel::Stdout));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                outputs.push((Box::new(stderr), OutputChannel::Stderr));
+            }
+            let stdin = child
+                .stdin
+                .take()
+                .ok_or_else(|| anyhow::anyhow!("stdin of child not piped"))?;
+            return Ok(Self {
+                child,
+                cgroup,
+                stdin: Box::new(stdin),
+                outputs,
+                terminal: None,
+            });
+        }
+
+        let pty = Pty::open(start.window_size.as_ref())?;
+        pty.attach(&mut cmd)?;
+        let child = cmd.spawn()?;
+        // Our copies of the slave side go away with the command and the pty,
+        // so the terminal hangs up once the executable is gone.
+        drop(cmd);
+        let master = pty.master;
+        Ok(Self {
+            child,
+            cgroup,
+            stdin: Box::new(master.try_clone()?),
+            outputs: vec![(
+                Box::new(master.try_clone()?),
+                OutputChannel::Stdout,
+            )],
+            terminal: Some(master),
+        })
+    }
+}
+
+/// Runs the executable the first request starts, and streams its output
+/// into `tx` while the rest of the requests are forwarded to it.
+pub(crate) async fn exec_interactive(
+    mut requests: Streaming<ExecInteractiveRequest>,
+    timeout: Option<Duration>,
+    tx: FrameSender,
+    events: Events,
+) -> Result<(), Status> {
+    let start = match requests.message().await?.and_then(|r| r.request) {
+        Some(Input::Start(start)) => start,
+        _ => {
+            return Err(Status::invalid_argument(
+                "the first request must start the executable",
+            ))
+        }
+    };
+    let executable = start.executable.clone().unwrap_or_default();
+    let timeout = earliest(timeout, executable.timeout());
+    let name = executable.command.clone();
+
+    let mut session = match Session::spawn(&start) {
+        Ok(session) => session,
+        Err(e) => {
+            let _ = tx.send(Ok(spawn_error_frame(e))).await;
+            return Ok(());
+        }
+    };
+    let pid = session.child.id() as i32;
+    let proc = meta::ProcessMeta { pid, start_time: unix_timestamp() };
+
+    let (output_tx, output) = mpsc::channel(EXEC_STREAM_BUFFER);
+    for (reader, channel) in session.outputs.drain(..) {
+        let (tx, name) = (output_tx.clone(), name.clone());
+        drop(tokio::task::spawn_blocking(move || {
+            forward_output(reader, channel, name, tx)
+        }));
+    }
+    drop(output_tx);
+
+    let (stdin_tx, mut stdin_rx) = mpsc::channel::<Vec<u8>>(STDIN_BUFFER);
+    let mut stdin = session.stdin;
+    drop(tokio::task::spawn_blocking(move || {
+        while let Some(data) = stdin_rx.blocking_recv() {
+            if stdin.write_all(&data).and_then(|_| stdin.flush()).is_err() {
+                return;
+            }
+        }
+    }));
+
+    let terminal = session.terminal;
+    let input = tokio::spawn(async move {
+        let close_stdin = |stdin_tx: &mut Option<mpsc::Sender<Vec<u8>>>| {
+            // A terminal stays open for the output, the end of input is
+            // signalled in band instead.
+            match (&terminal, stdin_tx.as_ref()) {
+                (Some(_), Some(stdin_tx)) => {
+                    let _ = stdin_tx.try_send(vec![TERMINAL_EOF]);
+                }
+                _ => *stdin_tx = None,
+            }
+        };
+        let mut stdin_tx = Some(stdin_tx);
+        loop {
+            match requests.message().await {
+                Ok(Some(request)) => match request.request {
+                    Some(Input::Stdin(data)) => {
+                        if let Some(stdin_tx) = &stdin_tx {
+                            let _ = stdin_tx.send(data).await;
+                        }
+                    }
+                    Some(Input::Resize(size)) => {
+                        if let Some(terminal) = &terminal {
+                            if let Err(e) = pty::resize(terminal, &size) {
+                                warn!("Failed to resize terminal: {}", e);
+                            }
+                        }
+                    }
+                    Some(Input::CloseStdin(true)) => close_stdin(&mut stdin_tx),
+                    Some(Input::Start(_)) => {
+                        warn!(
+                            "Ignoring start of an executable that already runs"
+                        )
+                    }
+                    Some(Input::CloseStdin(false)) | None => {}
+                },
+                Ok(None) => {
+                    close_stdin(&mut stdin_tx);
+                    return;
+                }
+                Err(e) => {
+                    warn!(
+                        "Interactive call for process {} dropped: {}",
+                        pid, e
+                    );
+                    let _ = kill_group(pid, libc::SIGHUP);
+                    return;
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let exited = finish_stream(
+            session.child,
+            session.cgroup,
+            proc,
+            output,
+            timeout,
+            name.clone(),
+            tx,
+        )
+        .await;
+        input.abort();
+        if let Some((exited, oom_kills)) = exited {
+            events.publish_exit(&name, (&exited).into(), oom_kills);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Executable, WindowSize};
+
+    #[test]
+    fn test_session_on_terminal() {
+        let start = ExecInteractiveStart {
+            executable: Some(Executable {
+                command: "sh".to_string(),
+                args: vec!["-c".into(), "tty; stty size; read line".into()],
+                ..Default::default()
+            }),
+            tty: true,
+            window_size: Some(WindowSize { rows: 30, columns: 100 }),
+        };
+        let mut session = Session::spawn(&start).expect("spawn");
+        session.stdin.write_all(b"done\n").expect("write stdin");
+
+        let (mut terminal, _) = session.outputs.remove(0);
+        let mut output = Vec::new();
+        // Reading stops with EIO once the terminal hangs up.
+        let _ = terminal.read_to_end(&mut output);
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("/dev/pts/"), "{}", output);
+        assert!(output.contains("30 100"), "{}", output);
+        assert!(session.child.wait().expect("wait").success());
+    }
+}
@@ -0,0 +1,646 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+//! Log files of the output of supervised executables.
+//!
+//! Every executable writes to a log file of its own below the root of the
+//! store, one record per line of output:
+//!
+//! ```text
+//! <sequence> <timestamp> <out|err> <line>
+//! ```
+//!
+//! A log file that grows past its size limit is rotated to `.1`, `.2` and so
+//! on, and the oldest rotated file is removed once there are too many.
+
+use crate::runtime::{unix_timestamp, OutputChannel};
+use log::warn;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tonic::Status;
+
+/// Size a log file is rotated at.
+const DEFAULT_MAX_FILE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Number of files kept per executable, the current one included.
+const DEFAULT_MAX_FILES: usize = 4;
+
+/// Lines longer than this are split into several records.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// Records buffered for followers of a log before the slowest one misses
+/// records.
+const FOLLOW_BUFFER: usize = 1024;
+
+/// Bytes read at a time when a log file is read from its end.
+const REVERSE_CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum LogError {
+    #[error("no logs of executable {name}")]
+    NotFound { name: String },
+    #[error("failed to read logs {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl From<LogError> for Status {
+    fn from(e: LogError) -> Self {
+        match e {
+            LogError::NotFound { .. } => Status::not_found(e.to_string()),
+            LogError::Io { .. } => Status::internal(e.to_string()),
+        }
+    }
+}
+
+/// A line of output of an executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LogRecord {
+    /// Sequence numbers of the records of an executable increase by one,
+    /// across runs and restarts of auraed.
+    pub sequence: u64,
+    /// Timestamp is when the line was read, in nanoseconds since the Unix
+    /// epoch.
+    pub timestamp: i64,
+    pub channel: OutputChannel,
+    pub line: String,
+}
+
+impl LogRecord {
+    fn encode(&self) -> String {
+        let channel = match self.channel {
+            OutputChannel::Stderr => "err",
+            _ => "out",
+        };
+        format!(
+            "{} {} {} {}\n",
+            self.sequence, self.timestamp, channel, self.line
+        )
+    }
+
+    fn decode(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, ' ');
+        let sequence = fields.next()?.parse().ok()?;
+        let timestamp = fields.next()?.parse().ok()?;
+        let channel = match fields.next()? {
+            "err" => OutputChannel::Stderr,
+            _ => OutputChannel::Stdout,
+        };
+        let line = fields.next().unwrap_or_default().to_string();
+        Some(Self { sequence, timestamp, channel, line })
+    }
+}
+
+/// Which records of a log to read.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LogQuery {
+    /// Tail is the number of most recent records to read, or 0 for all.
+    pub tail: usize,
+    /// Since is the time of the oldest record to read, in nanoseconds since
+    /// the Unix epoch.
+    pub since: i64,
+}
+
+/// The logs that are written to or followed, by the name of their
+/// executable.
+type Logs = Mutex<HashMap<String, Weak<Log>>>;
+
+/// LogStore keeps the logs of executables below its root directory.
+#[derive(Debug, Clone)]
+pub(crate) struct LogStore {
+    root: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    logs: Arc<Logs>,
+}
+
+/// The log of a single executable, shared by all of its runs and followers.
+/// It is evicted from its store once the last of them is dropped.
+#[derive(Debug)]
+struct Log {
+    name: String,
+    path: PathBuf,
+    file: Mutex<LogFile>,
+    followers: broadcast::Sender<LogRecord>,
+    logs: Weak<Logs>,
+}
+
+#[derive(Debug, Default)]
+struct LogFile {
+    file: Option<File>,
+    size: u64,
+    sequence: u64,
+}
+
+impl LogStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self::with_limits(root, DEFAULT_MAX_FILE_BYTES, DEFAULT_MAX_FILES)
+    }
+
+    /// A store that rotates log files at `max_file_bytes`, and keeps
+    /// `max_files` files of every executable.
+    pub fn with_limits(
+        root: PathBuf,
+        max_file_bytes: u64,
+        max_files: usize,
+    ) -> Self {
+        Self {
+            root,
+            max_file_bytes,
+            max_files: max_files.max(1),
+            logs: Arc::default(),
+        }
+    }
+
+    /// A writer that appends the output of a run of the executable `name`
+    /// to its log.
+    pub fn writer(&self, name: &str) -> LogWriter {
+        LogWriter { store: self.clone(), log: self.log(name) }
+    }
+
+    /// Reads the records of the executable `name` that match `query`, oldest
+    /// first.
+    pub fn read(
+        &self,
+        name: &str,
+        query: LogQuery,
+    ) -> Result<Vec<LogRecord>, LogError> {
+        let files = self.files(&self.path(name));
+        if files.iter().all(|file| !file.exists()) {
+            return Err(LogError::NotFound { name: name.to_string() });
+        }
+        let matches = |record: &LogRecord| record.timestamp >= query.since;
+        let mut records = Vec::new();
+        if query.tail == 0 {
+            for file in files.iter().rev() {
+                let content = match fs::read(file) {
+                    Ok(content) => content,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(io_error(file, e)),
+                };
+                records.extend(
+                    String::from_utf8_lossy(&content)
+                        .lines()
+                        .filter_map(LogRecord::decode)
+                        .filter(matches),
+                );
+            }
+            return Ok(records);
+        }
+        // The tail is read from the end of the newest file on, so that only
+        // as much of the log is read as is returned.
+        for file in &files {
+            let lines = match ReverseLines::open(file) {
+                Ok(lines) => lines,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(io_error(file, e)),
+            };
+            for line in lines {
+                let line = line.map_err(|e| io_error(file, e))?;
+                match LogRecord::decode(&line) {
+                    Some(record) if matches(&record) => records.push(record),
+                    _ => continue,
+                }
+                if records.len() == query.tail {
+                    records.reverse();
+                    return Ok(records);
+                }
+            }
+        }
+        records.reverse();
+        Ok(records)
+    }
+
+    /// Subscribes to the records written to the log of `name` from now on.
+    pub fn follow(&self, name: &str) -> LogFollower {
+        let log = self.log(name);
+        LogFollower { records: log.followers.subscribe(), _log: log }
+    }
+
+    /// Removes the log files of the executable `name`.
+    pub fn remove(&self, name: &str) -> Result<(), LogError> {
+        let path = self.path(name);
+        let log = self.lock().remove(name).and_then(|log| log.upgrade());
+        // Held so that no run writes to the log while it is removed.
+        let _file = log.as_ref().map(|log| log.lock());
+        for file in self.files(&path) {
+            match fs::remove_file(&file) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(LogError::Io { path: file, source: e }),
+            }
+        }
+        Ok(())
+    }
+
+    fn log(&self, name: &str) -> Arc<Log> {
+        let mut logs = self.lock();
+        if let Some(log) = logs.get(name).and_then(Weak::upgrade) {
+            return log;
+        }
+        let log = Arc::new(Log {
+            name: name.to_string(),
+            path: self.path(name),
+            file: Mutex::default(),
+            followers: broadcast::channel(FOLLOW_BUFFER).0,
+            logs: Arc::downgrade(&self.logs),
+        });
+        let _ = logs.insert(name.to_string(), Arc::downgrade(&log));
+        log
+    }
+
+    /// The path of the current log file of the executable `name`. Names are
+    /// percent encoded, so that any name makes a file name of its own.
+    fn path(&self, name: &str) -> PathBuf {
+        let mut file = String::with_capacity(name.len() + 4);
+        for byte in name.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => {
+                    file.push(byte as char)
+                }
+                _ => file.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        file.push_str(".log");
+        self.root.join(file)
+    }
+
+    /// The files of the log at `path`, the current one first.
+    fn files(&self, path: &Path) -> Vec<PathBuf> {
+        let mut files = vec![path.to_path_buf()];
+        files.extend((1..self.max_files).map(|i| rotated(path, i)));
+        files
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Weak<Log>>> {
+        lock_logs(&self.logs)
+    }
+}
+
+fn lock_logs(logs: &Logs) -> MutexGuard<'_, HashMap<String, Weak<Log>>> {
+    logs.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn rotated(path: &Path, i: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", i));
+    PathBuf::from(rotated)
+}
+
+fn io_error(path: &Path, source: io::Error) -> LogError {
+    LogError::Io { path: path.to_path_buf(), source }
+}
+
+impl Log {
+    fn lock(&self) -> MutexGuard<'_, LogFile> {
+        self.file.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        let logs = match self.logs.upgrade() {
+            Some(logs) => logs,
+            None => return,
+        };
+        let mut logs = lock_logs(&logs);
+        // A writer or follower may have opened the log anew in the meantime.
+        if logs.get(&self.name).is_some_and(|log| log.strong_count() == 0) {
+            let _ = logs.remove(&self.name);
+        }
+    }
+}
+
+/// LogFollower receives the records written to the log of an executable.
+/// It keeps the log open for as long as it is followed.
+#[derive(Debug)]
+pub(crate) struct LogFollower {
+    records: broadcast::Receiver<LogRecord>,
+    _log: Arc<Log>,
+}
+
+impl LogFollower {
+    pub async fn recv(&mut self) -> Result<LogRecord, RecvError> {
+        self.records.recv().await
+    }
+}
+
+/// LogWriter appends lines of output to the log of an executable.
+#[derive(Debug, Clone)]
+pub(crate) struct LogWriter {
+    store: LogStore,
+    log: Arc<Log>,
+}
+
+impl LogWriter {
+    /// Wraps `reader`, a pipe of the executable, so that everything read
+    /// from it is logged as `channel`.
+    pub fn tee<R: Read>(&self, reader: R, channel: OutputChannel) -> Tee<R> {
+        Tee { reader, writer: self.clone(), channel, partial: Vec::new() }
+    }
+
+    fn write(&self, channel: OutputChannel, line: &[u8]) {
+        let mut file = self.log.lock();
+        let record = LogRecord {
+            sequence: 0,
+            timestamp: unix_timestamp(),
+            channel,
+            line: String::from_utf8_lossy(line).into_owned(),
+        };
+        let record = match self.append(&mut file, record) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Failed to write log {}: {}", self.log.path.display(), e);
+                return;
+            }
+        };
+        let _ = self.log.followers.send(record);
+    }
+
+    /// Appends `record` with the next sequence number, and returns it.
+    fn append(
+        &self,
+        file: &mut LogFile,
+        mut record: LogRecord,
+    ) -> io::Result<LogRecord> {
+        if file.file.is_none() {
+            self.open(file)?;
+        }
+        record.sequence = file.sequence;
+        let encoded = record.encode();
+        if file.size > 0
+            && file.size + encoded.len() as u64 > self.store.max_file_bytes
+        {
+            self.rotate(file)?;
+        }
+        if let Some(f) = file.file.as_mut() {
+            f.write_all(encoded.as_bytes())?;
+            file.size += encoded.len() as u64;
+        }
+        file.sequence += 1;
+        Ok(record)
+    }
+
+    /// Opens the current log file, and continues the sequence of the
+    /// records already logged.
+    fn open(&self, file: &mut LogFile) -> io::Result<()> {
+        let path = &self.log.path;
+        fs::create_dir_all(&self.store.root)?;
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+        file.size = f.metadata()?.len();
+        file.sequence = self
+            .store
+            .files(path)
+            .iter()
+            .find_map(|path| last_sequence(path))
+            .map(|last| last + 1)
+            .unwrap_or_default()
+            .max(file.sequence);
+        file.file = Some(f);
+        Ok(())
+    }
+
+    fn rotate(&self, file: &mut LogFile) -> io::Result<()> {
+        let path = &self.log.path;
+        file.file = None;
+        let files = self.store.files(path);
+        // The oldest file makes room, the others move up by one.
+        for i in (1..files.len()).rev() {
+            let (from, to) = (&files[i - 1], &files[i]);
+            match fs::rename(from, to) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if files.len() == 1 {
+            let _ = fs::remove_file(path);
+        }
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+        file.size = 0;
+        file.file = Some(f);
+        Ok(())
+    }
+}
+
+/// The sequence number of the last record of the log file at `path`.
+fn last_sequence(path: &Path) -> Option<u64> {
+    ReverseLines::open(path)
+        .ok()?
+        .map_while(Result::ok)
+        .find_map(|line| LogRecord::decode(&line))
+        .map(|record| record.sequence)
+}
+
+/// ReverseLines reads the lines of a file from its end, a chunk at a time.
+struct ReverseLines {
+    file: File,
+    /// Offset is where the part of the file that has not been read ends.
+    offset: u64,
+    /// Read is what was read but not returned yet, which starts with the
+    /// end of a line the rest of which is not read yet.
+    read: Vec<u8>,
+    done: bool,
+}
+
+impl ReverseLines {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut offset = file.metadata()?.len();
+        // The newline that ends the last line does not start another one.
+        let mut last = [0u8];
+        if offset > 0 {
+            file.read_exact_at(&mut last, offset - 1)?;
+            if last[0] == b'\n' {
+                offset -= 1;
+            }
+        }
+        Ok(Self { file, offset, read: Vec::new(), done: false })
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+            if let Some(newline) = self.read.iter().rposition(|&b| b == b'\n') {
+                let line = self.read.split_off(newline + 1);
+                self.read.truncate(newline);
+                return Some(Ok(String::from_utf8_lossy(&line).into_owned()));
+            }
+            if self.offset == 0 {
+                self.done = true;
+                let line = std::mem::take(&mut self.read);
+                return Some(Ok(String::from_utf8_lossy(&line).into_owned()));
+            }
+            let len = self.offset.min(REVERSE_CHUNK_BYTES as u64);
+            self.offset -= len;
+            let mut chunk = vec![0; len as usize];
+            if let Err(e) = self.file.read_exact_at(&mut chunk, self.offset) {
+                self.done = true;
+                return Some(Err(e));
+            }
+            chunk.append(&mut self.read);
+            self.read = chunk;
+        }
+    }
+}
+
+/// Tee is a pipe of an executable that logs what is read from it, line by
+/// line. A line that is not terminated is logged once the pipe is dropped.
+pub(crate) struct Tee<R> {
+    reader: R,
+    writer: LogWriter,
+    channel: OutputChannel,
+    partial: Vec<u8>,
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        for &byte in &buf[..n] {
+            if byte == b'\n' {
+                self.writer.write(self.channel, &self.partial);
+                self.partial.clear();
+                continue;
+            }
+            self.partial.push(byte);
+            if self.partial.len() >= MAX_LINE_BYTES {
+                self.writer.write(self.channel, &self.partial);
+                self.partial.clear();
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R> Drop for Tee<R> {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            self.writer.write(self.channel, &self.partial);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// A store in a directory of its own below the temporary directory.
+    pub(crate) fn logs() -> LogStore {
+        static STORES: AtomicU64 = AtomicU64::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "auraed-logs-{}-{}",
+            std::process::id(),
+            STORES.fetch_add(1, Ordering::Relaxed)
+        ));
+        LogStore::new(root)
+    }
+
+    fn log(writer: &LogWriter, channel: OutputChannel, output: &[u8]) {
+        let mut tee = writer.tee(output, channel);
+        let _ = io::copy(&mut tee, &mut io::sink()).expect("copy");
+    }
+
+    #[test]
+    fn test_write_read_and_follow() {
+        let logs = logs();
+        let mut followed = logs.follow("web 1");
+        let writer = logs.writer("web 1");
+        log(&writer, OutputChannel::Stdout, b"one\ntwo\nthree");
+        log(&writer, OutputChannel::Stderr, b"oops\n");
+
+        let records = logs.read("web 1", LogQuery::default()).expect("read");
+        let lines: Vec<&str> =
+            records.iter().map(|r| r.line.as_str()).collect();
+        assert_eq!(lines, ["one", "two", "three", "oops"]);
+        assert_eq!(records[3].channel, OutputChannel::Stderr);
+        assert_eq!(records[3].sequence, 3);
+        assert_eq!(followed.records.try_recv().expect("followed").line, "one");
+
+        let tail =
+            logs.read("web 1", LogQuery { tail: 2, since: 0 }).expect("tail");
+        assert_eq!(tail[0].line, "three");
+        let since = LogQuery { tail: 0, since: records[3].timestamp };
+        assert!(!logs.read("web 1", since).expect("since").is_empty());
+
+        // Sequence numbers continue where the log left off.
+        let reopened = LogStore::new(logs.root.clone());
+        log(&reopened.writer("web 1"), OutputChannel::Stdout, b"four\n");
+        let records =
+            reopened.read("web 1", LogQuery::default()).expect("read");
+        assert_eq!(records.last().expect("last").sequence, 4);
+
+        // The log is evicted once nothing writes to or follows it.
+        drop((writer, followed));
+        assert!(logs.lock().is_empty());
+
+        logs.remove("web 1").expect("remove");
+        assert!(matches!(
+            logs.read("web 1", LogQuery::default()),
+            Err(LogError::NotFound { .. })
+        ));
+        let _ = fs::remove_dir_all(&logs.root);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let root = logs().root;
+        let logs = LogStore::with_limits(root, 64, 3);
+        let writer = logs.writer("rotated");
+        for i in 0..20 {
+            log(
+                &writer,
+                OutputChannel::Stdout,
+                format!("line {}\n", i).as_bytes(),
+            );
+        }
+        let path = logs.path("rotated");
+        assert!(rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+        let records = logs.read("rotated", LogQuery::default()).expect("read");
+        assert_eq!(records.last().expect("last").line, "line 19");
+        assert!(records.len() < 20);
+        assert!(records.windows(2).all(|w| w[1].sequence == w[0].sequence + 1));
+        // A tail spanning several files reads them from their end.
+        let tail =
+            logs.read("rotated", LogQuery { tail: 2, since: 0 }).expect("tail");
+        assert_eq!(tail, records[records.len() - 2..]);
+        let _ = fs::remove_dir_all(&logs.root);
+    }
+}
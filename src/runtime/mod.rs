@@ -34,117 +34,256 @@
 #![allow(dead_code)]
 tonic::include_proto!("runtime");
 
+use crate::image::ImageStore;
+use crate::meta;
+use crate::observe::events::Events;
+use crate::runtime::deadline::{earliest, grpc_timeout};
+use crate::runtime::output::{
+    finish_stream, forward_output, spawn_error_frame,
+};
+use crate::runtime::process::{error_status, parse_signal};
 use crate::runtime::runtime_server::Runtime;
-use crate::{command_from_string, meta};
-use tonic::{Request, Response, Status};
+use log::warn;
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+
+mod cgroup;
+mod container;
+mod deadline;
+mod executable;
+mod interactive;
+pub(crate) mod logs;
+mod namespace;
+mod oci;
+mod output;
+mod process;
+mod pty;
+mod usage;
+
+pub(crate) use cgroup::available as cgroups_available;
+pub(crate) use logs::{LogError, LogQuery, LogRecord, LogStore};
+#[cfg(test)]
+pub(crate) use namespace::privileged;
+pub(crate) use namespace::ProcessNamespaces;
+pub(crate) use output::unix_timestamp;
+pub(crate) use process::{Process, ProcessError, ProcessTable};
+
+/// Number of output frames buffered per stream before the readers of a
+/// child process block on a slow client.
+const EXEC_STREAM_BUFFER: usize = 64;
+
+/// Time a stopped executable is given to exit before it is killed.
+pub(crate) const DEFAULT_STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Prepares the host to run executables on. Executables can be started
+/// without it, but run in the cgroup of auraed then.
+pub(crate) fn init() {
+    if let Err(e) = cgroup::init() {
+        warn!("Failed to set up cgroups for executables: {}", e);
+    }
+}
 
 #[derive(Debug, Default, Clone)]
-pub struct RuntimeService {}
+pub struct RuntimeService {
+    processes: ProcessTable,
+    containers: ProcessTable,
+    images: ImageStore,
+    events: Events,
+}
+
+impl RuntimeService {
+    pub(crate) fn new(images: ImageStore, events: Events) -> Self {
+        Self {
+            processes: ProcessTable::new(events.clone()),
+            containers: ProcessTable::new(events.clone()),
+            images,
+            events,
+        }
+    }
+}
 
 #[tonic::async_trait]
 impl Runtime for RuntimeService {
+    type ExecStreamStream = ReceiverStream<Result<ExecStreamResponse, Status>>;
+    type ExecInteractiveStream =
+        ReceiverStream<Result<ExecStreamResponse, Status>>;
+
     async fn exec(
         &self,
         request: Request<Executable>,
     ) -> Result<Response<ExecutableStatus>, Status> {
+        let timeout = grpc_timeout(request.metadata());
         let r = request.into_inner();
-        let cmd = command_from_string(&r.command);
-        match cmd {
-            Ok(mut cmd) => {
-                let output = cmd.output();
-                match output {
-                    Ok(output) => {
-                        let meta = meta::AuraeMeta {
-                            name: r.command,
-                            message: "-".to_string(),
-                        };
-                        let proc = meta::ProcessMeta { pid: -1 }; // todo @kris-nova get pid, we will probably want to spawn() and wait and remember the pid
-                        let status = meta::Status::Complete as i32;
-                        let response = ExecutableStatus {
-                            meta: Some(meta),
-                            proc: Some(proc),
-                            status,
-                            stdout: String::from_utf8(output.stdout).unwrap(),
-                            stderr: String::from_utf8(output.stderr).unwrap(),
-                            exit_code: output.status.to_string(),
-                        };
-                        Ok(Response::new(response))
-                    }
-                    Err(e) => {
-                        let meta = meta::AuraeMeta {
-                            name: "-".to_string(),
-                            message: format!("{:?}", e),
-                        };
-                        let proc = meta::ProcessMeta { pid: -1 };
-                        let status = meta::Status::Error as i32;
-                        let response = ExecutableStatus {
-                            meta: Some(meta),
-                            proc: Some(proc),
-                            status,
-                            stdout: "-".to_string(),
-                            stderr: "-".to_string(),
-                            exit_code: "-".to_string(),
-                        };
-                        Ok(Response::new(response))
-                    }
-                }
+        let timeout = earliest(timeout, r.timeout());
+        let process = r.to_command().and_then(|cmd| {
+            Process::spawn(cmd, r.max_output(), timeout)
+                .map_err(anyhow::Error::from)
+        });
+        match process {
+            Ok(process) => {
+                process.publish_events(&r.command, &self.events);
+                let _ = process.wait().await;
+                Ok(Response::new(process.status(&r.command)))
             }
+            Err(e) => Ok(Response::new(error_status(format!("{:?}", e)))),
+        }
+    }
+
+    async fn exec_stream(
+        &self,
+        request: Request<Executable>,
+    ) -> Result<Response<Self::ExecStreamStream>, Status> {
+        let timeout = grpc_timeout(request.metadata());
+        let r = request.into_inner();
+        let timeout = earliest(timeout, r.timeout());
+        let (tx, rx) = mpsc::channel(EXEC_STREAM_BUFFER);
+
+        let child = r.to_command().and_then(|mut cmd| {
+            let child = cmd
+                .command
+                .process_group(0)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            Ok((child, cmd.cgroup))
+        });
+        let (mut child, cgroup) = match child {
+            Ok(child) => child,
             Err(e) => {
-                let meta = meta::AuraeMeta {
-                    name: "-".to_string(),
-                    message: format!("{:?}", e),
-                };
-                let proc = meta::ProcessMeta { pid: -1 };
-                let status = meta::Status::Error as i32;
-                let response = ExecutableStatus {
-                    meta: Some(meta),
-                    proc: Some(proc),
-                    status,
-                    stdout: "-".to_string(),
-                    stderr: "-".to_string(),
-                    exit_code: "-".to_string(),
-                };
-                Ok(Response::new(response))
+                let _ = tx.send(Ok(spawn_error_frame(e))).await;
+                return Ok(Response::new(ReceiverStream::new(rx)));
             }
+        };
+
+        let proc = meta::ProcessMeta {
+            pid: child.id() as i32,
+            start_time: unix_timestamp(),
+        };
+        self.events.publish_start(&r.command, proc.clone());
+        let (output_tx, output) = mpsc::channel(EXEC_STREAM_BUFFER);
+        if let Some(stdout) = child.stdout.take() {
+            let (tx, name) = (output_tx.clone(), r.command.clone());
+            drop(tokio::task::spawn_blocking(move || {
+                forward_output(stdout, OutputChannel::Stdout, name, tx)
+            }));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let (tx, name) = (output_tx, r.command.clone());
+            drop(tokio::task::spawn_blocking(move || {
+                forward_output(stderr, OutputChannel::Stderr, name, tx)
+            }));
         }
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let name = r.command;
+            let exited = finish_stream(
+                child,
+                cgroup,
+                proc,
+                output,
+                timeout,
+                name.clone(),
+                tx,
+            )
+            .await;
+            if let Some((exited, oom_kills)) = exited {
+                events.publish_exit(&name, (&exited).into(), oom_kills);
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn exec_interactive(
+        &self,
+        request: Request<Streaming<ExecInteractiveRequest>>,
+    ) -> Result<Response<Self::ExecInteractiveStream>, Status> {
+        let timeout = grpc_timeout(request.metadata());
+        let (tx, rx) = mpsc::channel(EXEC_STREAM_BUFFER);
+        interactive::exec_interactive(
+            request.into_inner(),
+            timeout,
+            tx,
+            self.events.clone(),
+        )
+        .await?;
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn start_executable(
+        &self,
+        request: Request<StartExecutableRequest>,
+    ) -> Result<Response<StartExecutableResponse>, Status> {
+        let executable = request.into_inner().executable.unwrap_or_default();
+        let status = self.processes.start(&executable)?;
+        Ok(Response::new(StartExecutableResponse { executable: Some(status) }))
+    }
+
+    async fn wait_executable(
+        &self,
+        request: Request<WaitExecutableRequest>,
+    ) -> Result<Response<WaitExecutableResponse>, Status> {
+        let name =
+            request.into_inner().meta.map(|m| m.name).unwrap_or_default();
+        let status = self.processes.wait(&name).await?;
+        Ok(Response::new(WaitExecutableResponse { executable: Some(status) }))
+    }
+
+    async fn stop_executable(
+        &self,
+        request: Request<StopExecutableRequest>,
+    ) -> Result<Response<StopExecutableResponse>, Status> {
+        let r = request.into_inner();
+        let name = r.meta.map(|m| m.name).unwrap_or_default();
+        let (signal, grace_period) =
+            stop_options(&r.signal, r.grace_period_ms)?;
+        let status = self.processes.stop(&name, signal, grace_period).await?;
+        Ok(Response::new(StopExecutableResponse { executable: Some(status) }))
+    }
+
+    async fn list_executables(
+        &self,
+        _request: Request<ListExecutablesRequest>,
+    ) -> Result<Response<ListExecutablesResponse>, Status> {
+        let executables = self.processes.list();
+        Ok(Response::new(ListExecutablesResponse { executables }))
+    }
+
+    async fn container_start(
+        &self,
+        request: Request<ContainerStartRequest>,
+    ) -> Result<Response<ContainerStartResponse>, Status> {
+        let container = request.into_inner().container.unwrap_or_default();
+        let (containers, images) =
+            (self.containers.clone(), self.images.clone());
+        // Unpacking the image of a container can take a while.
+        let status = tokio::task::spawn_blocking(move || {
+            container::start(&containers, &images, &container)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(ContainerStartResponse { container: Some(status) }))
+    }
+
+    async fn container_stop(
+        &self,
+        request: Request<ContainerStopRequest>,
+    ) -> Result<Response<ContainerStopResponse>, Status> {
+        let r = request.into_inner();
+        let (signal, grace_period) =
+            stop_options(&r.signal, r.grace_period_ms)?;
+        let status =
+            self.containers.stop(&r.name, signal, grace_period).await?;
+        Ok(Response::new(ContainerStopResponse {
+            container: Some(container::container_status(status)),
+        }))
     }
 
-    // async fn executable_stop(
-    //     &self,
-    //     request: Request<Executable>,
-    // ) -> Result<Response<ExecutableStatus>, Status> {
-    //     let _r = request.into_inner();
-    //     let meta = meta::AuraeMeta {
-    //         name: "UNKNOWN_NAME".to_string(),
-    //         message: "UNKNOWN_MESSAGE".to_string(),
-    //     };
-    //     let proc = meta::ProcessMeta { pid: -1 };
-    //     let status = meta::Status::Unknown as i32;
-    //     let response = ExecutableStatus {
-    //         meta: Some(meta),
-    //         proc: Some(proc),
-    //         status,
-    //         stdout: "-".to_string(),
-    //         stderr: "-".to_string(),
-    //         exit_code: "-".to_string(),
-    //     };
-    //     Ok(Response::new(response))
-    // }
-    //
-    // async fn container_start(
-    //     &self,
-    //     _request: Request<Container>,
-    // ) -> Result<Response<ContainerStatus>, Status> {
-    //     todo!()
-    // }
-    //
-    // async fn container_stop(
-    //     &self,
-    //     _request: Request<Container>,
-    // ) -> Result<Response<ContainerStatus>, Status> {
-    //     todo!()
-    // }
-    //
     // async fn instance_start(
     //     &self,
     //     _request: Request<Instance>,
@@ -159,3 +298,210 @@ impl Runtime for RuntimeService {
     //     todo!()
     // }
 }
+
+/// The signal and grace period of a stop request, with their defaults applied.
+fn stop_options(
+    signal: &str,
+    grace_period_ms: u64,
+) -> Result<(i32, Duration), ProcessError> {
+    let signal = match signal {
+        "" => libc::SIGTERM,
+        signal => parse_signal(signal)?,
+    };
+    let grace_period = match grace_period_ms {
+        0 => DEFAULT_STOP_GRACE_PERIOD,
+        ms => Duration::from_millis(ms),
+    };
+    Ok((signal, grace_period))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::exec_interactive_request::Request as Input;
+    use crate::runtime::runtime_client::RuntimeClient;
+    use crate::runtime::runtime_server::RuntimeServer;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic::transport::{Channel, Server};
+
+    /// Serves a RuntimeService on a local port, for RPCs that stream their
+    /// requests.
+    async fn client() -> RuntimeClient<Channel> {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        drop(tokio::spawn(
+            Server::builder()
+                .add_service(RuntimeServer::new(RuntimeService::default()))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        ));
+        RuntimeClient::connect(format!("http://{}", addr))
+            .await
+            .expect("connect")
+    }
+
+    /// Runs an ExecInteractive call with `requests`, and returns the output
+    /// it streamed back along with its final frame.
+    async fn exec_interactive(
+        requests: Vec<Input>,
+    ) -> (Vec<u8>, exec_stream_response::Frame) {
+        let requests = requests
+            .into_iter()
+            .map(|r| ExecInteractiveRequest { request: Some(r) });
+        let mut stream = client()
+            .await
+            .exec_interactive(tokio_stream::iter(requests))
+            .await
+            .expect("exec_interactive")
+            .into_inner();
+        let mut stdout = Vec::new();
+        while let Some(response) = stream.next().await {
+            match response.expect("frame").frame.expect("frame content") {
+                exec_stream_response::Frame::Output(o) => {
+                    stdout.extend_from_slice(&o.data)
+                }
+                exit => {
+                    assert!(stream.next().await.is_none(), "exit is last");
+                    return (stdout, exit);
+                }
+            }
+        }
+        panic!("stream ended without an exit frame");
+    }
+
+    fn start(command: &str, tty: bool) -> Input {
+        Input::Start(Box::new(ExecInteractiveStart {
+            executable: Some(Executable {
+                command: command.to_string(),
+                ..Default::default()
+            }),
+            tty,
+            window_size: None,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_exec_times_out() {
+        let request = Executable {
+            command: "sleep 5".to_string(),
+            timeout_ms: 100,
+            ..Default::default()
+        };
+        let status = RuntimeService::default()
+            .exec(Request::new(request))
+            .await
+            .expect("exec")
+            .into_inner();
+        assert_eq!(status.status, meta::Status::Timeout as i32);
+        assert_eq!(status.signal, libc::SIGKILL);
+    }
+
+    #[tokio::test]
+    async fn test_exec_stream_ends_with_exit() {
+        let request = Executable {
+            command: "echo hello".to_string(),
+            ..Default::default()
+        };
+        let stream = RuntimeService::default()
+            .exec_stream(Request::new(request))
+            .await
+            .expect("exec_stream")
+            .into_inner();
+        let frames: Vec<_> = stream
+            .map(|r| r.expect("frame").frame.expect("frame content"))
+            .collect()
+            .await;
+
+        let mut stdout = Vec::new();
+        for frame in &frames[..frames.len() - 1] {
+            match frame {
+                exec_stream_response::Frame::Output(o) => {
+                    stdout.extend_from_slice(&o.data)
+                }
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+        assert_eq!(stdout, b"hello\n");
+
+        match frames.last() {
+            Some(exec_stream_response::Frame::Exit(exit)) => {
+                assert_eq!(exit.status, meta::Status::Complete as i32);
+                assert!(exit.proc.as_ref().expect("proc").pid > 0);
+            }
+            frame => panic!("unexpected last frame {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec_stream_ends_while_background_holds_output() {
+        let request = Executable {
+            command: "sh".to_string(),
+            args: vec!["-c".into(), "echo started; sleep 1000 &".into()],
+            ..Default::default()
+        };
+        let stream = RuntimeService::default()
+            .exec_stream(Request::new(request))
+            .await
+            .expect("exec_stream")
+            .into_inner();
+        let frames: Vec<_> = tokio::time::timeout(
+            Duration::from_secs(5),
+            stream.map(|r| r.expect("frame").frame.expect("frame")).collect(),
+        )
+        .await
+        .expect("stream ends while the pipes are still open");
+
+        assert!(matches!(
+            frames.first(),
+            Some(exec_stream_response::Frame::Output(o)) if o.data == b"started\n"
+        ));
+        let pid = match frames.last() {
+            Some(exec_stream_response::Frame::Exit(exit)) => {
+                assert_eq!(exit.status, meta::Status::Complete as i32);
+                exit.proc.as_ref().expect("proc").pid
+            }
+            frame => panic!("unexpected last frame {:?}", frame),
+        };
+        let _ = process::kill_group(pid, libc::SIGKILL);
+    }
+
+    #[tokio::test]
+    async fn test_exec_interactive_forwards_stdin() {
+        let (stdout, exit) = exec_interactive(vec![
+            start("cat", false),
+            Input::Stdin(b"hello\n".to_vec()),
+            Input::Stdin(b"world\n".to_vec()),
+            // Cat only exits once its stdin is closed.
+            Input::CloseStdin(true),
+        ])
+        .await;
+        assert_eq!(stdout, b"hello\nworld\n");
+        match exit {
+            exec_stream_response::Frame::Exit(exit) => {
+                assert_eq!(exit.status, meta::Status::Complete as i32);
+                assert_eq!(exit.exit_code, 0);
+            }
+            frame => panic!("unexpected last frame {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec_interactive_resizes_terminal() {
+        let (stdout, exit) = exec_interactive(vec![
+            start("sh -c 'read line; stty size'", true),
+            Input::Resize(WindowSize { rows: 40, columns: 120 }),
+            Input::Stdin(b"go\n".to_vec()),
+        ])
+        .await;
+        let stdout = String::from_utf8_lossy(&stdout);
+        assert!(stdout.contains("40 120"), "{}", stdout);
+        match exit {
+            exec_stream_response::Frame::Exit(exit) => {
+                assert_eq!(exit.status, meta::Status::Complete as i32);
+                assert_eq!(exit.exit_code, 0);
+            }
+            frame => panic!("unexpected last frame {:?}", frame),
+        }
+    }
+}
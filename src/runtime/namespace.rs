@@ -0,0 +1,677 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::executable::check;
+use crate::runtime::{ExecutableIdMapping, ExecutableNamespaces};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Highest file descriptor the intermediate process of a PID namespace
+/// looks at when it closes the descriptors the executable closes on exec.
+const MAX_FD: libc::c_int = 4096;
+
+/// Namespaces another process can be moved into with setns, by their name in
+/// /proc/<pid>/ns. The user namespace comes first, as it grants the
+/// capabilities to join the others. PID namespaces are left out, as joining
+/// one only moves the children of a process into it.
+const JOINABLE: [(&str, libc::c_int); 5] = [
+    ("user", libc::CLONE_NEWUSER),
+    ("mnt", libc::CLONE_NEWNS),
+    ("uts", libc::CLONE_NEWUTS),
+    ("ipc", libc::CLONE_NEWIPC),
+    ("net", libc::CLONE_NEWNET),
+];
+
+/// The executable the intermediate process of a PID namespace waits for, by
+/// its pid in the namespace of auraed.
+static EXECUTABLE: AtomicI32 = AtomicI32::new(0);
+
+/// The /proc directory of EXECUTABLE, opened before the namespace gets a
+/// /proc of its own.
+static EXECUTABLE_PROC: AtomicI32 = AtomicI32::new(-1);
+
+/// The last signal that was turned into SIGKILL for EXECUTABLE.
+static KILLED_BY: AtomicI32 = AtomicI32::new(0);
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub(crate) enum NamespaceError {
+    #[error("a user namespace requires uid_mappings and gid_mappings")]
+    MissingIdMappings,
+    #[error("id mappings require a user namespace")]
+    IdMappingsWithoutUser,
+    #[error("hostname requires a uts namespace")]
+    HostnameWithoutUts,
+    #[error("invalid hostname {0:?}")]
+    InvalidHostname(String),
+}
+
+/// Namespaces are the namespaces an executable is started in, prepared so
+/// that they can be entered in the child with async-signal-safe calls only.
+#[derive(Debug)]
+pub(crate) struct Namespaces {
+    /// Flags are the CLONE_NEW* flags passed to unshare.
+    flags: libc::c_int,
+    hostname: Option<CString>,
+    /// IdMaps are the contents of uid_map and gid_map, if the executable
+    /// runs in a user namespace.
+    id_maps: Option<(Vec<u8>, Vec<u8>)>,
+    /// MountProc mounts /proc for a new PID namespace, if the executable has
+    /// a mount namespace as well.
+    pub mount_proc: bool,
+}
+
+impl Namespaces {
+    /// Prepares `namespaces`. Returns None if the executable shares all of
+    /// its namespaces with auraed.
+    pub fn new(
+        namespaces: &ExecutableNamespaces,
+    ) -> Result<Option<Self>, NamespaceError> {
+        let mut flags = 0;
+        for (enabled, flag) in [
+            (namespaces.pid, libc::CLONE_NEWPID),
+            (namespaces.mount, libc::CLONE_NEWNS),
+            (namespaces.uts, libc::CLONE_NEWUTS),
+            (namespaces.ipc, libc::CLONE_NEWIPC),
+            (namespaces.net, libc::CLONE_NEWNET),
+            (namespaces.user, libc::CLONE_NEWUSER),
+        ] {
+            if enabled {
+                flags |= flag;
+            }
+        }
+
+        let hostname = match namespaces.hostname.as_str() {
+            "" => None,
+            _ if !namespaces.uts => {
+                return Err(NamespaceError::HostnameWithoutUts)
+            }
+            hostname if hostname.len() > 64 => {
+                return Err(NamespaceError::InvalidHostname(hostname.into()))
+            }
+            hostname => Some(CString::new(hostname).map_err(|_| {
+                NamespaceError::InvalidHostname(hostname.into())
+            })?),
+        };
+
+        let mappings = !namespaces.uid_mappings.is_empty()
+            || !namespaces.gid_mappings.is_empty();
+        let id_maps = match (namespaces.user, mappings) {
+            (false, false) => None,
+            (false, true) => return Err(NamespaceError::IdMappingsWithoutUser),
+            (true, _)
+                if namespaces.uid_mappings.is_empty()
+                    || namespaces.gid_mappings.is_empty() =>
+            {
+                return Err(NamespaceError::MissingIdMappings)
+            }
+            (true, _) => Some((
+                id_map(&namespaces.uid_mappings),
+                id_map(&namespaces.gid_mappings),
+            )),
+        };
+
+        if flags == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Self { flags, hostname, id_maps, mount_proc: true }))
+    }
+
+    /// Adds a cgroup namespace, rooted at the cgroup the calling process is
+    /// in once it enters the namespaces.
+    pub fn add_cgroup(&mut self) {
+        self.flags |= libc::CLONE_NEWCGROUP;
+    }
+
+    /// Moves the calling process into new namespaces. In a new PID namespace
+    /// only children are created, so the calling process forks, and stays
+    /// behind to wait for its child and report its exit, while the child
+    /// returns and goes on to become PID 1 of the namespace.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called in a child process that has been forked to exec
+    /// the executable.
+    pub unsafe fn enter(&self) -> io::Result<()> {
+        match &self.id_maps {
+            Some((uid_map, gid_map)) => {
+                self.unshare_mapped(uid_map, gid_map)?
+            }
+            None => check(libc::unshare(self.flags))?,
+        }
+        if self.flags & libc::CLONE_NEWNS != 0 {
+            // Mounts are kept from propagating back into the namespace of
+            // auraed.
+            check(libc::mount(
+                ptr::null(),
+                c"/".as_ptr(),
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            ))?;
+        }
+        if let Some(hostname) = &self.hostname {
+            check(libc::sethostname(
+                hostname.as_ptr(),
+                hostname.as_bytes().len(),
+            ))?;
+        }
+        if self.flags & libc::CLONE_NEWNET != 0 {
+            loopback_up()?;
+        }
+        if self.flags & libc::CLONE_NEWPID != 0 {
+            fork_into_pid_namespace()?;
+            if self.mount_proc && self.flags & libc::CLONE_NEWNS != 0 {
+                check(libc::mount(
+                    c"proc".as_ptr(),
+                    c"/proc".as_ptr(),
+                    c"proc".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    ptr::null(),
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unshares the namespaces with a new user namespace, and has its ids
+    /// mapped. The maps can only be written from the parent user namespace,
+    /// so a helper that stays behind writes them once the namespace exists.
+    unsafe fn unshare_mapped(
+        &self,
+        uid_map: &[u8],
+        gid_map: &[u8],
+    ) -> io::Result<()> {
+        let mut pipe = [0; 2];
+        check(libc::pipe2(pipe.as_mut_ptr(), libc::O_CLOEXEC))?;
+        let helper = libc::fork();
+        if helper < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if helper == 0 {
+            let _ = libc::close(pipe[1]);
+            // The namespace is only there once a byte arrives. End of file
+            // means unshare failed.
+            let mut ready = 0u8;
+            let read = libc::read(pipe[0], ptr::addr_of_mut!(ready).cast(), 1);
+            let pid = libc::getppid();
+            let code = match read == 1
+                && write_id_map(pid, b"/uid_map\0", uid_map)
+                && write_id_map(pid, b"/gid_map\0", gid_map)
+            {
+                true => 0,
+                false => 1,
+            };
+            libc::_exit(code);
+        }
+        let _ = libc::close(pipe[0]);
+
+        let unshared = check(libc::unshare(self.flags));
+        if unshared.is_ok() {
+            let _ = libc::write(pipe[1], b"1".as_ptr().cast(), 1);
+        }
+        let _ = libc::close(pipe[1]);
+        let status = wait(helper)?;
+        unshared?;
+        if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "failed to write the id mappings of the user namespace",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// ProcessNamespaces are the namespaces of a running process that differ
+/// from those of auraed, opened so that other processes can join them.
+#[derive(Debug)]
+pub(crate) struct ProcessNamespaces {
+    namespaces: Vec<(File, libc::c_int)>,
+}
+
+impl ProcessNamespaces {
+    /// Opens the namespaces of the process `pid` that auraed is not in.
+    pub fn of(pid: libc::pid_t) -> io::Result<Self> {
+        let mut namespaces = Vec::new();
+        for (name, kind) in JOINABLE {
+            let own = fs::metadata(format!("/proc/self/ns/{}", name))?;
+            let namespace = File::open(format!("/proc/{}/ns/{}", pid, name))?;
+            let metadata = namespace.metadata()?;
+            if (metadata.dev(), metadata.ino()) != (own.dev(), own.ino()) {
+                namespaces.push((namespace, kind));
+            }
+        }
+        Ok(Self { namespaces })
+    }
+
+    /// The network namespace of the process, if it has one of its own.
+    pub fn net(&self) -> Option<&File> {
+        self.namespaces
+            .iter()
+            .find(|(_, kind)| *kind == libc::CLONE_NEWNET)
+            .map(|(namespace, _)| namespace)
+    }
+
+    /// Moves the calling process into the namespaces.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called in a child process that has been forked to exec
+    /// an executable, as setns only moves the calling thread, and only a
+    /// single threaded process can join a user namespace.
+    pub unsafe fn join(&self) -> io::Result<()> {
+        for (namespace, kind) in &self.namespaces {
+            check(libc::setns(namespace.as_raw_fd(), *kind))?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders mappings in the format of /proc/[pid]/uid_map.
+fn id_map(mappings: &[ExecutableIdMapping]) -> Vec<u8> {
+    mappings
+        .iter()
+        .map(|m| format!("{} {} {}\n", m.container_id, m.host_id, m.size))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Writes `map` to /proc/`pid``file`. The map has to be written at once.
+unsafe fn write_id_map(pid: libc::pid_t, file: &[u8], map: &[u8]) -> bool {
+    let mut digits = [0u8; 16];
+    let mut path = [0u8; 64];
+    let mut len = 0;
+    for part in [&b"/proc/"[..], decimal(pid, &mut digits), file] {
+        path[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+    let fd = libc::open(path.as_ptr().cast(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return false;
+    }
+    let written = libc::write(fd, map.as_ptr().cast(), map.len());
+    let _ = libc::close(fd);
+    written == map.len() as isize
+}
+
+/// Formats `n` into `buf` without allocating.
+pub(crate) fn decimal(mut n: libc::pid_t, buf: &mut [u8; 16]) -> &[u8] {
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &buf[start..];
+        }
+    }
+}
+
+/// Forks the first process of a new PID namespace. The calling process only
+/// waits for it and exits the same way.
+///
+/// Signals sent to the process group reach the executable as well. As PID 1
+/// of its namespace, the executable drops those it has no handler for, so
+/// the calling process turns the ones that would have terminated it into
+/// SIGKILL, which the executable cannot drop.
+unsafe fn fork_into_pid_namespace() -> io::Result<()> {
+    let mut ready = [0; 2];
+    check(libc::pipe2(ready.as_mut_ptr(), libc::O_CLOEXEC))?;
+    let child = libc::fork();
+    if child < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if child == 0 {
+        // The executable is killed if the process that waits for it is.
+        check(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL))?;
+        // The namespace may only mount its own /proc once the process that
+        // waits for us opened our directory in the /proc of auraed.
+        let _ = libc::close(ready[1]);
+        let mut byte = 0u8;
+        let _ = libc::read(ready[0], ptr::addr_of_mut!(byte).cast(), 1);
+        let _ = libc::close(ready[0]);
+        return Ok(());
+    }
+
+    let _ = libc::close(ready[0]);
+    let mut digits = [0u8; 16];
+    let mut path = [0u8; 32];
+    let mut len = 0;
+    for part in [&b"/proc/"[..], decimal(child, &mut digits), b"\0"] {
+        path[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+    EXECUTABLE.store(child, Ordering::Relaxed);
+    EXECUTABLE_PROC.store(
+        libc::open(path.as_ptr().cast(), libc::O_PATH | libc::O_DIRECTORY),
+        Ordering::Relaxed,
+    );
+    for signal in 1..32 {
+        if signal != libc::SIGKILL
+            && signal != libc::SIGSTOP
+            && signal != libc::SIGCHLD
+        {
+            let _ = libc::signal(
+                signal,
+                forward_signal as extern "C" fn(libc::c_int) as usize,
+            );
+        }
+    }
+    let _ = libc::write(ready[1], b"1".as_ptr().cast(), 1);
+    let _ = libc::close(ready[1]);
+
+    // The executable closes these once it execs. Keeping them open here
+    // would hold up auraed, which waits for them to close on exec.
+    for fd in 3..MAX_FD {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags >= 0 && flags & libc::FD_CLOEXEC != 0 {
+            let _ = libc::close(fd);
+        }
+    }
+    let status = match wait(child) {
+        Ok(status) => status,
+        Err(_) => libc::_exit(1),
+    };
+    if libc::WIFSIGNALED(status) {
+        let mut signal = libc::WTERMSIG(status);
+        let killed_by = KILLED_BY.load(Ordering::Relaxed);
+        if signal == libc::SIGKILL && killed_by != 0 {
+            signal = killed_by;
+        }
+        let _ = libc::signal(signal, libc::SIG_DFL);
+        let _ = libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status));
+}
+
+/// Handles a signal in the process that waits for the executable of a PID
+/// namespace. The executable got the signal as well if it was sent to the
+/// process group. Unless the executable handles it, it is killed if the
+/// signal would have terminated any other process.
+extern "C" fn forward_signal(signal: libc::c_int) {
+    if !terminates(signal) || unsafe { catches(signal) } {
+        return;
+    }
+    KILLED_BY.store(signal, Ordering::Relaxed);
+    let _ = unsafe {
+        libc::kill(EXECUTABLE.load(Ordering::Relaxed), libc::SIGKILL)
+    };
+}
+
+/// Whether the default action of `signal` terminates a process.
+fn terminates(signal: libc::c_int) -> bool {
+    !matches!(
+        signal,
+        libc::SIGCHLD
+            | libc::SIGCONT
+            | libc::SIGSTOP
+            | libc::SIGTSTP
+            | libc::SIGTTIN
+            | libc::SIGTTOU
+            | libc::SIGURG
+            | libc::SIGWINCH
+    )
+}
+
+/// Whether EXECUTABLE has a handler for `signal`, as its SigCgt mask in
+/// /proc/[pid]/status says.
+unsafe fn catches(signal: libc::c_int) -> bool {
+    let fd = libc::openat(
+        EXECUTABLE_PROC.load(Ordering::Relaxed),
+        c"status".as_ptr(),
+        libc::O_RDONLY | libc::O_CLOEXEC,
+    );
+    if fd < 0 {
+        return false;
+    }
+    let mut status = [0u8; 4096];
+    let mut len = 0;
+    while len < status.len() {
+        let read = libc::read(
+            fd,
+            status[len..].as_mut_ptr().cast(),
+            status.len() - len,
+        );
+        if read <= 0 {
+            break;
+        }
+        len += read as usize;
+    }
+    let _ = libc::close(fd);
+    status_mask(&status[..len], b"SigCgt:") & (1 << (signal - 1)) != 0
+}
+
+/// Parses the hexadecimal mask of the line starting with `field` in
+/// /proc/[pid]/status.
+fn status_mask(status: &[u8], field: &[u8]) -> u64 {
+    let mut mask = 0;
+    for line in status.split(|&b| b == b'\n') {
+        let Some(value) = line.strip_prefix(field) else {
+            continue;
+        };
+        for &b in value {
+            let digit = match b {
+                b'0'..=b'9' => b - b'0',
+                b'a'..=b'f' => b - b'a' + 10,
+                _ => continue,
+            };
+            mask = mask << 4 | u64::from(digit);
+        }
+    }
+    mask
+}
+
+unsafe fn wait(pid: libc::pid_t) -> io::Result<libc::c_int> {
+    let mut status = 0;
+    loop {
+        if libc::waitpid(pid, &mut status, 0) >= 0 {
+            return Ok(status);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// The part of struct ifreq used to get and set the flags of an interface.
+#[repr(C)]
+struct InterfaceFlags {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    flags: libc::c_short,
+    _padding: [u8; 22],
+}
+
+/// Brings up the loopback interface of a new network namespace.
+unsafe fn loopback_up() -> io::Result<()> {
+    let socket =
+        libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if socket < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut request = InterfaceFlags {
+        name: [0; libc::IFNAMSIZ],
+        flags: 0,
+        _padding: [0; 22],
+    };
+    request.name[0] = b'l' as libc::c_char;
+    request.name[1] = b'o' as libc::c_char;
+    let mut result =
+        check(libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request));
+    if result.is_ok() {
+        request.flags |= libc::IFF_UP as libc::c_short;
+        result = check(libc::ioctl(socket, libc::SIOCSIFFLAGS, &request));
+    }
+    let _ = libc::close(socket);
+    result
+}
+
+/// Whether auraed may create namespaces. Tests that need them are skipped
+/// without CAP_SYS_ADMIN.
+#[cfg(test)]
+pub(crate) fn privileged() -> bool {
+    let status = std::fs::read("/proc/self/status").unwrap_or_default();
+    status_mask(&status, b"CapEff:") & (1 << 21) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::process::Process;
+    use crate::runtime::Executable;
+
+    #[test]
+    fn test_new_validates_namespaces() {
+        let none = Namespaces::new(&ExecutableNamespaces::default());
+        assert!(matches!(none, Ok(None)));
+
+        let hostname = ExecutableNamespaces {
+            hostname: "web".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Namespaces::new(&hostname).err(),
+            Some(NamespaceError::HostnameWithoutUts)
+        );
+
+        let user = ExecutableNamespaces { user: true, ..Default::default() };
+        assert_eq!(
+            Namespaces::new(&user).err(),
+            Some(NamespaceError::MissingIdMappings)
+        );
+
+        let mapping = ExecutableIdMapping {
+            container_id: 0,
+            host_id: 100000,
+            size: 65536,
+        };
+        let user = ExecutableNamespaces {
+            user: true,
+            pid: true,
+            uid_mappings: vec![mapping.clone()],
+            gid_mappings: vec![mapping],
+            ..Default::default()
+        };
+        let namespaces = Namespaces::new(&user).expect("valid").expect("some");
+        assert_eq!(namespaces.flags, libc::CLONE_NEWUSER | libc::CLONE_NEWPID);
+        let (uid_map, _) = namespaces.id_maps.expect("id maps");
+        assert_eq!(uid_map, b"0 100000 65536\n");
+    }
+
+    #[test]
+    fn test_status_mask() {
+        let status = b"Name:\tsh\nSigIgn:\t0000000000000004\nSigCgt:\t0000000000010002\n";
+        let caught = status_mask(status, b"SigCgt:");
+        assert_eq!(caught, 0x10002);
+        assert!(caught & (1 << (libc::SIGINT - 1)) != 0);
+        assert!(caught & (1 << (libc::SIGTERM - 1)) == 0);
+    }
+
+    #[tokio::test]
+    async fn test_executable_in_namespaces() {
+        if !privileged() {
+            eprintln!("skipping, creating namespaces requires CAP_SYS_ADMIN");
+            return;
+        }
+        let mapping =
+            ExecutableIdMapping { container_id: 0, host_id: 0, size: 65536 };
+        let executable = Executable {
+            command: "sh".to_string(),
+            args: vec![
+                "-c".into(),
+                "echo $$; hostname; cat /proc/self/uid_map".into(),
+            ],
+            namespaces: Some(ExecutableNamespaces {
+                pid: true,
+                mount: true,
+                uts: true,
+                hostname: "sandbox".to_string(),
+                ipc: true,
+                net: true,
+                user: true,
+                uid_mappings: vec![mapping.clone()],
+                gid_mappings: vec![mapping],
+            }),
+            ..Default::default()
+        };
+        let cmd = executable.to_command().expect("command");
+        let process = Process::spawn(cmd, 4096, None).expect("spawn");
+        let exit = process.wait().await;
+        let stdout = String::from_utf8_lossy(&exit.stdout.data).to_string();
+        assert!(exit.status.expect("status").success(), "{}", stdout);
+        let lines: Vec<&str> = stdout
+            .lines()
+            .map(|l| l.split_whitespace().next().unwrap_or_default())
+            .collect();
+        assert_eq!(lines, ["1", "sandbox", "0"]);
+    }
+
+    #[tokio::test]
+    async fn test_stop_executable_in_pid_namespace() {
+        if !privileged() {
+            eprintln!("skipping, creating namespaces requires CAP_SYS_ADMIN");
+            return;
+        }
+        // Sleep has no handler for SIGTERM, which PID 1 would drop.
+        let executable = Executable {
+            command: "sleep 30".to_string(),
+            namespaces: Some(ExecutableNamespaces {
+                pid: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let cmd = executable.to_command().expect("command");
+        let process = Process::spawn(cmd, 4096, None).expect("spawn");
+        // Give the executable a moment to start in its namespace.
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let exit = process
+            .stop(libc::SIGTERM, std::time::Duration::from_secs(5))
+            .await
+            .expect("stop");
+        let status = exit.status.expect("status");
+        assert_eq!(
+            std::os::unix::process::ExitStatusExt::signal(&status),
+            Some(libc::SIGTERM)
+        );
+    }
+
+    #[test]
+    fn test_decimal() {
+        assert_eq!(decimal(0, &mut [0; 16]), b"0");
+        assert_eq!(decimal(4194304, &mut [0; 16]), b"4194304");
+    }
+}
@@ -0,0 +1,258 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::meta;
+use crate::runtime::cgroup::Cgroup;
+use crate::runtime::exec_stream_response::Frame;
+use crate::runtime::process::{wait_child, ProcessGroup, OUTPUT_DRAIN_TIMEOUT};
+use crate::runtime::{
+    ExecStreamResponse, ExecutableExit, ExecutableOutput, ExecutableUsage,
+    OutputChannel,
+};
+use log::warn;
+use std::io::{ErrorKind, Read};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{timeout_at, Instant};
+use tonic::Status;
+
+/// Size of the buffer used to read from the pipes of a child process.
+/// Every read becomes one output frame, so this bounds the frame size.
+const OUTPUT_CHUNK_SIZE: usize = 8192;
+
+/// Output kept for an executable that does not set max_output_bytes.
+pub(crate) const DEFAULT_MAX_OUTPUT_BYTES: usize = 4 * 1024 * 1024;
+
+pub(crate) type FrameSender = Sender<Result<ExecStreamResponse, Status>>;
+pub(crate) type FrameReceiver = Receiver<Result<ExecStreamResponse, Status>>;
+
+pub(crate) fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+/// Reads from a pipe of a child process until EOF, and forwards every chunk
+/// as an output frame. The pipes of a std child are blocking, so this is
+/// expected to run on the blocking thread pool.
+pub(crate) fn forward_output(
+    mut reader: impl Read,
+    channel: OutputChannel,
+    name: String,
+    tx: FrameSender,
+) {
+    let mut buf = [0u8; OUTPUT_CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // A pseudo terminal reports EIO once the terminal hangs up.
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => return,
+            Err(e) => {
+                warn!("Failed to read {:?} of {}: {}", channel, name, e);
+                return;
+            }
+        };
+        let frame = Frame::Output(ExecutableOutput {
+            channel: channel as i32,
+            data: buf[..n].to_vec(),
+            timestamp: unix_timestamp(),
+        });
+        // The client hung up, there is nobody left to read the output.
+        if tx.blocking_send(Ok(response(&name, "-", frame))).is_err() {
+            return;
+        }
+    }
+}
+
+/// Output read from a pipe of a child process, up to a cap.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CapturedOutput {
+    pub data: Vec<u8>,
+    pub truncated: bool,
+}
+
+/// Reads from a pipe of a child process until EOF into `output`, keeping at
+/// most `cap` bytes. The pipe is drained past the cap, so the child never
+/// blocks on a full pipe.
+pub(crate) fn capture_output(
+    mut reader: impl Read,
+    cap: usize,
+    output: &Mutex<CapturedOutput>,
+) {
+    let mut buf = [0u8; OUTPUT_CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+        let keep = n.min(cap - output.data.len());
+        output.data.extend_from_slice(&buf[..keep]);
+        output.truncated |= keep < n;
+    }
+}
+
+/// The exit of a process as reported to clients: the exit code or -1, the
+/// terminating signal or 0, and whether a core was dumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ExitFields {
+    pub exit_code: i32,
+    pub signal: i32,
+    pub core_dumped: bool,
+}
+
+impl ExitFields {
+    pub const NONE: ExitFields =
+        ExitFields { exit_code: -1, signal: 0, core_dumped: false };
+}
+
+impl From<ExitStatus> for ExitFields {
+    fn from(status: ExitStatus) -> Self {
+        ExitFields {
+            exit_code: status.code().unwrap_or(-1),
+            signal: status.signal().unwrap_or(0),
+            core_dumped: status.core_dumped(),
+        }
+    }
+}
+
+pub(crate) fn exit_frame(
+    name: &str,
+    message: &str,
+    proc: meta::ProcessMeta,
+    status: meta::Status,
+    exit: ExitFields,
+    usage: Option<ExecutableUsage>,
+) -> ExecStreamResponse {
+    let frame = Frame::Exit(ExecutableExit {
+        proc: Some(proc),
+        status: status as i32,
+        timestamp: unix_timestamp(),
+        exit_code: exit.exit_code,
+        signal: exit.signal,
+        core_dumped: exit.core_dumped,
+        usage,
+    });
+    response(name, message, frame)
+}
+
+/// The only frame of a stream whose executable could not be started.
+pub(crate) fn spawn_error_frame(e: anyhow::Error) -> ExecStreamResponse {
+    exit_frame(
+        "-",
+        &format!("{:?}", e),
+        meta::ProcessMeta { pid: -1, start_time: 0 },
+        meta::Status::Error,
+        ExitFields::NONE,
+        None,
+    )
+}
+
+/// Waits for a streamed child, and relays the `output` its readers forward
+/// until it exited, then for at most OUTPUT_DRAIN_TIMEOUT longer, as anything
+/// it forked into the background may hold its pipes open for much longer.
+/// The exit is sent last, and ends the stream. Returns the exit along with
+/// the OOM kills in the cgroup of the child.
+pub(crate) async fn finish_stream(
+    child: Child,
+    cgroup: Option<Cgroup>,
+    proc: meta::ProcessMeta,
+    mut output: FrameReceiver,
+    timeout: Option<Duration>,
+    name: String,
+    tx: FrameSender,
+) -> Option<(ExecutableExit, u64)> {
+    let group = ProcessGroup::new(child.id() as i32);
+    let exited = wait_child(child, cgroup, timeout, group);
+    tokio::pin!(exited);
+    let exit = loop {
+        tokio::select! {
+            exit = &mut exited => break exit,
+            Some(frame) = output.recv() => {
+                let _ = tx.send(frame).await;
+            }
+        }
+    };
+    let deadline = Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+    while let Ok(Some(frame)) = timeout_at(deadline, output.recv()).await {
+        let _ = tx.send(frame).await;
+    }
+    let usage = exit.usage;
+    let frame = match exit.status {
+        Ok(status) if exit.timed_out => exit_frame(
+            &name,
+            "timed out",
+            proc,
+            meta::Status::Timeout,
+            status.into(),
+            usage,
+        ),
+        Ok(status) => exit_frame(
+            &name,
+            "-",
+            proc,
+            meta::Status::Complete,
+            status.into(),
+            usage,
+        ),
+        Err(e) => exit_frame(
+            &name,
+            &e,
+            proc,
+            meta::Status::Error,
+            ExitFields::NONE,
+            usage,
+        ),
+    };
+    let exited = match &frame.frame {
+        Some(Frame::Exit(exited)) => Some((exited.clone(), exit.oom_kills)),
+        _ => None,
+    };
+    let _ = tx.send(Ok(frame)).await;
+    exited
+}
+
+fn response(name: &str, message: &str, frame: Frame) -> ExecStreamResponse {
+    ExecStreamResponse {
+        meta: Some(meta::AuraeMeta {
+            name: name.to_string(),
+            message: message.to_string(),
+        }),
+        frame: Some(frame),
+    }
+}
@@ -0,0 +1,894 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::meta;
+use crate::observe::events::Events;
+use crate::observe::ProcessExited;
+use crate::runtime::cgroup::Cgroup;
+use crate::runtime::container::ContainerRoot;
+use crate::runtime::executable::ExecutableCommand;
+use crate::runtime::logs::LogWriter;
+use crate::runtime::output::{
+    capture_output, unix_timestamp, CapturedOutput, ExitFields,
+};
+use crate::runtime::usage;
+use crate::runtime::{
+    Executable, ExecutableStatus, ExecutableUsage, OutputChannel,
+};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::mem::MaybeUninit;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tonic::Status;
+
+/// How long the output of a process is drained after it exited. Anything it
+/// forked into the background may hold its pipes open for much longer.
+pub(crate) const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many finished processes a ProcessTable keeps around for their status.
+const MAX_FINISHED_PROCESSES: usize = 256;
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ProcessError {
+    #[error("executable has no name, set meta.name to track it")]
+    MissingName,
+    #[error("executable {name} is already running")]
+    AlreadyRunning { name: String },
+    #[error("executable {name} not found")]
+    NotFound { name: String },
+    #[error("failed to start executable {name}: {source}")]
+    SpawnFailure { name: String, source: anyhow::Error },
+    #[error("invalid signal {signal}")]
+    InvalidSignal { signal: String },
+    #[error("failed to signal executable {name}: {source}")]
+    SignalFailure { name: String, source: io::Error },
+}
+
+impl From<ProcessError> for Status {
+    fn from(e: ProcessError) -> Self {
+        match e {
+            ProcessError::MissingName | ProcessError::InvalidSignal { .. } => {
+                Status::invalid_argument(e.to_string())
+            }
+            ProcessError::AlreadyRunning { .. } => {
+                Status::already_exists(e.to_string())
+            }
+            ProcessError::NotFound { .. } => Status::not_found(e.to_string()),
+            ProcessError::SpawnFailure { .. }
+            | ProcessError::SignalFailure { .. } => {
+                Status::internal(e.to_string())
+            }
+        }
+    }
+}
+
+/// The captured output and exit of a process that is no longer running.
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessExit {
+    pub status: Result<ExitStatus, String>,
+    /// TimedOut is set when the process was killed because it ran past its
+    /// timeout.
+    pub timed_out: bool,
+    pub stdout: CapturedOutput,
+    pub stderr: CapturedOutput,
+    /// Usage is what the process used, if its exit was observed.
+    pub usage: Option<ExecutableUsage>,
+    /// OomKills is the number of processes in the cgroup of the process that
+    /// were killed for running out of memory.
+    pub oom_kills: u64,
+}
+
+impl ProcessExit {
+    /// The status of the exited process, a message for clients, and how it
+    /// exited.
+    fn outcome(&self) -> (meta::Status, String, ExitFields) {
+        match &self.status {
+            Ok(status) if self.timed_out => (
+                meta::Status::Timeout,
+                "timed out".to_string(),
+                (*status).into(),
+            ),
+            Ok(status) => {
+                (meta::Status::Complete, "-".to_string(), (*status).into())
+            }
+            Err(e) => (meta::Status::Error, e.clone(), ExitFields::NONE),
+        }
+    }
+}
+
+/// The state of a process as published by its waiter.
+#[derive(Debug, Clone)]
+enum ProcessState {
+    Running,
+    /// The process exited, and its output is still being drained.
+    Exited(ProcessExit),
+    /// The process exited, and its output is final.
+    Drained(ProcessExit),
+}
+
+/// A child process that has been started with its output captured.
+/// The exit is published as soon as the process has exited, and again with
+/// its output once both of its pipes are drained, or the drain timed out.
+#[derive(Debug, Clone)]
+pub(crate) struct Process {
+    pub pid: i32,
+    pub start_time: i64,
+    /// The cgroup the process runs in, if it has one of its own.
+    cgroup: Option<PathBuf>,
+    group: ProcessGroup,
+    state: watch::Receiver<ProcessState>,
+}
+
+impl Process {
+    /// Spawns `cmd` and returns as soon as the process is running.
+    /// Waiting for the process happens on the blocking thread pool.
+    ///
+    /// The process leads a new process group, so that it can be stopped
+    /// along with anything it forks.
+    pub fn spawn(
+        cmd: ExecutableCommand,
+        max_output: usize,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        Self::spawn_logged(cmd, max_output, timeout, None)
+    }
+
+    /// Spawns `cmd` like spawn, with its output written to `log` as well.
+    pub fn spawn_logged(
+        cmd: ExecutableCommand,
+        max_output: usize,
+        timeout: Option<Duration>,
+        log: Option<LogWriter>,
+    ) -> io::Result<Self> {
+        let ExecutableCommand { command: mut cmd, cgroup } = cmd;
+        let mut child = cmd
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let pid = child.id() as i32;
+        let start_time = unix_timestamp();
+        let cgroup_path = cgroup.as_ref().map(|c| c.path().to_path_buf());
+
+        let stdout = child.stdout.take().map(|r| {
+            capture(r, max_output, log.clone(), OutputChannel::Stdout)
+        });
+        let stderr = child.stderr.take().map(|r| {
+            capture(r, max_output, log.clone(), OutputChannel::Stderr)
+        });
+        let group = ProcessGroup::new(pid);
+        let (tx, rx) = watch::channel(ProcessState::Running);
+        let waited = group.clone();
+        tokio::spawn(async move {
+            let exited = wait_child(child, cgroup, timeout, waited).await;
+            let mut exit = ProcessExit {
+                status: exited.status,
+                timed_out: exited.timed_out,
+                usage: exited.usage,
+                oom_kills: exited.oom_kills,
+                stdout: CapturedOutput::default(),
+                stderr: CapturedOutput::default(),
+            };
+            let _ = tx.send(ProcessState::Exited(exit.clone()));
+            let deadline = tokio::time::Instant::now() + OUTPUT_DRAIN_TIMEOUT;
+            exit.stdout = collect(stdout, deadline).await;
+            exit.stderr = collect(stderr, deadline).await;
+            let _ = tx.send(ProcessState::Drained(exit));
+        });
+
+        Ok(Self { pid, start_time, cgroup: cgroup_path, group, state: rx })
+    }
+
+    pub fn exit(&self) -> Option<ProcessExit> {
+        match &*self.state.borrow() {
+            ProcessState::Running => None,
+            ProcessState::Exited(exit) | ProcessState::Drained(exit) => {
+                Some(exit.clone())
+            }
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(*self.state.borrow(), ProcessState::Running)
+    }
+
+    /// Waits for the process to exit, without waiting for its output.
+    pub async fn exited(&self) -> ProcessExit {
+        self.wait_for(|state| !matches!(state, ProcessState::Running)).await
+    }
+
+    /// Waits for the process to exit and for its output to be drained.
+    pub async fn wait(&self) -> ProcessExit {
+        self.wait_for(|state| matches!(state, ProcessState::Drained(_))).await
+    }
+
+    async fn wait_for(
+        &self,
+        done: impl Fn(&ProcessState) -> bool,
+    ) -> ProcessExit {
+        let mut rx = self.state.clone();
+        // The sender is only dropped after publishing the drained exit.
+        while !done(&rx.borrow()) {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+        self.exit().unwrap_or_else(|| ProcessExit {
+            status: Err("process exit was never observed".into()),
+            timed_out: false,
+            stdout: CapturedOutput::default(),
+            stderr: CapturedOutput::default(),
+            usage: None,
+            oom_kills: 0,
+        })
+    }
+
+    /// Sends `signal` to the process group, and escalates to SIGKILL if the
+    /// process has not exited after `grace_period`. Whatever is left of the
+    /// group once the process exited is killed as well, as it may hold the
+    /// pipes of the process open.
+    pub async fn stop(
+        &self,
+        signal: i32,
+        grace_period: Duration,
+    ) -> io::Result<ProcessExit> {
+        if let Some(exit) = self.exit() {
+            return Ok(exit);
+        }
+        self.group.stop(signal)?;
+        if tokio::time::timeout(grace_period, self.exited()).await.is_err() {
+            warn!(
+                "Process {} did not exit within {:?}, sending SIGKILL",
+                self.pid, grace_period
+            );
+            self.group.stop(libc::SIGKILL)?;
+        }
+        Ok(self.wait().await)
+    }
+
+    /// Publishes the start of the process as an event of the executable
+    /// `name` to `events`, and its exit once it exited.
+    pub fn publish_events(&self, name: &str, events: &Events) {
+        let proc =
+            meta::ProcessMeta { pid: self.pid, start_time: self.start_time };
+        events.publish_start(name, proc.clone());
+        let (process, name, events) =
+            (self.clone(), name.to_string(), events.clone());
+        tokio::spawn(async move {
+            let exit = process.exited().await;
+            let (status, _, fields) = exit.outcome();
+            let exited = ProcessExited {
+                proc: Some(proc),
+                status: status as i32,
+                exit_code: fields.exit_code,
+                signal: fields.signal,
+                core_dumped: fields.core_dumped,
+            };
+            events.publish_exit(&name, exited, exit.oom_kills);
+        });
+    }
+
+    pub fn status(&self, name: &str) -> ExecutableStatus {
+        let proc =
+            meta::ProcessMeta { pid: self.pid, start_time: self.start_time };
+        let exit = match self.exit() {
+            Some(exit) => exit,
+            None => {
+                return ExecutableStatus {
+                    meta: Some(meta::AuraeMeta {
+                        name: name.to_string(),
+                        message: "-".to_string(),
+                    }),
+                    proc: Some(proc),
+                    status: meta::Status::Active as i32,
+                    exit_code: ExitFields::NONE.exit_code,
+                    usage: usage::running(self.pid, self.cgroup.as_deref()),
+                    ..Default::default()
+                }
+            }
+        };
+        let (status, message, exit_fields) = exit.outcome();
+        ExecutableStatus {
+            meta: Some(meta::AuraeMeta { name: name.to_string(), message }),
+            proc: Some(proc),
+            status: status as i32,
+            stdout: exit.stdout.data,
+            stderr: exit.stderr.data,
+            stdout_truncated: exit.stdout.truncated,
+            stderr_truncated: exit.stderr.truncated,
+            exit_code: exit_fields.exit_code,
+            signal: exit_fields.signal,
+            core_dumped: exit_fields.core_dumped,
+            usage: exit.usage,
+            ..Default::default()
+        }
+    }
+}
+
+/// The status reported for an executable that could not be started at all.
+pub(crate) fn error_status(message: String) -> ExecutableStatus {
+    ExecutableStatus {
+        meta: Some(meta::AuraeMeta { name: "-".to_string(), message }),
+        proc: Some(meta::ProcessMeta { pid: -1, start_time: 0 }),
+        status: meta::Status::Error as i32,
+        exit_code: ExitFields::NONE.exit_code,
+        ..Default::default()
+    }
+}
+
+/// Sends `signal` to the process group led by `pgid`.
+pub(crate) fn kill_group(pgid: i32, signal: i32) -> io::Result<()> {
+    // A negative pid addresses the whole process group.
+    let ret = unsafe { libc::kill(-pgid, signal) };
+    let err = io::Error::last_os_error();
+    match ret {
+        // The group is already gone.
+        -1 if err.raw_os_error() == Some(libc::ESRCH) => Ok(()),
+        -1 => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// ProcessGroup is the process group led by a child. It is only signalled
+/// until its leader is reaped, as its id may be reused by any process after
+/// that.
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessGroup {
+    pgid: i32,
+    state: Arc<Mutex<GroupState>>,
+}
+
+#[derive(Debug, Default)]
+struct GroupState {
+    /// Stopping is set once the group was asked to stop, so that whatever
+    /// is left of it is killed when the leader exited.
+    stopping: bool,
+    reaped: bool,
+}
+
+impl ProcessGroup {
+    pub fn new(pgid: i32) -> Self {
+        Self { pgid, state: Arc::default() }
+    }
+
+    /// Sends `signal` to the group, and kills whatever is left of it once
+    /// its leader exited.
+    pub fn stop(&self, signal: i32) -> io::Result<()> {
+        let mut state = self.lock();
+        if state.reaped {
+            return Ok(());
+        }
+        state.stopping = true;
+        kill_group(self.pgid, signal)
+    }
+
+    /// Waits for the leader to exit, and kills the rest of the group if it
+    /// was stopped, before `reap` reaps the leader. Until then the leader is
+    /// a zombie that keeps the id of the group from being reused.
+    fn reap<T>(&self, reap: impl FnOnce() -> T) -> T {
+        let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        while unsafe {
+            libc::waitid(libc::P_PID, self.pgid as _, info.as_mut_ptr(), flags)
+        } < 0
+        {
+            // Any other error is reported by reap as well.
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                break;
+            }
+        }
+        {
+            let mut state = self.lock();
+            if state.stopping {
+                if let Err(e) = kill_group(self.pgid, libc::SIGKILL) {
+                    warn!("Failed to kill process group {}: {}", self.pgid, e);
+                }
+            }
+            state.reaped = true;
+        }
+        reap()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, GroupState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The exit of a child as observed by wait_child.
+#[derive(Debug)]
+pub(crate) struct ChildExit {
+    pub status: Result<ExitStatus, String>,
+    /// TimedOut is set when the child was killed for running past its
+    /// timeout.
+    pub timed_out: bool,
+    pub usage: Option<ExecutableUsage>,
+    /// OomKills is the number of processes in the cgroup of the child that
+    /// were killed for running out of memory.
+    pub oom_kills: u64,
+}
+
+/// Waits for a child that leads its own process group. The wait happens on
+/// the blocking thread pool, so a child never holds up the async runtime.
+/// If the child is still running after `timeout`, its whole `group` is
+/// killed. The cgroup of the child is removed once it exited, after its IO
+/// and OOM kills have been accounted for.
+pub(crate) async fn wait_child(
+    child: Child,
+    cgroup: Option<Cgroup>,
+    timeout: Option<Duration>,
+    group: ProcessGroup,
+) -> ChildExit {
+    let pid = child.id() as i32;
+    let waited = group.clone();
+    let mut wait = tokio::task::spawn_blocking(move || {
+        let exit = waited.reap(|| usage::wait(pid));
+        let io = cgroup.as_ref().and_then(Cgroup::io_bytes);
+        let oom_kills = cgroup.as_ref().map(Cgroup::oom_kills);
+        drop(cgroup);
+        drop(child);
+        exit.map(|(status, mut usage)| {
+            if let Some((read, written)) = io {
+                usage.read_bytes = read;
+                usage.write_bytes = written;
+            }
+            (status, usage, oom_kills.unwrap_or_default())
+        })
+    });
+    let mut timed_out = false;
+    let result = match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, &mut wait).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    "Process {} timed out after {:?}, killing it",
+                    pid, timeout
+                );
+                timed_out = true;
+                if let Err(e) = group.stop(libc::SIGKILL) {
+                    warn!("Failed to kill process {}: {}", pid, e);
+                }
+                wait.await
+            }
+        },
+        None => wait.await,
+    };
+    let (status, usage, oom_kills) = match result {
+        Ok(Ok((status, usage, oom_kills))) => {
+            (Ok(status), Some(usage), oom_kills)
+        }
+        Ok(Err(e)) => (Err(e.to_string()), None, 0),
+        Err(e) => (Err(e.to_string()), None, 0),
+    };
+    ChildExit { status, timed_out, usage, oom_kills }
+}
+
+/// Output of a pipe that is being read on the blocking thread pool. What
+/// was read so far can be taken without waiting for the pipe to close.
+struct Capture {
+    output: Arc<Mutex<CapturedOutput>>,
+    reader: JoinHandle<()>,
+}
+
+fn capture(
+    reader: impl Read + Send + 'static,
+    max_output: usize,
+    log: Option<LogWriter>,
+    channel: OutputChannel,
+) -> Capture {
+    let output = Arc::new(Mutex::new(CapturedOutput::default()));
+    let captured = output.clone();
+    let reader = tokio::task::spawn_blocking(move || match log {
+        Some(log) => {
+            capture_output(log.tee(reader, channel), max_output, &captured)
+        }
+        None => capture_output(reader, max_output, &captured),
+    });
+    Capture { output, reader }
+}
+
+/// Waits until the pipe is drained or `deadline` passed, and takes what was
+/// read from it.
+async fn collect(
+    capture: Option<Capture>,
+    deadline: tokio::time::Instant,
+) -> CapturedOutput {
+    let Some(Capture { output, reader }) = capture else {
+        return CapturedOutput::default();
+    };
+    let _ = tokio::time::timeout_at(deadline, reader).await;
+    let output = output.lock().unwrap_or_else(|e| e.into_inner());
+    output.clone()
+}
+
+/// Parses a signal given by name, with or without the SIG prefix, or by
+/// number.
+pub(crate) fn parse_signal(signal: &str) -> Result<i32, ProcessError> {
+    let invalid = || ProcessError::InvalidSignal { signal: signal.to_string() };
+    if let Ok(number) = signal.parse::<i32>() {
+        return match number {
+            1..=64 => Ok(number),
+            _ => Err(invalid()),
+        };
+    }
+    let name = signal.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    Ok(match name {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "ABRT" => libc::SIGABRT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "PIPE" => libc::SIGPIPE,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "WINCH" => libc::SIGWINCH,
+        _ => return Err(invalid()),
+    })
+}
+
+/// ProcessTable tracks the processes started by auraed by the name of their
+/// executable. Finished processes stay in the table until a new process is
+/// started under the same name, or until more than MAX_FINISHED_PROCESSES
+/// have finished, the oldest first.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ProcessTable {
+    processes: Arc<Mutex<HashMap<String, Process>>>,
+    /// Starting are the names reserved for processes that are being
+    /// prepared.
+    starting: Arc<Mutex<HashSet<String>>>,
+    /// Events is where the starts and exits of processes are published.
+    events: Events,
+}
+
+/// Starting holds the name of a process that is being prepared, until it is
+/// started or dropped.
+#[derive(Debug)]
+pub(crate) struct Starting<'a> {
+    table: &'a ProcessTable,
+    name: String,
+}
+
+impl Starting<'_> {
+    /// Starts `executable`, which has to have the reserved name, with `root`
+    /// set up as its root filesystem if one is given.
+    pub fn start_in(
+        self,
+        executable: &Executable,
+        root: Option<ContainerRoot>,
+    ) -> Result<ExecutableStatus, ProcessError> {
+        let reserved = executable.name() == self.name;
+        self.table.start_with(executable, root, None, reserved)
+    }
+}
+
+impl Drop for Starting<'_> {
+    fn drop(&mut self) {
+        let _ = self.table.starting().remove(&self.name);
+    }
+}
+
+impl ProcessTable {
+    pub fn new(events: Events) -> Self {
+        Self { processes: Arc::default(), starting: Arc::default(), events }
+    }
+
+    pub fn start(
+        &self,
+        executable: &Executable,
+    ) -> Result<ExecutableStatus, ProcessError> {
+        self.start_with(executable, None, None, false)
+    }
+
+    /// Reserves `name` for a process that takes a while to prepare, so that
+    /// no other process starts under the name in the meantime.
+    pub fn reserve(&self, name: &str) -> Result<Starting<'_>, ProcessError> {
+        let processes = self.lock();
+        if processes.get(name).is_some_and(Process::is_running)
+            || !self.starting().insert(name.to_string())
+        {
+            return Err(ProcessError::AlreadyRunning {
+                name: name.to_string(),
+            });
+        }
+        Ok(Starting { table: self, name: name.to_string() })
+    }
+
+    /// Starts `executable` like start, with its output written to `log` as
+    /// well.
+    pub fn start_logged(
+        &self,
+        executable: &Executable,
+        log: LogWriter,
+    ) -> Result<ExecutableStatus, ProcessError> {
+        self.start_with(executable, None, Some(log), false)
+    }
+
+    /// Starts `executable`, unless a process of its name runs, or its name
+    /// is reserved by someone other than the caller.
+    fn start_with(
+        &self,
+        executable: &Executable,
+        root: Option<ContainerRoot>,
+        log: Option<LogWriter>,
+        reserved: bool,
+    ) -> Result<ExecutableStatus, ProcessError> {
+        let name = executable.name();
+        if name.is_empty() {
+            return Err(ProcessError::MissingName);
+        }
+        let mut processes = self.lock();
+        if processes.get(name).is_some_and(Process::is_running)
+            || (!reserved && self.starting().contains(name))
+        {
+            return Err(ProcessError::AlreadyRunning {
+                name: name.to_string(),
+            });
+        }
+        let process = executable
+            .to_command_in(root)
+            .and_then(|cmd| {
+                Process::spawn_logged(
+                    cmd,
+                    executable.max_output(),
+                    executable.timeout(),
+                    log,
+                )
+                .map_err(anyhow::Error::from)
+            })
+            .map_err(|e| ProcessError::SpawnFailure {
+                name: name.to_string(),
+                source: e,
+            })?;
+        process.publish_events(name, &self.events);
+        let status = process.status(name);
+        let _ = processes.insert(name.to_string(), process);
+        evict_finished(&mut processes);
+        Ok(status)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Process, ProcessError> {
+        self.lock()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ProcessError::NotFound { name: name.to_string() })
+    }
+
+    pub async fn wait(
+        &self,
+        name: &str,
+    ) -> Result<ExecutableStatus, ProcessError> {
+        let process = self.get(name)?;
+        let _ = process.wait().await;
+        Ok(process.status(name))
+    }
+
+    pub async fn stop(
+        &self,
+        name: &str,
+        signal: i32,
+        grace_period: Duration,
+    ) -> Result<ExecutableStatus, ProcessError> {
+        let process = self.get(name)?;
+        let _ = process.stop(signal, grace_period).await.map_err(|e| {
+            ProcessError::SignalFailure { name: name.to_string(), source: e }
+        })?;
+        Ok(process.status(name))
+    }
+
+    pub fn list(&self) -> Vec<ExecutableStatus> {
+        let processes = self.lock();
+        let mut names: Vec<&String> = processes.keys().collect();
+        names.sort();
+        names.into_iter().map(|name| processes[name].status(name)).collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Process>> {
+        // A panic while holding the lock cannot leave the map half updated.
+        self.processes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Taken after lock when both are held.
+    fn starting(&self) -> MutexGuard<'_, HashSet<String>> {
+        self.starting.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Removes the oldest finished processes until at most
+/// MAX_FINISHED_PROCESSES are left.
+fn evict_finished(processes: &mut HashMap<String, Process>) {
+    let mut finished: Vec<(i64, String)> = processes
+        .iter()
+        .filter(|(_, p)| !p.is_running())
+        .map(|(name, p)| (p.start_time, name.clone()))
+        .collect();
+    if finished.len() <= MAX_FINISHED_PROCESSES {
+        return;
+    }
+    finished.sort();
+    let evict = finished.len() - MAX_FINISHED_PROCESSES;
+    for (_, name) in finished.into_iter().take(evict) {
+        let _ = processes.remove(&name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executable(name: &str, command: &str, args: &[&str]) -> Executable {
+        Executable {
+            meta: Some(meta::AuraeMeta {
+                name: name.to_string(),
+                message: String::new(),
+            }),
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_table_start_wait() {
+        let table = ProcessTable::default();
+        let started = table
+            .start(&executable("sleepy", "sleep 0.2", &[]))
+            .expect("start");
+        assert_eq!(started.status, meta::Status::Active as i32);
+        assert!(started.proc.as_ref().expect("proc").pid > 0);
+        assert_eq!(started.usage.expect("running usage").threads, 1);
+        assert!(matches!(
+            table.start(&executable("sleepy", "true", &[])),
+            Err(ProcessError::AlreadyRunning { .. })
+        ));
+
+        let finished = table.wait("sleepy").await.expect("wait");
+        assert_eq!(finished.status, meta::Status::Complete as i32);
+        assert_eq!(finished.proc, started.proc);
+        assert_eq!(finished.exit_code, 0);
+        assert!(finished.usage.expect("usage").peak_rss_bytes > 0);
+        assert_eq!(table.list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reserved_names_are_not_started_by_others() {
+        let table = ProcessTable::default();
+        let starting = table.reserve("box").expect("reserve");
+        assert!(table.reserve("box").is_err());
+        assert!(matches!(
+            table.start(&executable("box", "true", &[])),
+            Err(ProcessError::AlreadyRunning { .. })
+        ));
+        let _ = starting
+            .start_in(&executable("box", "sleep 0.2", &[]), None)
+            .expect("start reserved");
+        assert!(table.reserve("box").is_err());
+        let _ = table.wait("box").await.expect("wait");
+        drop(table.reserve("box").expect("reserve again"));
+    }
+
+    #[tokio::test]
+    async fn test_process_output_is_binary_safe_and_capped() {
+        let mut binary = executable("binary", "printf", &["\\377\\376abc"]);
+        binary.max_output_bytes = 3;
+        let table = ProcessTable::default();
+        let _ = table.start(&binary).expect("start");
+        let finished = table.wait("binary").await.expect("wait");
+        assert_eq!(finished.stdout, b"\xff\xfea");
+        assert!(finished.stdout_truncated);
+        assert!(!finished.stderr_truncated);
+    }
+
+    #[tokio::test]
+    async fn test_process_exit_does_not_wait_for_background_children() {
+        let table = ProcessTable::default();
+        // The backgrounded sleep inherits the pipes of the shell.
+        let forking =
+            executable("forking", "sh", &["-c", "echo started; sleep 30 &"]);
+        let started = table.start(&forking).expect("start");
+        let finished = tokio::time::timeout(
+            OUTPUT_DRAIN_TIMEOUT * 3,
+            table.wait("forking"),
+        )
+        .await
+        .expect("wait returns while the pipes are still open")
+        .expect("wait");
+        assert_eq!(finished.status, meta::Status::Complete as i32);
+        assert_eq!(finished.stdout, b"started\n");
+        let pid = started.proc.expect("proc").pid;
+        kill_group(pid, libc::SIGKILL).expect("kill background child");
+    }
+
+    #[tokio::test]
+    async fn test_process_table_stop_kills_group() {
+        let table = ProcessTable::default();
+        // The shell ignores SIGTERM, and so does the grandchild it forks.
+        let stubborn = executable(
+            "stubborn",
+            "sh",
+            &["-c", "trap '' TERM; sleep 30 & wait"],
+        );
+        let _ = table.start(&stubborn).expect("start");
+        // Give the shell a moment to install its trap.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stopped = table
+            .stop("stubborn", libc::SIGTERM, Duration::from_millis(200))
+            .await
+            .expect("stop");
+        assert_eq!(stopped.status, meta::Status::Complete as i32);
+        assert_eq!(stopped.exit_code, -1);
+        assert_eq!(stopped.signal, libc::SIGKILL);
+    }
+
+    #[tokio::test]
+    async fn test_process_table_stop_kills_rest_of_group() {
+        let table = ProcessTable::default();
+        // The shell exits on SIGTERM, the grandchild it forks ignores it.
+        let leaving = executable(
+            "leaving",
+            "sh",
+            &["-c", "sh -c 'trap \"\" TERM; echo $$; exec sleep 30' & wait"],
+        );
+        let _ = table.start(&leaving).expect("start");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stopped = table
+            .stop("leaving", libc::SIGTERM, Duration::from_secs(5))
+            .await
+            .expect("stop");
+        assert_eq!(stopped.signal, libc::SIGTERM);
+        let grandchild = String::from_utf8_lossy(&stopped.stdout);
+        let stat = format!("/proc/{}/stat", grandchild.trim());
+        // Once killed, the grandchild is at most a zombie of its new parent.
+        let alive = std::fs::read_to_string(stat)
+            .is_ok_and(|stat| !stat.contains(") Z "));
+        assert!(!alive, "grandchild {} survived", grandchild);
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGTERM").ok(), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("kill").ok(), Some(libc::SIGKILL));
+        assert_eq!(parse_signal("2").ok(), Some(libc::SIGINT));
+        assert!(parse_signal("SIGNOPE").is_err());
+        assert!(parse_signal("0").is_err());
+    }
+}
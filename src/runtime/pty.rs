@@ -0,0 +1,108 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::executable::check;
+use crate::runtime::WindowSize;
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize { rows: 24, columns: 80 };
+
+/// Pty is a pseudo terminal, made of the master side kept by auraed and the
+/// slave side handed to the executable.
+pub(crate) struct Pty {
+    pub master: File,
+    slave: File,
+}
+
+impl Pty {
+    pub fn open(size: Option<&WindowSize>) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+        check(unsafe { libc::grantpt(fd) })?;
+        check(unsafe { libc::unlockpt(fd) })?;
+
+        let mut name = [0 as libc::c_char; 128];
+        let ret = unsafe { libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(name.to_string_lossy().as_ref())?;
+
+        resize(&master, size.unwrap_or(&DEFAULT_WINDOW_SIZE))?;
+        Ok(Self { master, slave })
+    }
+
+    /// Attaches `cmd` to the slave side of the terminal. The executable
+    /// leads a new session with the terminal as its controlling terminal,
+    /// which also makes it the leader of a new process group.
+    pub fn attach(&self, cmd: &mut Command) -> io::Result<()> {
+        let _ = cmd
+            .stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave.try_clone()?));
+        unsafe {
+            let _ = cmd.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                check(libc::ioctl(0, libc::TIOCSCTTY, 0))
+            });
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn resize(master: &File, size: &WindowSize) -> io::Result<()> {
+    let winsize = libc::winsize {
+        ws_row: u16::try_from(size.rows).unwrap_or(u16::MAX),
+        ws_col: u16::try_from(size.columns).unwrap_or(u16::MAX),
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    check(unsafe {
+        libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &winsize)
+    })
+}
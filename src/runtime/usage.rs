@@ -0,0 +1,261 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::{cgroup, ExecutableUsage};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::{fs, io};
+
+/// Waits for the child `pid` to exit, and returns its exit along with the
+/// resources it and the children it waited for used.
+pub(crate) fn wait(pid: i32) -> io::Result<(ExitStatus, ExecutableUsage)> {
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) };
+        if ret == pid {
+            return Ok((ExitStatus::from_raw(status), exited(&rusage)));
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn exited(rusage: &libc::rusage) -> ExecutableUsage {
+    let micros =
+        |t: libc::timeval| t.tv_sec as u64 * 1_000_000 + t.tv_usec as u64;
+    ExecutableUsage {
+        user_cpu_us: micros(rusage.ru_utime),
+        system_cpu_us: micros(rusage.ru_stime),
+        // Linux reports the peak RSS in kibibytes.
+        peak_rss_bytes: rusage.ru_maxrss as u64 * 1024,
+        // Block IO is counted in 512 byte units.
+        read_bytes: rusage.ru_inblock as u64 * 512,
+        write_bytes: rusage.ru_oublock as u64 * 512,
+        voluntary_context_switches: rusage.ru_nvcsw as u64,
+        involuntary_context_switches: rusage.ru_nivcsw as u64,
+        threads: 0,
+    }
+}
+
+/// The usage of the running executable started as `pid` so far. Executables
+/// with a cgroup are accounted by their cgroup, which covers everything they
+/// forked. Otherwise, and for what a cgroup does not count, the executable
+/// itself is read from /proc. None once the process is gone.
+pub(crate) fn running(
+    pid: i32,
+    cgroup: Option<&Path>,
+) -> Option<ExecutableUsage> {
+    let mut usage = process(executable(pid))?;
+    if let Some(cgroup) = cgroup {
+        from_cgroup(cgroup, &mut usage);
+    }
+    Some(usage)
+}
+
+/// The process that runs the executable started as `pid`. An executable in a
+/// PID namespace of its own is forked by an intermediate process, which only
+/// stays behind to wait for it.
+fn executable(pid: i32) -> i32 {
+    let namespace = |name: &str| {
+        fs::metadata(format!("/proc/{}/ns/{}", pid, name)).map(|m| m.ino()).ok()
+    };
+    if namespace("pid") == namespace("pid_for_children") {
+        return pid;
+    }
+    // Until the intermediate process forked, it is all there is to read.
+    child(pid).unwrap_or(pid)
+}
+
+/// A child of the process `pid`, found by the parents in /proc.
+fn child(pid: i32) -> Option<i32> {
+    fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        let child: i32 = entry.file_name().to_str()?.parse().ok()?;
+        let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+        (parse_ppid(&stat)? == pid).then_some(child)
+    })
+}
+
+fn process(pid: i32) -> Option<ExecutableUsage> {
+    let proc = format!("/proc/{}", pid);
+    let stat = fs::read_to_string(format!("{}/stat", proc)).ok()?;
+    let status = fs::read_to_string(format!("{}/status", proc)).ok()?;
+    let mut usage = ExecutableUsage::default();
+    parse_stat(&stat, &mut usage)?;
+    parse_status(&status, &mut usage);
+    // The IO counters are only readable with the right to trace the process.
+    if let Ok(io) = fs::read_to_string(format!("{}/io", proc)) {
+        parse_io(&io, &mut usage);
+    }
+    Some(usage)
+}
+
+/// Replaces the CPU time, memory, threads and IO in `usage` with those of
+/// the cgroup at `path`, as far as its controllers report them.
+fn from_cgroup(path: &Path, usage: &mut ExecutableUsage) {
+    let read = |file: &str| fs::read_to_string(path.join(file)).ok();
+    if let Some(stat) = read("cpu.stat") {
+        parse_cpu_stat(&stat, usage);
+    }
+    // memory.peak is only there from Linux 5.19 on.
+    let memory = read("memory.peak").or_else(|| read("memory.current"));
+    if let Some(bytes) = memory.and_then(|m| m.trim().parse().ok()) {
+        usage.peak_rss_bytes = bytes;
+    }
+    if let Some(tasks) =
+        read("pids.current").and_then(|p| p.trim().parse().ok())
+    {
+        usage.threads = tasks;
+    }
+    if let Some((read, written)) = cgroup::io_bytes(path) {
+        usage.read_bytes = read;
+        usage.write_bytes = written;
+    }
+}
+
+fn parse_cpu_stat(stat: &str, usage: &mut ExecutableUsage) {
+    for line in stat.lines() {
+        match line.split_once(' ') {
+            Some(("user_usec", value)) => {
+                usage.user_cpu_us = value.trim().parse().unwrap_or_default()
+            }
+            Some(("system_usec", value)) => {
+                usage.system_cpu_us = value.trim().parse().unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reads the parent from a stat file, the 4th field.
+fn parse_ppid(stat: &str) -> Option<i32> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Reads the CPU time and threads from a stat file. The name in the 2nd
+/// field may hold spaces and parentheses itself, so fields are counted from
+/// its closing parenthesis.
+fn parse_stat(stat: &str, usage: &mut ExecutableUsage) -> Option<()> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    // utime, stime and num_threads are the 14th, 15th and 20th fields.
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    let ticks = if ticks > 0 { ticks as u64 } else { 100 };
+    usage.user_cpu_us = utime * 1_000_000 / ticks;
+    usage.system_cpu_us = stime * 1_000_000 / ticks;
+    usage.threads = fields.get(17)?.parse().ok()?;
+    Some(())
+}
+
+fn parse_status(status: &str, usage: &mut ExecutableUsage) {
+    for line in status.lines() {
+        let (key, value) = match line.split_once(':') {
+            Some(field) => field,
+            None => continue,
+        };
+        let value = value.trim().trim_end_matches("kB").trim();
+        let value: u64 = value.parse().unwrap_or_default();
+        match key {
+            "VmHWM" => usage.peak_rss_bytes = value * 1024,
+            "voluntary_ctxt_switches" => {
+                usage.voluntary_context_switches = value
+            }
+            "nonvoluntary_ctxt_switches" => {
+                usage.involuntary_context_switches = value
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_io(io: &str, usage: &mut ExecutableUsage) {
+    for line in io.lines() {
+        match line.split_once(": ") {
+            Some(("read_bytes", value)) => {
+                usage.read_bytes = value.trim().parse().unwrap_or_default()
+            }
+            Some(("write_bytes", value)) => {
+                usage.write_bytes = value.trim().parse().unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc() {
+        let mut usage = ExecutableUsage::default();
+        let stat = "42 (a b) c) S 1 42 42 0 -1 4194560 100 0 0 0 300 100 0 0 20 0 3 0 12345";
+        parse_stat(stat, &mut usage).expect("stat");
+        assert_eq!(parse_ppid(stat), Some(1));
+        assert_eq!(usage.threads, 3);
+        assert!(usage.user_cpu_us > usage.system_cpu_us);
+
+        parse_status(
+            "Name:\tsleep\nVmHWM:\t    2048 kB\nvoluntary_ctxt_switches:\t5\nnonvoluntary_ctxt_switches:\t2\n",
+            &mut usage,
+        );
+        assert_eq!(usage.peak_rss_bytes, 2 << 20);
+        assert_eq!(
+            (
+                usage.voluntary_context_switches,
+                usage.involuntary_context_switches
+            ),
+            (5, 2)
+        );
+
+        parse_io(
+            "rchar: 10\nread_bytes: 4096\nwrite_bytes: 8192\n",
+            &mut usage,
+        );
+        assert_eq!((usage.read_bytes, usage.write_bytes), (4096, 8192));
+    }
+
+    #[test]
+    fn test_parse_cpu_stat() {
+        let mut usage = ExecutableUsage::default();
+        parse_cpu_stat(
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\nnr_periods 0\n",
+            &mut usage,
+        );
+        assert_eq!((usage.user_cpu_us, usage.system_cpu_us), (1000, 500));
+    }
+}
@@ -0,0 +1,273 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+//! Cron expressions of the five classic fields, evaluated in UTC.
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Days searched for the next match of an expression. Covers the leap
+/// years of expressions firing on February 29th only.
+const SEARCH_DAYS: i64 = 8 * 366;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct",
+    "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub(crate) enum CronError {
+    #[error("cron expression needs 5 fields, found {0}")]
+    FieldCount(usize),
+    #[error(
+        "invalid cron field {field:?}, expected values from {min} to {max}"
+    )]
+    InvalidField { field: String, min: u32, max: u32 },
+}
+
+/// A parsed cron expression, such as `*/15 2-4 * * mon-fri`. Every field is
+/// kept as a bit set of the values it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Days and weekdays match when either matches, unless one of them is
+    /// a `*`, as in every cron since Vixie cron.
+    any_day: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, CronError> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(CronError::FieldCount(fields.len()));
+        }
+        let mut weekdays = field(fields[4], 0, 7, &WEEKDAYS)?;
+        // Sunday is both 0 and 7.
+        if weekdays & 1 << 7 != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: field(fields[0], 0, 59, &[])?,
+            hours: field(fields[1], 0, 23, &[])?,
+            days: field(fields[2], 1, 31, &[])?,
+            months: field(fields[3], 1, 12, &MONTHS)?,
+            weekdays,
+            any_day: fields[2].starts_with('*') || fields[4].starts_with('*'),
+        })
+    }
+
+    /// The first time matching the expression after `after`, both in
+    /// seconds since the Unix epoch.
+    pub fn next(&self, after: i64) -> Option<i64> {
+        // Matches are whole minutes, and strictly after `after`.
+        let start = (after.div_euclid(60) + 1) * 60;
+        let first_day = start.div_euclid(SECONDS_PER_DAY);
+        for day in first_day..first_day + SEARCH_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let midnight = day * SECONDS_PER_DAY;
+            for hour in 0..24 {
+                if self.hours & 1 << hour == 0 {
+                    continue;
+                }
+                for minute in 0..60 {
+                    let time = midnight + hour * 3600 + minute * 60;
+                    if self.minutes & 1 << minute != 0 && time >= start {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        // The Unix epoch was a Thursday.
+        let weekday = (day + 4).rem_euclid(7);
+        let day_matches = self.days & 1 << day_of_month != 0;
+        let weekday_matches = self.weekdays & 1 << weekday != 0;
+        if self.any_day {
+            day_matches && weekday_matches
+        } else {
+            day_matches || weekday_matches
+        }
+    }
+}
+
+/// Parses a comma separated list of values, ranges and steps into a bit set.
+fn field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+) -> Result<u64, CronError> {
+    let invalid =
+        || CronError::InvalidField { field: field.to_string(), min, max };
+    let value = |v: &str| -> Result<u32, CronError> {
+        let v = v.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == v) {
+            // Names count from 1 for months, and from 0 for weekdays.
+            Some(i) => i as u32 + min,
+            None => v.parse().map_err(|_| invalid())?,
+        };
+        if value < min || value > max {
+            return Err(invalid());
+        }
+        Ok(value)
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                (range, step.parse::<u32>().map_err(|_| invalid())?)
+            }
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // A single value with a step runs to the end of the range.
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for v in (first..=last).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+/// Converts days since the Unix epoch into a year, month and day, after
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2022-11-01T00:00:00Z, a Tuesday.
+    const NOV_1_2022: i64 = 1_667_260_800;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(
+            civil_from_days(NOV_1_2022 / SECONDS_PER_DAY),
+            (2022, 11, 1)
+        );
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn test_next() {
+        let next = |expression: &str, after: i64| {
+            Cron::parse(expression)
+                .expect("parse")
+                .next(after)
+                .map(|t| t - NOV_1_2022)
+        };
+        assert_eq!(next("* * * * *", NOV_1_2022), Some(60));
+        assert_eq!(next("* * * * *", NOV_1_2022 + 59), Some(60));
+        assert_eq!(next("*/15 * * * *", NOV_1_2022 + 60), Some(15 * 60));
+        assert_eq!(next("30 2 * * *", NOV_1_2022), Some(2 * 3600 + 1800));
+        // The next Saturday, by number and by name, and on Sunday as 7.
+        assert_eq!(next("0 0 * * 6", NOV_1_2022), Some(4 * SECONDS_PER_DAY));
+        assert_eq!(next("0 0 * * SAT", NOV_1_2022), Some(4 * SECONDS_PER_DAY));
+        assert_eq!(next("0 0 * * 7", NOV_1_2022), Some(5 * SECONDS_PER_DAY));
+        // Day of month or weekday, whichever comes first.
+        assert_eq!(next("0 0 3 * 6", NOV_1_2022), Some(2 * SECONDS_PER_DAY));
+        assert_eq!(next("@monthly", NOV_1_2022), Some(30 * SECONDS_PER_DAY));
+        assert_eq!(next("0 0 1 jan *", NOV_1_2022), Some(61 * SECONDS_PER_DAY));
+        // 2024-02-29 is the next February 29th.
+        assert_eq!(
+            next("0 0 29 2 *", NOV_1_2022),
+            Some(19_782 * SECONDS_PER_DAY - NOV_1_2022)
+        );
+        assert_eq!(next("0 0 31 2 *", NOV_1_2022), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Cron::parse("* * *"), Err(CronError::FieldCount(3)));
+        for expression in [
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            assert!(
+                matches!(
+                    Cron::parse(expression),
+                    Err(CronError::InvalidField { .. })
+                ),
+                "{}",
+                expression
+            );
+        }
+    }
+}
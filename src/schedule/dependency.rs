@@ -0,0 +1,164 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::{Executable, ExecutableDependency};
+use crate::schedule::store::ScheduleError;
+use std::collections::{BTreeMap, HashSet};
+
+/// The dependencies of `executable`, each along with whether it is
+/// required.
+pub(crate) fn dependencies(
+    executable: &Executable,
+) -> impl Iterator<Item = (&ExecutableDependency, bool)> {
+    let after = executable.after.iter().map(|d| (d, false));
+    after.chain(executable.requires.iter().map(|d| (d, true)))
+}
+
+/// Fails when the dependencies of `executables` form a cycle. Dependencies
+/// on executables that are not among them are ignored.
+pub(crate) fn check_cycles(
+    executables: &[Executable],
+) -> Result<(), ScheduleError> {
+    let _ = start_order(executables)?;
+    Ok(())
+}
+
+/// Orders `executables` so that every executable comes after the
+/// executables it depends on. Executables that do not depend on each other
+/// are ordered by name.
+pub(crate) fn start_order(
+    executables: &[Executable],
+) -> Result<Vec<&Executable>, ScheduleError> {
+    let by_name: BTreeMap<&str, &Executable> =
+        executables.iter().map(|e| (e.name(), e)).collect();
+    let mut ordered = Vec::with_capacity(by_name.len());
+    let mut done = HashSet::new();
+    for name in by_name.keys() {
+        visit(name, &by_name, &mut done, &mut Vec::new(), &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+/// Orders `name` after its dependencies, depth first. `path` holds the
+/// executables being visited, which `name` must not be among.
+fn visit<'a>(
+    name: &'a str,
+    by_name: &BTreeMap<&'a str, &'a Executable>,
+    done: &mut HashSet<&'a str>,
+    path: &mut Vec<&'a str>,
+    ordered: &mut Vec<&'a Executable>,
+) -> Result<(), ScheduleError> {
+    if done.contains(name) {
+        return Ok(());
+    }
+    let executable = match by_name.get(name) {
+        Some(executable) => *executable,
+        None => return Ok(()),
+    };
+    if let Some(start) = path.iter().position(|n| *n == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name);
+        return Err(ScheduleError::DependencyCycle(cycle.join(" -> ")));
+    }
+    path.push(name);
+    let mut names: Vec<&str> =
+        dependencies(executable).map(|(d, _)| d.name.as_str()).collect();
+    names.sort_unstable();
+    for dependency in names {
+        visit(dependency, by_name, done, path, ordered)?;
+    }
+    let _ = path.pop();
+    let _ = done.insert(name);
+    ordered.push(executable);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta;
+
+    fn executable(name: &str, after: &[&str], requires: &[&str]) -> Executable {
+        let dependencies = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| ExecutableDependency {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect()
+        };
+        Executable {
+            meta: Some(meta::AuraeMeta {
+                name: name.to_string(),
+                message: String::new(),
+            }),
+            after: dependencies(after),
+            requires: dependencies(requires),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_start_order() {
+        let executables = [
+            executable("app", &["cache"], &["db"]),
+            executable("cache", &["network"], &[]),
+            executable("db", &[], &["network", "missing"]),
+            executable("network", &[], &[]),
+            executable("cron", &[], &[]),
+        ];
+        let order: Vec<&str> = start_order(&executables)
+            .expect("order")
+            .iter()
+            .map(|e| e.name())
+            .collect();
+        assert_eq!(order, ["network", "cache", "db", "app", "cron"]);
+    }
+
+    #[test]
+    fn test_cycles() {
+        let executables = [
+            executable("a", &["b"], &[]),
+            executable("b", &[], &["c"]),
+            executable("c", &["a"], &[]),
+        ];
+        match check_cycles(&executables) {
+            Err(ScheduleError::DependencyCycle(cycle)) => {
+                assert_eq!(cycle, "a -> b -> c -> a")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            check_cycles(&[executable("self", &["self"], &[])]),
+            Err(ScheduleError::DependencyCycle(_))
+        ));
+    }
+}
@@ -0,0 +1,697 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+//! Manifests are TOML or YAML files describing the executables the schedule
+//! subsystem should know about. The scheduler is reconciled against the
+//! manifests of a directory at startup and whenever the directory changes.
+//!
+//! ```toml
+//! [[executables]]
+//! name = "web"
+//! command = "/usr/bin/web --port 8080"
+//! restart = { policy = "on-failure" }
+//! requires = ["db"]
+//! readiness_probe = { http = { port = 8080, path = "/healthz" } }
+//! ```
+
+use crate::meta;
+use crate::runtime::{
+    executable_probe, ConcurrencyPolicy, DependencyCondition, Executable,
+    ExecutableDependency, ExecutableNamespaces, ExecutableProbe,
+    ExecutableResources, ExecutableRestart, ExecutableSchedule, ExecutableUser,
+    HttpProbe, RestartPolicy, TcpProbe,
+};
+use crate::schedule::dependency;
+use crate::schedule::scheduler::Scheduler;
+use crate::schedule::store::ScheduleError;
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the manifest directory is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ManifestError {
+    #[error("failed to read {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid manifest {path}: {message}")]
+    Invalid { path: PathBuf, message: String },
+    #[error("executable {name} of {path} is already defined in {first}")]
+    Duplicate { name: String, path: PathBuf, first: PathBuf },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    executables: Vec<ManifestExecutable>,
+}
+
+/// An executable as it is spelled out in a manifest. The fields follow the
+/// fields of runtime.Executable.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestExecutable {
+    name: String,
+    command: String,
+    #[serde(default)]
+    comment: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    argv: bool,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    clear_env: bool,
+    #[serde(default)]
+    cwd: String,
+    user: Option<ExecutableUser>,
+    #[serde(default)]
+    umask: String,
+    #[serde(default)]
+    max_output_bytes: u64,
+    #[serde(default)]
+    timeout_ms: u64,
+    resources: Option<ExecutableResources>,
+    namespaces: Option<ExecutableNamespaces>,
+    restart: Option<ManifestRestart>,
+    schedule: Option<ManifestSchedule>,
+    #[serde(default)]
+    after: Vec<ManifestDependency>,
+    #[serde(default)]
+    requires: Vec<ManifestDependency>,
+    liveness_probe: Option<ManifestProbe>,
+    readiness_probe: Option<ManifestProbe>,
+    /// Enabled executables are started, disabled ones are only kept on
+    /// record. Defaults to true.
+    #[serde(default = "enabled")]
+    enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestRestart {
+    policy: ManifestRestartPolicy,
+    backoff_ms: u64,
+    max_backoff_ms: u64,
+    max_restarts: u32,
+    reset_after_ms: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ManifestRestartPolicy {
+    #[default]
+    Always,
+    OnFailure,
+    Never,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestSchedule {
+    cron: String,
+    interval_ms: u64,
+    jitter_ms: u64,
+    concurrency: ManifestConcurrency,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ManifestConcurrency {
+    #[default]
+    Allow,
+    Forbid,
+    Replace,
+}
+
+/// A dependency, either just the name of the executable depended on being
+/// started, or a table with a name and a condition.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ManifestDependency {
+    Started(String),
+    Condition {
+        name: String,
+        #[serde(default)]
+        condition: ManifestCondition,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ManifestCondition {
+    #[default]
+    Started,
+    Completed,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ManifestProbe {
+    command: Option<String>,
+    tcp: Option<TcpProbe>,
+    http: Option<HttpProbe>,
+    initial_delay_ms: u64,
+    period_ms: u64,
+    timeout_ms: u64,
+    failure_threshold: u32,
+}
+
+impl ManifestExecutable {
+    fn into_executable(self) -> Result<(Executable, bool), String> {
+        if self.name.is_empty() {
+            return Err("executable without a name".to_string());
+        }
+        let restart = self.restart.map(|r| ExecutableRestart {
+            policy: match r.policy {
+                ManifestRestartPolicy::Always => RestartPolicy::Always,
+                ManifestRestartPolicy::OnFailure => RestartPolicy::OnFailure,
+                ManifestRestartPolicy::Never => RestartPolicy::Never,
+            } as i32,
+            backoff_ms: r.backoff_ms,
+            max_backoff_ms: r.max_backoff_ms,
+            max_restarts: r.max_restarts,
+            reset_after_ms: r.reset_after_ms,
+        });
+        let schedule = self.schedule.map(|s| ExecutableSchedule {
+            cron: s.cron,
+            interval_ms: s.interval_ms,
+            jitter_ms: s.jitter_ms,
+            concurrency: match s.concurrency {
+                ManifestConcurrency::Allow => ConcurrencyPolicy::Allow,
+                ManifestConcurrency::Forbid => ConcurrencyPolicy::Forbid,
+                ManifestConcurrency::Replace => ConcurrencyPolicy::Replace,
+            } as i32,
+        });
+        let probe = |probe: Option<ManifestProbe>, kind: &str| {
+            probe.map(|p| p.into_probe(kind)).transpose()
+        };
+        let executable = Executable {
+            meta: Some(meta::AuraeMeta {
+                name: self.name.clone(),
+                message: String::new(),
+            }),
+            command: self.command,
+            comment: self.comment,
+            args: self.args,
+            argv: self.argv,
+            env: self.env,
+            clear_env: self.clear_env,
+            cwd: self.cwd,
+            user: self.user,
+            umask: self.umask,
+            max_output_bytes: self.max_output_bytes,
+            timeout_ms: self.timeout_ms,
+            resources: self.resources,
+            namespaces: self.namespaces,
+            restart,
+            schedule,
+            after: self.after.into_iter().map(dependency).collect(),
+            requires: self.requires.into_iter().map(dependency).collect(),
+            liveness_probe: probe(self.liveness_probe, "liveness_probe")?,
+            readiness_probe: probe(self.readiness_probe, "readiness_probe")?,
+        };
+        Ok((executable, self.enabled))
+    }
+}
+
+fn dependency(dependency: ManifestDependency) -> ExecutableDependency {
+    let (name, condition) = match dependency {
+        ManifestDependency::Started(name) => (name, ManifestCondition::Started),
+        ManifestDependency::Condition { name, condition } => (name, condition),
+    };
+    ExecutableDependency {
+        name,
+        condition: match condition {
+            ManifestCondition::Started => DependencyCondition::Started,
+            ManifestCondition::Completed => DependencyCondition::Completed,
+        } as i32,
+    }
+}
+
+impl ManifestProbe {
+    fn into_probe(self, kind: &str) -> Result<ExecutableProbe, String> {
+        let probe = match (self.command, self.tcp, self.http) {
+            (Some(command), None, None) => {
+                executable_probe::Probe::Command(command)
+            }
+            (None, Some(tcp), None) => executable_probe::Probe::Tcp(tcp),
+            (None, None, Some(http)) => executable_probe::Probe::Http(http),
+            _ => {
+                return Err(format!(
+                    "{} needs exactly one of command, tcp or http",
+                    kind
+                ))
+            }
+        };
+        Ok(ExecutableProbe {
+            probe: Some(probe),
+            initial_delay_ms: self.initial_delay_ms,
+            period_ms: self.period_ms,
+            timeout_ms: self.timeout_ms,
+            failure_threshold: self.failure_threshold,
+        })
+    }
+}
+
+/// An executable the manifests ask for.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Desired {
+    pub executable: Executable,
+    pub enabled: bool,
+    pub manifest: String,
+}
+
+/// The executables manifests ask for by name, along with the manifests that
+/// failed to load.
+pub(crate) type Loaded =
+    (BTreeMap<String, Desired>, Vec<(PathBuf, ManifestError)>);
+
+/// The executables the manifests of `dir` ask for, by name. Manifests that
+/// cannot be read are reported, and returned so that the executables they
+/// defined before are left alone. Fails if `dir` cannot be listed, as which
+/// manifests are gone is not known then.
+pub(crate) fn load(dir: &Path) -> Result<Loaded, ManifestError> {
+    let mut desired: BTreeMap<String, Desired> = BTreeMap::new();
+    let mut failed = Vec::new();
+    let paths = manifest_paths(dir).map_err(|source| ManifestError::Read {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    for path in paths {
+        let executables = match parse(&path) {
+            Ok(executables) => executables,
+            Err(e) => {
+                failed.push((path, e));
+                continue;
+            }
+        };
+        let manifest = path.display().to_string();
+        for (executable, enabled) in executables {
+            let name = executable.name().to_string();
+            if let Some(first) = desired.get(&name) {
+                let e = ManifestError::Duplicate {
+                    name,
+                    path: path.clone(),
+                    first: PathBuf::from(&first.manifest),
+                };
+                failed.push((path.clone(), e));
+                continue;
+            }
+            let _ = desired.insert(
+                name,
+                Desired { executable, enabled, manifest: manifest.clone() },
+            );
+        }
+    }
+    Ok((desired, failed))
+}
+
+/// The manifest files of `dir`, in order. A directory that does not exist
+/// has none.
+fn manifest_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(paths),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let manifest = matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("toml" | "yaml" | "yml")
+        );
+        if manifest && !hidden && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+fn parse(path: &Path) -> Result<Vec<(Executable, bool)>, ManifestError> {
+    let invalid = |message: String| ManifestError::Invalid {
+        path: path.to_path_buf(),
+        message,
+    };
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        ManifestError::Read { path: path.to_path_buf(), source: e }
+    })?;
+    let manifest: Manifest = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => {
+            toml::from_str(&content).map_err(|e| invalid(e.to_string()))?
+        }
+        // An empty YAML document is an empty manifest.
+        _ if content.trim().is_empty() => Manifest::default(),
+        _ => serde_yaml::from_str(&content)
+            .map_err(|e| invalid(e.to_string()))?,
+    };
+    manifest
+        .executables
+        .into_iter()
+        .map(|e| e.into_executable().map_err(invalid))
+        .collect()
+}
+
+/// What reconciling the scheduler against the manifests changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Diff {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Diff {
+    fn log(&self) {
+        for name in &self.created {
+            info!("Manifests: + {}", name);
+        }
+        for name in &self.updated {
+            info!("Manifests: ~ {}", name);
+        }
+        for name in &self.removed {
+            info!("Manifests: - {}", name);
+        }
+    }
+}
+
+/// Creates, updates and removes executables of `scheduler` to match the
+/// manifests of `dir`. Executables that were not taken from a manifest are
+/// left alone, unless a manifest defines an executable of the same name.
+pub(crate) async fn reconcile(
+    scheduler: &Scheduler,
+    dir: &Path,
+) -> Result<Diff, ScheduleError> {
+    let (desired, failed) = match load(dir) {
+        Ok(loaded) => loaded,
+        // Nothing is removed while it is unknown which manifests are gone.
+        Err(e) => {
+            error!("Skipping manifests: {}", e);
+            return Ok(Diff::default());
+        }
+    };
+    for (_, e) in &failed {
+        error!("Skipping manifest: {}", e);
+    }
+    let failed: HashSet<String> =
+        failed.iter().map(|(path, _)| path.display().to_string()).collect();
+
+    let stored: HashMap<String, _> = scheduler
+        .store()
+        .list(None)
+        .await?
+        .into_iter()
+        .map(|s| (s.executable.name().to_string(), s))
+        .collect();
+
+    let mut diff = Diff::default();
+    for (name, scheduled) in &stored {
+        let managed = match &scheduled.manifest {
+            Some(manifest) => manifest,
+            None => continue,
+        };
+        // Executables of manifests that failed to load are kept as they are.
+        if !desired.contains_key(name) && !failed.contains(managed) {
+            let _ = scheduler.destroy(name).await?;
+            diff.removed.push(name.clone());
+        }
+    }
+
+    // Dependencies are created before their dependents.
+    let executables: Vec<Executable> =
+        desired.values().map(|d| d.executable.clone()).collect();
+    let ordered: Vec<String> = match dependency::start_order(&executables) {
+        Ok(ordered) => ordered.iter().map(|e| e.name().to_string()).collect(),
+        Err(_) => desired.keys().cloned().collect(),
+    };
+    for name in ordered {
+        let wanted = &desired[&name];
+        let current = stored.get(&name);
+        let unchanged = current.is_some_and(|s| {
+            s.executable == wanted.executable
+                && s.enabled == wanted.enabled
+                && s.manifest.as_deref() == Some(wanted.manifest.as_str())
+        });
+        if unchanged {
+            continue;
+        }
+        if let Err(e) = apply(scheduler, wanted, current.is_some()).await {
+            error!(
+                "Failed to apply manifest {} for {}: {}",
+                wanted.manifest, name, e
+            );
+            continue;
+        }
+        if current.is_some() {
+            diff.updated.push(name);
+        } else {
+            diff.created.push(name);
+        }
+    }
+    diff.log();
+    Ok(diff)
+}
+
+/// Makes the executable of the scheduler match `wanted`. A running
+/// executable is stopped first, so that it runs as it is defined now.
+async fn apply(
+    scheduler: &Scheduler,
+    wanted: &Desired,
+    exists: bool,
+) -> Result<(), ScheduleError> {
+    let name = wanted.executable.name();
+    if exists {
+        let _ = scheduler.disable(name).await?;
+    }
+    if wanted.enabled {
+        let _ = scheduler.enable(&wanted.executable).await?;
+    } else {
+        scheduler.store().save(&wanted.executable, false).await?;
+    }
+    scheduler.store().set_manifest(name, Some(wanted.manifest.clone())).await
+}
+
+/// Reconciles `scheduler` against the manifests of `dir` whenever the
+/// manifests change, for as long as auraed runs.
+pub(crate) fn watch(scheduler: Scheduler, dir: PathBuf) {
+    tokio::spawn(async move {
+        let mut last = fingerprint(&dir).ok();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            // Manifests that cannot be looked at are taken to be unchanged.
+            let current = match fingerprint(&dir) {
+                Ok(current) => Some(current),
+                Err(e) => {
+                    warn!("Failed to look at manifests: {}", e);
+                    continue;
+                }
+            };
+            if current == last {
+                continue;
+            }
+            last = current;
+            info!("Manifests in {} changed, reconciling", dir.display());
+            if let Err(e) = reconcile(&scheduler, &dir).await {
+                error!("Failed to reconcile manifests: {}", e);
+            }
+        }
+    });
+}
+
+/// The names, sizes and modification times of the manifests of `dir`.
+fn fingerprint(dir: &Path) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    manifest_paths(dir)?
+        .into_iter()
+        .map(|path| {
+            let metadata = std::fs::metadata(&path)?;
+            Ok((path, metadata.len(), metadata.modified()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observe::events::Events;
+    use crate::runtime::logs::tests::logs;
+    use crate::schedule::store::tests::{executable, store};
+
+    const TOML: &str = r#"
+[[executables]]
+name = "web"
+command = "sleep 30"
+env = { PORT = "8080" }
+restart = { policy = "on-failure", backoff_ms = 500 }
+requires = ["db", { name = "migrate", condition = "completed" }]
+readiness_probe = { http = { port = 8080, path = "/healthz" }, period_ms = 1000 }
+
+[[executables]]
+name = "cleanup"
+command = "true"
+enabled = false
+schedule = { cron = "@daily", concurrency = "forbid" }
+resources = { memory_max = 268435456 }
+"#;
+
+    const YAML: &str = r#"
+executables:
+  - name: db
+    command: sleep 30
+  - name: migrate
+    command: "true"
+    restart:
+      policy: never
+"#;
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "auraed-manifests-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("dir");
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, content: &str) {
+        std::fs::write(dir.join(name), content).expect("write manifest");
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempdir("load");
+        write(&dir, "web.toml", TOML);
+        write(&dir, "db.yaml", YAML);
+        write(&dir, "notes.txt", "not a manifest");
+        write(&dir, "broken.yml", "executables: [{ name: x }]");
+
+        let (desired, failed) = load(&dir).expect("load");
+        let names: Vec<&String> = desired.keys().collect();
+        assert_eq!(names, ["cleanup", "db", "migrate", "web"]);
+        assert_eq!(failed.len(), 1);
+        assert!(failed[0].0.ends_with("broken.yml"));
+
+        let web = &desired["web"].executable;
+        assert_eq!(web.env["PORT"], "8080");
+        assert_eq!(
+            web.restart.as_ref().expect("restart").policy(),
+            RestartPolicy::OnFailure
+        );
+        assert_eq!(web.requires[1].condition(), DependencyCondition::Completed);
+        let probe = web.readiness_probe.as_ref().expect("probe");
+        assert!(matches!(
+            &probe.probe,
+            Some(executable_probe::Probe::Http(HttpProbe { port: 8080, .. }))
+        ));
+        assert!(!desired["cleanup"].enabled);
+        assert_eq!(
+            desired["cleanup"]
+                .executable
+                .resources
+                .as_ref()
+                .expect("resources")
+                .memory_max,
+            256 << 20
+        );
+
+        // An executable is only defined once.
+        write(
+            &dir,
+            "more.toml",
+            "[[executables]]\nname = \"db\"\ncommand = \"true\"\n",
+        );
+        let (_, failed) = load(&dir).expect("load");
+        assert!(failed
+            .iter()
+            .any(|(_, e)| matches!(e, ManifestError::Duplicate { .. })));
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let dir = tempdir("reconcile");
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        // Executables enabled by clients are not managed by manifests.
+        scheduler
+            .store()
+            .save(&executable("adhoc", "true"), false)
+            .await
+            .expect("save");
+
+        write(&dir, "db.yaml", YAML);
+        let diff = reconcile(&scheduler, &dir).await.expect("reconcile");
+        assert_eq!(diff.created, ["db", "migrate"]);
+        assert_eq!(
+            reconcile(&scheduler, &dir).await.expect("again"),
+            Diff::default()
+        );
+
+        write(&dir, "db.yaml", &YAML.replace("sleep 30", "sleep 60"));
+        write(&dir, "web.toml", TOML);
+        let diff = reconcile(&scheduler, &dir).await.expect("reconcile");
+        assert_eq!(diff.created, ["cleanup", "web"]);
+        assert_eq!(diff.updated, ["db"]);
+        let db = scheduler.store().get("db").await.expect("get");
+        assert_eq!(db.executable.command, "sleep 60");
+        assert!(!scheduler.store().get("cleanup").await.expect("get").enabled);
+
+        // A directory that cannot be listed removes nothing.
+        assert_eq!(
+            reconcile(&scheduler, &dir.join("db.yaml")).await.expect("file"),
+            Diff::default()
+        );
+        assert!(fingerprint(&dir.join("db.yaml")).is_err());
+
+        std::fs::remove_file(dir.join("db.yaml")).expect("remove");
+        let diff = reconcile(&scheduler, &dir).await.expect("reconcile");
+        assert_eq!(diff.removed.len(), 2);
+        assert!(scheduler.store().get("db").await.is_err());
+        assert!(scheduler.store().get("adhoc").await.is_ok());
+
+        scheduler.shutdown().await.expect("shutdown");
+        std::fs::remove_dir_all(&dir).expect("cleanup");
+    }
+}
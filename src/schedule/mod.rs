@@ -28,19 +28,100 @@
  *                                                                            *
 \* -------------------------------------------------------------------------- */
 /*
- * [Runtime] is a SYNCHRONOUS subsystem.
+ * [Schedule] is an ASYNCHRONOUS subsystem.
  */
 
 #![allow(dead_code)]
 tonic::include_proto!("schedule");
 
-use crate::runtime::Executable;
+use crate::meta;
+use crate::runtime::{Executable, ExecutableStatus};
 use crate::schedule::schedule_executable_server::ScheduleExecutable;
-use crate::{command_from_string, meta};
+use crate::schedule::schedule_server::Schedule;
 use tonic::{Request, Response, Status};
 
-#[derive(Debug, Default, Clone)]
-pub struct ScheduleExecutableService {}
+mod cron;
+mod dependency;
+mod entities;
+mod manifest;
+mod probe;
+mod restart;
+mod scheduler;
+mod store;
+mod timer;
+
+pub(crate) use manifest::{reconcile, watch as watch_manifests};
+pub(crate) use scheduler::Scheduler;
+pub(crate) use store::{connect, ScheduleError, ScheduleStore};
+
+#[cfg(test)]
+pub(crate) use store::tests::store;
+
+#[derive(Debug, Clone)]
+pub struct ScheduleService {
+    scheduler: Scheduler,
+}
+
+impl ScheduleService {
+    pub(crate) fn new(scheduler: Scheduler) -> Self {
+        Self { scheduler }
+    }
+
+    async fn show(
+        &self,
+        enabled: bool,
+        prefix: &str,
+    ) -> Result<(Vec<Executable>, Vec<ExecutableStatus>), Status> {
+        let shown = self.scheduler.show(enabled, prefix).await?;
+        Ok(shown.into_iter().unzip())
+    }
+}
+
+#[tonic::async_trait]
+impl Schedule for ScheduleService {
+    async fn show_enabled(
+        &self,
+        request: Request<ShowEnabledRequest>,
+    ) -> Result<Response<ShowEnabledResponse>, Status> {
+        let r = request.into_inner();
+        let (executables, statuses) = self.show(true, &r.name_prefix).await?;
+        Ok(Response::new(ShowEnabledResponse {
+            meta: Some(meta::AuraeMeta {
+                name: r.name_prefix,
+                message: format!("{} enabled", executables.len()),
+            }),
+            executables,
+            statuses,
+        }))
+    }
+
+    async fn show_disabled(
+        &self,
+        request: Request<ShowDisabledRequest>,
+    ) -> Result<Response<ShowDisabledResponse>, Status> {
+        let r = request.into_inner();
+        let (executables, statuses) = self.show(false, &r.name_prefix).await?;
+        Ok(Response::new(ShowDisabledResponse {
+            meta: Some(meta::AuraeMeta {
+                name: r.name_prefix,
+                message: format!("{} disabled", executables.len()),
+            }),
+            executables,
+            statuses,
+        }))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScheduleExecutableService {
+    scheduler: Scheduler,
+}
+
+impl ScheduleExecutableService {
+    pub(crate) fn new(scheduler: Scheduler) -> Self {
+        Self { scheduler }
+    }
+}
 
 #[tonic::async_trait]
 impl ScheduleExecutable for ScheduleExecutableService {
@@ -49,53 +130,41 @@ impl ScheduleExecutable for ScheduleExecutableService {
         request: Request<Executable>,
     ) -> Result<Response<ExecutableEnableResponse>, Status> {
         let r = request.into_inner();
-        let cmd = command_from_string(&r.command);
-        match cmd {
-            Ok(mut cmd) => {
-                let output = cmd.output();
-                match output {
-                    Ok(_) => {
-                        let meta = meta::AuraeMeta {
-                            name: r.command,
-                            message: "-".to_string(),
-                        };
-                        let response =
-                            ExecutableEnableResponse { meta: Some(meta) };
-                        Ok(Response::new(response))
-                    }
-                    Err(e) => {
-                        let meta = meta::AuraeMeta {
-                            name: "-".to_string(),
-                            message: format!("{:?}", e),
-                        };
-                        let response =
-                            ExecutableEnableResponse { meta: Some(meta) };
-                        Ok(Response::new(response))
-                    }
-                }
+        let status = self.scheduler.enable(&r).await?;
+        let message = match (status.status, status.proc, status.meta) {
+            (s, Some(proc), _) if s == meta::Status::Active as i32 => {
+                format!("enabled, running as pid {}", proc.pid)
             }
-            Err(e) => {
-                let meta = meta::AuraeMeta {
-                    name: "-".to_string(),
-                    message: format!("{:?}", e),
-                };
-                let response = ExecutableEnableResponse { meta: Some(meta) };
-                Ok(Response::new(response))
-            }
-        }
+            (_, _, Some(meta)) => format!("enabled, {}", meta.message),
+            _ => "enabled".to_string(),
+        };
+        let meta = meta::AuraeMeta { name: r.name().to_string(), message };
+        Ok(Response::new(ExecutableEnableResponse { meta: Some(meta) }))
     }
 
     async fn disable(
         &self,
-        _request: Request<Executable>,
+        request: Request<Executable>,
     ) -> Result<Response<ExecutableDisableResponse>, Status> {
-        todo!()
+        let r = request.into_inner();
+        let removal = self.scheduler.disable(r.name()).await?;
+        let meta = meta::AuraeMeta {
+            name: r.name().to_string(),
+            message: removal.message("disabled"),
+        };
+        Ok(Response::new(ExecutableDisableResponse { meta: Some(meta) }))
     }
 
     async fn destroy(
         &self,
-        _request: Request<Executable>,
+        request: Request<Executable>,
     ) -> Result<Response<ExecutableDestroyResponse>, Status> {
-        todo!()
+        let r = request.into_inner();
+        let removal = self.scheduler.destroy(r.name()).await?;
+        let meta = meta::AuraeMeta {
+            name: r.name().to_string(),
+            message: removal.message("destroyed"),
+        };
+        Ok(Response::new(ExecutableDestroyResponse { meta: Some(meta) }))
     }
 }
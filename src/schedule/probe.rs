@@ -0,0 +1,383 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::meta;
+use crate::runtime::executable_probe;
+use crate::runtime::{
+    Executable, ExecutableProbe, HttpProbe, Process, ProcessNamespaces,
+};
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+
+const DEFAULT_PERIOD: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+const DEFAULT_HOST: &str = "127.0.0.1";
+
+/// Output kept of a probe command, which is only used in failure messages.
+const MAX_COMMAND_OUTPUT: usize = 4096;
+
+/// A liveness or readiness probe, with defaults filled in.
+#[derive(Debug, Clone)]
+pub(crate) struct Probe {
+    check: executable_probe::Probe,
+    /// Name is the name the probe commands of an executable run as.
+    name: String,
+    pub initial_delay: Duration,
+    pub period: Duration,
+    pub timeout: Duration,
+    pub failure_threshold: u32,
+}
+
+impl Probe {
+    /// The probe of the executable `name`, unless `probe` checks nothing.
+    pub fn new(name: &str, probe: &Option<ExecutableProbe>) -> Option<Self> {
+        let probe = probe.as_ref()?;
+        let millis = |ms, default| match ms {
+            0 => default,
+            ms => Duration::from_millis(ms),
+        };
+        Some(Self {
+            check: probe.probe.clone()?,
+            name: format!("{}-probe", name),
+            initial_delay: Duration::from_millis(probe.initial_delay_ms),
+            period: millis(probe.period_ms, DEFAULT_PERIOD),
+            timeout: millis(probe.timeout_ms, DEFAULT_TIMEOUT),
+            failure_threshold: match probe.failure_threshold {
+                0 => DEFAULT_FAILURE_THRESHOLD,
+                n => n,
+            },
+        })
+    }
+
+    /// Probes the process `pid` once, and describes the failure if the
+    /// probe fails. Probes run in the namespaces of the process, so that
+    /// commands see its filesystem, and connections reach its network.
+    pub async fn check(&self, pid: i32) -> Result<(), String> {
+        let namespaces = ProcessNamespaces::of(pid).map_err(|e| {
+            format!("failed to open the namespaces of pid {}: {}", pid, e)
+        })?;
+        match &self.check {
+            executable_probe::Probe::Command(command) => {
+                self.command(command, namespaces).await
+            }
+            executable_probe::Probe::Tcp(tcp) => {
+                let _ = self.connect(&namespaces, &tcp.host, tcp.port).await?;
+                Ok(())
+            }
+            executable_probe::Probe::Http(http) => {
+                self.http(&namespaces, http).await
+            }
+        }
+    }
+
+    async fn command(
+        &self,
+        command: &str,
+        namespaces: ProcessNamespaces,
+    ) -> Result<(), String> {
+        let executable = Executable {
+            meta: Some(meta::AuraeMeta {
+                name: self.name.clone(),
+                message: String::new(),
+            }),
+            command: command.to_string(),
+            ..Default::default()
+        };
+        let process = executable
+            .to_command()
+            .and_then(|mut cmd| {
+                // The namespaces are joined once the probe is in its cgroup.
+                unsafe {
+                    let _ = cmd.command.pre_exec(move || namespaces.join());
+                }
+                Process::spawn(cmd, MAX_COMMAND_OUTPUT, Some(self.timeout))
+                    .map_err(anyhow::Error::from)
+            })
+            .map_err(|e| format!("{:?}", e))?;
+        let exit = process.wait().await;
+        let status = process.status(&self.name);
+        match exit.status {
+            _ if exit.timed_out => {
+                Err(format!("timed out after {:?}", self.timeout))
+            }
+            Ok(s) if s.success() => Ok(()),
+            Ok(_) if status.signal != 0 => {
+                Err(format!("killed by signal {}", status.signal))
+            }
+            Ok(_) => Err(format!("exited with code {}", status.exit_code)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn connect(
+        &self,
+        namespaces: &ProcessNamespaces,
+        host: &str,
+        port: u32,
+    ) -> Result<TcpStream, String> {
+        let host = if host.is_empty() { DEFAULT_HOST } else { host };
+        let port = u16::try_from(port)
+            .map_err(|_| format!("invalid port {}", port))?;
+        let connect = async {
+            let addr = tokio::net::lookup_host((host, port))
+                .await?
+                .next()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            let socket = match namespaces.net() {
+                Some(net) => socket_in(net, &addr)?,
+                None if addr.is_ipv4() => TcpSocket::new_v4()?,
+                None => TcpSocket::new_v6()?,
+            };
+            socket.connect(addr).await
+        };
+        match tokio::time::timeout(self.timeout, connect).await {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => {
+                Err(format!("failed to connect to {}:{}: {}", host, port, e))
+            }
+            Err(_) => Err(format!("connecting to {}:{} timed out", host, port)),
+        }
+    }
+
+    async fn http(
+        &self,
+        namespaces: &ProcessNamespaces,
+        http: &HttpProbe,
+    ) -> Result<(), String> {
+        let host = if http.host.is_empty() { DEFAULT_HOST } else { &http.host };
+        let path = if http.path.is_empty() { "/" } else { &http.path };
+        let get = async {
+            let mut stream = self.connect(namespaces, host, http.port).await?;
+            let request = format!(
+                "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: auraed\r\nConnection: close\r\n\r\n",
+                path, host
+            );
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(|e| format!("failed to send request: {}", e))?;
+            // Only the status line is of interest.
+            let mut response = Vec::new();
+            let mut buf = [0u8; 256];
+            while !response.contains(&b'\n') && response.len() < 1024 {
+                let n = stream
+                    .read(&mut buf)
+                    .await
+                    .map_err(|e| format!("failed to read response: {}", e))?;
+                if n == 0 {
+                    break;
+                }
+                response.extend_from_slice(&buf[..n]);
+            }
+            status_code(&response)
+        };
+        let code = tokio::time::timeout(self.timeout, get)
+            .await
+            .map_err(|_| format!("GET {} timed out", path))??;
+        match code {
+            200..=399 => Ok(()),
+            code => Err(format!("GET {} returned {}", path, code)),
+        }
+    }
+}
+
+/// A TCP socket for `addr` in the network namespace `net`. Only a thread of
+/// its own joins the namespace, and the socket stays in it once the thread
+/// is gone.
+fn socket_in(net: &File, addr: &SocketAddr) -> io::Result<TcpSocket> {
+    let domain = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+    let fd = std::thread::scope(|scope| {
+        scope
+            .spawn(|| unsafe {
+                if libc::setns(net.as_raw_fd(), libc::CLONE_NEWNET) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let flags = libc::SOCK_STREAM
+                    | libc::SOCK_NONBLOCK
+                    | libc::SOCK_CLOEXEC;
+                match libc::socket(domain, flags, 0) {
+                    fd if fd < 0 => Err(io::Error::last_os_error()),
+                    fd => Ok(fd),
+                }
+            })
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::Other)))
+    })?;
+    Ok(unsafe { TcpSocket::from_raw_fd(fd) })
+}
+
+/// The status code of the status line of an HTTP response.
+fn status_code(response: &[u8]) -> Result<u16, String> {
+    let line = response.split(|b| *b == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next().map(str::parse::<u16>)) {
+        (Some(version), Some(Ok(code))) if version.starts_with("HTTP/") => {
+            Ok(code)
+        }
+        _ => Err(format!("invalid HTTP response {:?}", line.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{privileged, ExecutableNamespaces, TcpProbe};
+    use tokio::net::TcpListener;
+
+    fn probe(check: executable_probe::Probe) -> Probe {
+        Probe::new(
+            "probed",
+            &Some(ExecutableProbe {
+                probe: Some(check),
+                timeout_ms: 500,
+                ..Default::default()
+            }),
+        )
+        .expect("probe")
+    }
+
+    /// The pid of the test, whose namespaces are those of auraed.
+    fn own() -> i32 {
+        std::process::id() as i32
+    }
+
+    /// Answers every connection with `status`.
+    async fn http_server(status: &'static str) -> u32 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let response =
+                    format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        u32::from(port)
+    }
+
+    #[tokio::test]
+    async fn test_command_probe() {
+        use executable_probe::Probe::Command;
+        assert_eq!(probe(Command("true".into())).check(own()).await, Ok(()));
+        assert_eq!(
+            probe(Command("sh -c 'exit 4'".into())).check(own()).await,
+            Err("exited with code 4".to_string())
+        );
+        let slow = probe(Command("sleep 5".into())).check(own()).await;
+        assert!(slow.expect_err("timeout").starts_with("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_network_probes() {
+        let port = http_server("200 OK").await;
+        let tcp = |port| {
+            probe(executable_probe::Probe::Tcp(TcpProbe {
+                port,
+                ..Default::default()
+            }))
+        };
+        assert_eq!(tcp(port).check(own()).await, Ok(()));
+
+        let http = |port, path: &str| {
+            probe(executable_probe::Probe::Http(HttpProbe {
+                port,
+                path: path.to_string(),
+                ..Default::default()
+            }))
+        };
+        assert_eq!(http(port, "/healthz").check(own()).await, Ok(()));
+        let failing = http_server("503 Service Unavailable").await;
+        assert_eq!(
+            http(failing, "").check(own()).await,
+            Err("GET / returned 503".to_string())
+        );
+
+        // Nothing listens on a port that was just released.
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let closed = u32::from(listener.local_addr().expect("addr").port());
+        drop(listener);
+        assert!(tcp(closed).check(own()).await.is_err());
+        assert!(http(closed, "/").check(own()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probes_run_in_namespaces_of_process() {
+        if !privileged() {
+            eprintln!("skipping, creating namespaces requires CAP_SYS_ADMIN");
+            return;
+        }
+        let executable = Executable {
+            command: "sleep 30".to_string(),
+            namespaces: Some(ExecutableNamespaces {
+                uts: true,
+                hostname: "probed".to_string(),
+                net: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let cmd = executable.to_command().expect("command");
+        let process = Process::spawn(cmd, 4096, None).expect("spawn");
+        // Give the executable a moment to enter its namespaces.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // The port auraed listens on is closed in the namespace.
+        let port = http_server("200 OK").await;
+        let tcp = probe(executable_probe::Probe::Tcp(TcpProbe {
+            port,
+            ..Default::default()
+        }));
+        assert_eq!(tcp.check(own()).await, Ok(()));
+        assert!(tcp.check(process.pid).await.is_err());
+
+        let hostname = probe(executable_probe::Probe::Command(
+            "sh -c 'test $(hostname) = probed'".into(),
+        ));
+        assert!(hostname.check(own()).await.is_err());
+        assert_eq!(hostname.check(process.pid).await, Ok(()));
+        let _ = process.stop(libc::SIGKILL, Duration::ZERO).await;
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(status_code(b"HTTP/1.1 204 No Content\r\n"), Ok(204));
+        assert!(status_code(b"SSH-2.0-OpenSSH\r\n").is_err());
+    }
+}
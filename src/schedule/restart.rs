@@ -0,0 +1,130 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::meta;
+use crate::runtime::{Executable, ExecutableStatus, RestartPolicy};
+use std::time::Duration;
+
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+const DEFAULT_RESET_AFTER: Duration = Duration::from_secs(10);
+
+/// The restart settings of an executable, with defaults filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Restart {
+    pub policy: RestartPolicy,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub max_restarts: u32,
+    pub reset_after: Duration,
+}
+
+impl Restart {
+    pub fn new(executable: &Executable) -> Self {
+        let restart = executable.restart.clone().unwrap_or_default();
+        let millis = |ms, default| match ms {
+            0 => default,
+            ms => Duration::from_millis(ms),
+        };
+        let backoff = millis(restart.backoff_ms, DEFAULT_BACKOFF);
+        Self {
+            policy: restart.policy(),
+            backoff,
+            max_backoff: millis(restart.max_backoff_ms, DEFAULT_MAX_BACKOFF)
+                .max(backoff),
+            max_restarts: match restart.max_restarts {
+                0 => DEFAULT_MAX_RESTARTS,
+                n => n,
+            },
+            reset_after: millis(restart.reset_after_ms, DEFAULT_RESET_AFTER),
+        }
+    }
+
+    /// Whether a run that exited with `status` is restarted.
+    pub fn applies(&self, status: &ExecutableStatus) -> bool {
+        match self.policy {
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => failed(status),
+            RestartPolicy::Never => false,
+        }
+    }
+
+    /// The delay before the next restart, given the delay before the last
+    /// one in a row.
+    pub fn next_backoff(&self, last: Option<Duration>) -> Duration {
+        match last {
+            Some(last) => last.saturating_mul(2).min(self.max_backoff),
+            None => self.backoff,
+        }
+    }
+}
+
+fn failed(status: &ExecutableStatus) -> bool {
+    status.status != meta::Status::Complete as i32 || status.exit_code != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::ExecutableRestart;
+
+    #[test]
+    fn test_restart() {
+        let restart = Restart::new(&Executable {
+            restart: Some(ExecutableRestart {
+                policy: RestartPolicy::OnFailure as i32,
+                backoff_ms: 100,
+                max_backoff_ms: 300,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(restart.max_restarts, DEFAULT_MAX_RESTARTS);
+        assert_eq!(restart.next_backoff(None), Duration::from_millis(100));
+        let second = restart.next_backoff(Some(Duration::from_millis(100)));
+        assert_eq!(second, Duration::from_millis(200));
+        assert_eq!(restart.next_backoff(Some(second)), restart.max_backoff);
+
+        let exited = |exit_code, status: meta::Status| ExecutableStatus {
+            status: status as i32,
+            exit_code,
+            ..Default::default()
+        };
+        assert!(!restart.applies(&exited(0, meta::Status::Complete)));
+        assert!(restart.applies(&exited(1, meta::Status::Complete)));
+        assert!(restart.applies(&exited(0, meta::Status::Timeout)));
+
+        // Everything is restarted by default.
+        let restart = Restart::new(&Executable::default());
+        assert!(restart.applies(&exited(0, meta::Status::Complete)));
+        assert_eq!(restart.max_backoff, DEFAULT_MAX_BACKOFF);
+    }
+}
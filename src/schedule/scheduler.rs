@@ -0,0 +1,1512 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::meta;
+use crate::observe::events::Events;
+use crate::observe::{
+    event, ExecutableDisabled, ExecutableEnabled, ExecutableRestarted,
+    ProbeFailed,
+};
+use crate::runtime::{
+    unix_timestamp, ConcurrencyPolicy, DependencyCondition, Executable,
+    ExecutableStatus, ExecutableUsage, LogStore, Process, ProcessError,
+    ProcessTable, DEFAULT_STOP_GRACE_PERIOD,
+};
+use crate::schedule::dependency::{self, dependencies};
+use crate::schedule::entities::executable_run;
+use crate::schedule::probe::Probe;
+use crate::schedule::restart::Restart;
+use crate::schedule::store::{
+    ScheduleError, ScheduleStore, StoredExecutable, INTERRUPTED,
+};
+use crate::schedule::timer::{Ticks, Timer};
+use log::{error, info, warn};
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// Tells supervisions of executables of the same name apart.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Runs the enabled executables of a schedule store, restarts them
+/// according to their restart policy or runs them on their schedule, and
+/// records the history of their runs in the store.
+#[derive(Debug, Clone)]
+pub(crate) struct Scheduler {
+    store: ScheduleStore,
+    /// Logs keeps the output of every run.
+    logs: LogStore,
+    /// Events is where the lifecycle of executables is published.
+    events: Events,
+    processes: ProcessTable,
+    supervisions: Arc<Mutex<HashMap<String, Supervision>>>,
+    /// Changes is notified whenever an executable is enabled, started or
+    /// exits, which is what executables waiting for their dependencies
+    /// wait for.
+    changes: Arc<watch::Sender<()>>,
+    /// StartedAt is when the scheduler started, in nanoseconds since the
+    /// Unix epoch. Runs before it do not complete dependencies.
+    started_at: i64,
+}
+
+/// The supervision of an enabled executable. A supervision is removed when
+/// its executable is disabled, which wakes up a supervisor waiting to
+/// restart the executable.
+#[derive(Debug)]
+struct Supervision {
+    generation: u64,
+    restarts: u32,
+    /// Timed is set for an executable that runs on a schedule.
+    timed: bool,
+    /// Runs are the runs of an executable on a schedule, which are not kept
+    /// in the process table as they may overlap.
+    runs: Vec<Process>,
+    /// Waiting tells which dependency an executable waits for before its
+    /// first start.
+    waiting: Option<String>,
+    /// Ready is cleared while the readiness probe of the running executable
+    /// has not succeeded yet, or failed.
+    ready: bool,
+    /// ProbeFailure tells why the last probe of the running executable
+    /// failed, once it failed often enough to count.
+    probe_failure: Option<String>,
+    /// Finished tells why the executable is no longer restarted, once its
+    /// supervisor gave up on it.
+    finished: Option<String>,
+    _cancel: watch::Sender<()>,
+}
+
+impl Scheduler {
+    pub fn new(store: ScheduleStore, logs: LogStore, events: Events) -> Self {
+        Self {
+            store,
+            logs,
+            processes: ProcessTable::new(events.clone()),
+            events,
+            supervisions: Arc::default(),
+            changes: Arc::new(watch::channel(()).0),
+            started_at: unix_timestamp(),
+        }
+    }
+
+    pub fn store(&self) -> &ScheduleStore {
+        &self.store
+    }
+
+    pub fn logs(&self) -> &LogStore {
+        &self.logs
+    }
+
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Starts every executable that was enabled when auraed last ran, in
+    /// the order of their dependencies. Executables that fail to start stay
+    /// enabled, and are reported. Executables that are already supervised
+    /// are left as they are.
+    pub async fn hydrate(&self) -> Result<(), ScheduleError> {
+        let closed = self.store.close_interrupted_runs(self.started_at).await?;
+        if closed > 0 {
+            warn!("Closed {} runs interrupted by auraed stopping", closed);
+        }
+        for executable in self.enabled_in_order().await? {
+            let name = executable.name().to_string();
+            if self.supervisions().contains_key(&name) {
+                continue;
+            }
+            match self.supervise(&executable).await {
+                Ok(status) => info!(
+                    "Restored scheduled executable {} as pid {}",
+                    name,
+                    status.proc.map(|p| p.pid).unwrap_or_default()
+                ),
+                Err(e) => {
+                    error!("Failed to restore executable {}: {}", name, e)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops every enabled executable in the reverse order of their
+    /// dependencies, leaving them enabled for the next start of auraed.
+    pub async fn shutdown(&self) -> Result<(), ScheduleError> {
+        for executable in self.enabled_in_order().await?.iter().rev() {
+            match self.stop(executable.name()).await {
+                Ok(Some(status)) => info!(
+                    "Stopped scheduled executable {} (pid {})",
+                    executable.name(),
+                    status.proc.map(|p| p.pid).unwrap_or_default()
+                ),
+                Ok(None) => {}
+                Err(e) => error!(
+                    "Failed to stop executable {}: {}",
+                    executable.name(),
+                    e
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    async fn enabled_in_order(&self) -> Result<Vec<Executable>, ScheduleError> {
+        let executables: Vec<Executable> = self
+            .store
+            .list(Some(true))
+            .await?
+            .into_iter()
+            .map(|s| s.executable)
+            .collect();
+        match dependency::start_order(&executables) {
+            Ok(ordered) => return Ok(ordered.into_iter().cloned().collect()),
+            // Cycles are refused on enable, but are not fatal either.
+            Err(e) => error!("Ignoring the order of executables: {}", e),
+        }
+        Ok(executables)
+    }
+
+    /// Enables `executable` and starts it, or waits for its schedule. An
+    /// executable of the same name that is still supervised is left as it
+    /// is, and runs the new definition once it is restarted or its next run
+    /// is due. Only putting an executable on a schedule, or taking it off,
+    /// stops what is still running.
+    pub async fn enable(
+        &self,
+        executable: &Executable,
+    ) -> Result<ExecutableStatus, ScheduleError> {
+        let timed = Timer::new(executable)?.is_some();
+        let mut executables: Vec<Executable> = self
+            .store
+            .list(None)
+            .await?
+            .into_iter()
+            .map(|s| s.executable)
+            .filter(|e| e.name() != executable.name())
+            .collect();
+        executables.push(executable.clone());
+        dependency::check_cycles(&executables)?;
+        self.store.save(executable, true).await?;
+        let enabled = event::Event::Enabled(ExecutableEnabled {});
+        self.events.publish(executable.name(), enabled);
+        let switched = self
+            .supervisions()
+            .get(executable.name())
+            .is_some_and(|s| s.finished.is_none() && s.timed != timed);
+        if switched {
+            let _ = self.stop(executable.name()).await?;
+        }
+        self.supervise(executable).await
+    }
+
+    /// Lists the enabled or disabled executables with a name starting with
+    /// `prefix`, each along with its current status.
+    pub async fn show(
+        &self,
+        enabled: bool,
+        prefix: &str,
+    ) -> Result<Vec<(Executable, ExecutableStatus)>, ScheduleError> {
+        let mut shown = Vec::new();
+        for scheduled in self.store.list(Some(enabled)).await? {
+            if !scheduled.executable.name().starts_with(prefix) {
+                continue;
+            }
+            let status = self.status(&scheduled).await?;
+            shown.push((scheduled.executable, status));
+        }
+        Ok(shown)
+    }
+
+    /// The status of the running or last run of a scheduled executable.
+    async fn status(
+        &self,
+        scheduled: &StoredExecutable,
+    ) -> Result<ExecutableStatus, ScheduleError> {
+        let name = scheduled.executable.name();
+        let running = self.supervisions().get(name).and_then(|s| {
+            s.runs.iter().rev().find(|p| p.is_running()).cloned()
+        });
+        let process = match running {
+            Some(process) => Ok(process),
+            None => self.processes.get(name),
+        };
+        let mut status = match process {
+            Ok(process) => process.status(name),
+            Err(_) => match self.store.last_run(name).await? {
+                Some(run) => run_status(run),
+                None => ExecutableStatus {
+                    meta: Some(meta::AuraeMeta {
+                        name: name.to_string(),
+                        message: "has not run".to_string(),
+                    }),
+                    exit_code: -1,
+                    ..Default::default()
+                },
+            },
+        };
+        if status.status != meta::Status::Active as i32 {
+            status.status = if scheduled.enabled {
+                // Exited runs of enabled executables keep their outcome.
+                if status.proc.is_some() {
+                    status.status
+                } else {
+                    meta::Status::Standby as i32
+                }
+            } else {
+                meta::Status::Passive as i32
+            };
+        }
+        if let Some(supervision) = self.supervisions().get(name) {
+            status.restarts = supervision.restarts;
+            if status.status == meta::Status::Active as i32
+                && !supervision.ready
+            {
+                let (state, message) = match &supervision.probe_failure {
+                    Some(failure) => (meta::Status::Error, failure.clone()),
+                    None => (
+                        meta::Status::Standby,
+                        "waiting to be ready".to_string(),
+                    ),
+                };
+                status.status = state as i32;
+                if let Some(meta) = status.meta.as_mut() {
+                    meta.message = message;
+                }
+            }
+            if let (Some(waiting), Some(meta)) =
+                (&supervision.waiting, status.meta.as_mut())
+            {
+                status.status = meta::Status::Standby as i32;
+                meta.message = waiting.clone();
+            }
+            if let (Some(finished), Some(meta)) =
+                (&supervision.finished, status.meta.as_mut())
+            {
+                meta.message = finished.clone();
+            }
+        }
+        Ok(status)
+    }
+
+    /// Disables the executable `name` and stops it if it is running. The
+    /// executable stays on record, and can be enabled again.
+    pub async fn disable(&self, name: &str) -> Result<Removal, ScheduleError> {
+        if name.is_empty() {
+            return Err(ScheduleError::MissingName);
+        }
+        let was_enabled = match self.store.set_enabled(name, false).await {
+            Ok(was_enabled) => was_enabled,
+            Err(ScheduleError::NotFound { .. }) => {
+                return Ok(Removal { scheduled: false, stopped: None })
+            }
+            Err(e) => return Err(e),
+        };
+        if was_enabled {
+            self.disabled(name, false);
+        }
+        let stopped = self.stop(name).await?;
+        Ok(Removal { scheduled: was_enabled, stopped })
+    }
+
+    /// Stops the executable `name` if it is running, and forgets about it
+    /// along with the history of its runs.
+    pub async fn destroy(&self, name: &str) -> Result<Removal, ScheduleError> {
+        if name.is_empty() {
+            return Err(ScheduleError::MissingName);
+        }
+        // Disabled first, so that nothing starts it again while it stops.
+        let scheduled = match self.store.set_enabled(name, false).await {
+            Ok(_) => true,
+            Err(ScheduleError::NotFound { .. }) => false,
+            Err(e) => return Err(e),
+        };
+        if scheduled {
+            self.disabled(name, true);
+        }
+        let stopped = self.stop(name).await?;
+        let _ = self.store.delete(name).await?;
+        if let Err(e) = self.logs.remove(name) {
+            warn!("Failed to remove the logs of executable {}: {}", name, e);
+        }
+        Ok(Removal { scheduled, stopped })
+    }
+
+    /// Ends the supervision of the executable `name` and stops it, and
+    /// returns its status if it was running.
+    async fn stop(
+        &self,
+        name: &str,
+    ) -> Result<Option<ExecutableStatus>, ScheduleError> {
+        // Once the supervision is gone, its supervisor starts nothing new.
+        let runs = self
+            .supervisions()
+            .remove(name)
+            .map(|s| s.runs)
+            .unwrap_or_default();
+        let mut stopped = None;
+        for process in runs.into_iter().filter(Process::is_running) {
+            let _ = process
+                .stop(libc::SIGTERM, DEFAULT_STOP_GRACE_PERIOD)
+                .await
+                .map_err(|e| ProcessError::SignalFailure {
+                    name: name.to_string(),
+                    source: e,
+                })?;
+            stopped = Some(process.status(name));
+        }
+        match self.processes.get(name) {
+            Ok(process) if process.is_running() => {
+                stopped = Some(
+                    self.processes
+                        .stop(name, libc::SIGTERM, DEFAULT_STOP_GRACE_PERIOD)
+                        .await?,
+                )
+            }
+            Ok(_) | Err(ProcessError::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(stopped)
+    }
+
+    /// Starts `executable` under a new supervision, or waits for its
+    /// dependencies or its schedule, unless it is still supervised.
+    async fn supervise(
+        &self,
+        executable: &Executable,
+    ) -> Result<ExecutableStatus, ScheduleError> {
+        let name = executable.name().to_string();
+        let timed = Timer::new(executable)?.is_some();
+        let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+        let (cancel, cancelled) = watch::channel(());
+        let waits = dependencies(executable).next().is_some();
+        let inserted = {
+            let mut supervisions = self.supervisions();
+            match supervisions.get(&name) {
+                Some(s) if s.finished.is_none() => false,
+                _ => {
+                    let waiting = waits
+                        .then(|| "waiting for its dependencies".to_string());
+                    let _ = supervisions.insert(
+                        name.clone(),
+                        Supervision {
+                            generation,
+                            restarts: 0,
+                            timed,
+                            runs: Vec::new(),
+                            waiting,
+                            ready: true,
+                            probe_failure: None,
+                            finished: None,
+                            _cancel: cancel,
+                        },
+                    );
+                    true
+                }
+            }
+        };
+        if !inserted {
+            let scheduled = self.store.get(&name).await?;
+            return self.status(&scheduled).await;
+        }
+        self.changed();
+        if waits {
+            let unmet = self.unmet(executable).await.unwrap_or_else(|e| {
+                Some(format!("failed to check dependencies: {}", e))
+            });
+            if let Some(waiting) = unmet {
+                let _ =
+                    self.set_waiting(&name, generation, Some(waiting.clone()));
+                let scheduler = self.clone();
+                tokio::spawn(async move {
+                    scheduler
+                        .wait_for_dependencies(name, generation, cancelled)
+                        .await
+                });
+                return Ok(standby_status(executable, &waiting));
+            }
+            if !self.set_waiting(&name, generation, None) {
+                return Err(ScheduleError::NotFound { name });
+            }
+        }
+        self.begin(name, generation, executable, cancelled).await
+    }
+
+    /// Starts the first run of a supervised executable, or its timer if it
+    /// runs on a schedule.
+    async fn begin(
+        &self,
+        name: String,
+        generation: u64,
+        executable: &Executable,
+        cancelled: watch::Receiver<()>,
+    ) -> Result<ExecutableStatus, ScheduleError> {
+        if Timer::new(executable)?.is_some() {
+            let scheduler = self.clone();
+            tokio::spawn(async move {
+                scheduler.timer(name, generation, cancelled).await
+            });
+            return Ok(standby_status(executable, "waiting for its schedule"));
+        }
+        let status = match self.start(&name, generation, executable, false) {
+            Some(Ok(status)) => status,
+            Some(Err(e)) => {
+                self.finish(&name, generation, e.to_string());
+                return Err(e.into());
+            }
+            None => return Err(ScheduleError::NotFound { name }),
+        };
+        let run = self.store.record_start(&name, &status).await;
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            scheduler.supervisor(name, generation, run, cancelled).await
+        });
+        Ok(status)
+    }
+
+    /// Waits until the dependencies of a supervised executable are met, and
+    /// starts it then.
+    async fn wait_for_dependencies(
+        self,
+        name: String,
+        generation: u64,
+        mut cancelled: watch::Receiver<()>,
+    ) {
+        let mut changes = self.changes.subscribe();
+        let executable = loop {
+            // The definition is looked up again, as it may change.
+            let executable = match self.store.get(&name).await {
+                Ok(scheduled) if scheduled.enabled => scheduled.executable,
+                Ok(_) | Err(ScheduleError::NotFound { .. }) => return,
+                Err(e) => {
+                    let reason = format!("failed to look up executable: {}", e);
+                    return self.finish(&name, generation, reason);
+                }
+            };
+            let waiting = match self.unmet(&executable).await {
+                Ok(None) => break executable,
+                Ok(Some(waiting)) => waiting,
+                Err(e) => format!("failed to check dependencies: {}", e),
+            };
+            if !self.set_waiting(&name, generation, Some(waiting)) {
+                return;
+            }
+            tokio::select! {
+                _ = changes.changed() => {}
+                // The sender is dropped along with the supervision.
+                _ = cancelled.changed() => return,
+            }
+        };
+        if !self.set_waiting(&name, generation, None) {
+            return;
+        }
+        if let Err(e) =
+            self.begin(name.clone(), generation, &executable, cancelled).await
+        {
+            error!("Failed to start executable {}: {}", name, e);
+        }
+    }
+
+    /// The first dependency of `executable` that is not met, if any.
+    async fn unmet(
+        &self,
+        executable: &Executable,
+    ) -> Result<Option<String>, ScheduleError> {
+        for (dependency, required) in dependencies(executable) {
+            let name = dependency.name.as_str();
+            let enabled = match self.store.get(name).await {
+                Ok(scheduled) => scheduled.enabled,
+                Err(ScheduleError::NotFound { .. }) => false,
+                Err(e) => return Err(e),
+            };
+            if !enabled {
+                if required {
+                    return Ok(Some(format!(
+                        "waiting for {} to be enabled",
+                        name
+                    )));
+                }
+                continue;
+            }
+            let (met, event) = match dependency.condition() {
+                DependencyCondition::Started => {
+                    (self.is_running(name), "start")
+                }
+                DependencyCondition::Completed => {
+                    let completed =
+                        self.store.last_run(name).await?.is_some_and(|run| {
+                            run.started_at >= self.started_at
+                                && run.exited_at.is_some()
+                                && run.status == meta::Status::Complete as i32
+                                && run.exit_code == 0
+                        });
+                    (completed, "complete")
+                }
+            };
+            if !met {
+                return Ok(Some(format!("waiting for {} to {}", name, event)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether the executable `name` is running, and ready if it has a
+    /// readiness probe.
+    fn is_running(&self, name: &str) -> bool {
+        let (timed, ready) = match self.supervisions().get(name) {
+            Some(s) => (s.runs.iter().any(Process::is_running), s.ready),
+            None => (false, true),
+        };
+        timed || ready && self.processes.get(name).is_ok_and(|p| p.is_running())
+    }
+
+    fn set_waiting(
+        &self,
+        name: &str,
+        generation: u64,
+        waiting: Option<String>,
+    ) -> bool {
+        match self
+            .supervisions()
+            .get_mut(name)
+            .filter(|s| s.generation == generation)
+        {
+            Some(supervision) => {
+                supervision.waiting = waiting;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn changed(&self) {
+        self.changes.send_replace(());
+    }
+
+    /// Publishes that the executable `name` was disabled, or destroyed.
+    fn disabled(&self, name: &str, destroyed: bool) {
+        let disabled = ExecutableDisabled { destroyed };
+        self.events.publish(name, event::Event::Disabled(disabled));
+    }
+
+    /// Waits for the runs of a supervised executable, and restarts it
+    /// according to its restart policy until it is disabled, its policy
+    /// leaves it exited, or it is crash looping.
+    async fn supervisor(
+        self,
+        name: String,
+        generation: u64,
+        mut run: Result<i64, ScheduleError>,
+        mut cancelled: watch::Receiver<()>,
+    ) {
+        let mut started = Instant::now();
+        let mut backoff = None;
+        let mut restarts_in_row = 0;
+        let reason = loop {
+            let status = match run {
+                Ok(id) => self.wait_run(&name, id).await,
+                Err(e) => {
+                    error!("Failed to start executable {}: {}", name, e);
+                    failure_status(&name, e.to_string())
+                }
+            };
+            let executable = match self.store.get(&name).await {
+                Ok(scheduled) if scheduled.enabled => scheduled.executable,
+                Ok(_) | Err(ScheduleError::NotFound { .. }) => return,
+                Err(e) => break format!("failed to look up executable: {}", e),
+            };
+            let restart = Restart::new(&executable);
+            if !restart.applies(&status) {
+                break "exited, left exited by its restart policy".to_string();
+            }
+            if started.elapsed() >= restart.reset_after {
+                restarts_in_row = 0;
+                backoff = None;
+            }
+            if restarts_in_row >= restart.max_restarts {
+                break format!(
+                    "crash looping, gave up after {} restarts in a row",
+                    restarts_in_row
+                );
+            }
+            restarts_in_row += 1;
+            let delay = restart.next_backoff(backoff);
+            backoff = Some(delay);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                // The sender is dropped along with the supervision.
+                _ = cancelled.changed() => return,
+            }
+            started = Instant::now();
+            run = match self.start(&name, generation, &executable, true) {
+                Some(Ok(status)) => {
+                    self.store.record_start(&name, &status).await
+                }
+                Some(Err(e)) => Err(e.into()),
+                None => return,
+            };
+        };
+        warn!("Executable {} is no longer restarted: {}", name, reason);
+        self.finish(&name, generation, reason);
+    }
+
+    /// Starts the runs of an executable on a schedule whenever they are due,
+    /// until it is disabled.
+    async fn timer(
+        self,
+        name: String,
+        generation: u64,
+        mut cancelled: watch::Receiver<()>,
+    ) {
+        let mut ticking: Option<(Timer, Ticks)> = None;
+        loop {
+            // The definition is looked up for every run, as it may change.
+            let executable = match self.store.get(&name).await {
+                Ok(scheduled) if scheduled.enabled => scheduled.executable,
+                Ok(_) | Err(ScheduleError::NotFound { .. }) => return,
+                Err(e) => {
+                    let reason = format!("failed to look up executable: {}", e);
+                    warn!("Executable {} is no longer run: {}", name, reason);
+                    return self.finish(&name, generation, reason);
+                }
+            };
+            let timer = match Timer::new(&executable) {
+                Ok(Some(timer)) => timer,
+                _ => {
+                    let reason = "no run is due anymore".to_string();
+                    return self.finish(&name, generation, reason);
+                }
+            };
+            // A changed schedule starts over from now.
+            let (timer, mut ticks) = match ticking.take() {
+                Some((previous, ticks)) if previous == timer => (timer, ticks),
+                _ => {
+                    let ticks = timer.ticks();
+                    (timer, ticks)
+                }
+            };
+            let jitter = timer.jitter();
+            tokio::select! {
+                due = async {
+                    let due = ticks.tick().await;
+                    tokio::time::sleep(jitter).await;
+                    due
+                } => if !due {
+                    let reason = "no run is due anymore".to_string();
+                    return self.finish(&name, generation, reason);
+                },
+                // The sender is dropped along with the supervision.
+                _ = cancelled.changed() => return,
+            }
+            self.fire(&name, generation, &executable, timer.concurrency).await;
+            ticking = Some((timer, ticks));
+        }
+    }
+
+    /// Starts a run of an executable on a schedule that is due, applying
+    /// its concurrency policy to the runs still going on.
+    async fn fire(
+        &self,
+        name: &str,
+        generation: u64,
+        executable: &Executable,
+        concurrency: ConcurrencyPolicy,
+    ) {
+        let running: Vec<Process> = match self
+            .supervisions()
+            .get_mut(name)
+            .filter(|s| s.generation == generation)
+        {
+            Some(supervision) => {
+                supervision.runs.retain(Process::is_running);
+                supervision.runs.clone()
+            }
+            None => return,
+        };
+        match concurrency {
+            ConcurrencyPolicy::Forbid if !running.is_empty() => {
+                info!(
+                    "Skipping run of executable {}, its last run is still going on",
+                    name
+                );
+                return;
+            }
+            ConcurrencyPolicy::Replace => {
+                for process in running {
+                    if let Err(e) = process
+                        .stop(libc::SIGTERM, DEFAULT_STOP_GRACE_PERIOD)
+                        .await
+                    {
+                        warn!("Failed to replace run of {}: {}", name, e);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let spawned = executable.to_command().and_then(|cmd| {
+            Process::spawn_logged(
+                cmd,
+                executable.max_output(),
+                executable.timeout(),
+                Some(self.logs.writer(name)),
+            )
+            .map_err(anyhow::Error::from)
+        });
+        let process = match spawned {
+            Ok(process) => process,
+            Err(e) => {
+                error!("Failed to start executable {}: {:?}", name, e);
+                let status = failure_status(name, format!("{:?}", e));
+                if let Ok(id) = self.store.record_start(name, &status).await {
+                    let _ = self.store.record_exit(id, &status).await;
+                }
+                return;
+            }
+        };
+        // Registered under the lock, so that stopping the executable never
+        // misses a run.
+        let registered = match self
+            .supervisions()
+            .get_mut(name)
+            .filter(|s| s.generation == generation)
+        {
+            Some(supervision) => {
+                supervision.runs.push(process.clone());
+                true
+            }
+            None => false,
+        };
+        if !registered {
+            let _ =
+                process.stop(libc::SIGTERM, DEFAULT_STOP_GRACE_PERIOD).await;
+            return;
+        }
+        process.publish_events(name, &self.events);
+
+        self.changed();
+        let name = name.to_string();
+        let store = self.store.clone();
+        let changes = self.changes.clone();
+        let run = store.record_start(&name, &process.status(&name)).await;
+        tokio::spawn(async move {
+            let _ = process.wait().await;
+            let status = process.status(&name);
+            let recorded = match run {
+                Ok(id) => store.record_exit(id, &status).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = recorded {
+                error!("Failed to record run of executable {}: {}", name, e);
+            }
+            changes.send_replace(());
+        });
+    }
+
+    /// Starts a run of a supervised executable, unless the supervision
+    /// ended in the meantime.
+    fn start(
+        &self,
+        name: &str,
+        generation: u64,
+        executable: &Executable,
+        restart: bool,
+    ) -> Option<Result<ExecutableStatus, ProcessError>> {
+        {
+            let mut supervisions = self.supervisions();
+            let supervision = supervisions
+                .get_mut(name)
+                .filter(|s| s.generation == generation)?;
+            if restart {
+                supervision.restarts += 1;
+                info!(
+                    "Restarting executable {} ({} restarts)",
+                    name, supervision.restarts
+                );
+                let restarts = supervision.restarts;
+                let restarted = ExecutableRestarted { restarts };
+                self.events.publish(name, event::Event::Restarted(restarted));
+            }
+            supervision.ready = executable.readiness_probe.is_none();
+            supervision.probe_failure = None;
+        }
+        // The lock is not held while forking. A supervision that ended in
+        // the meantime may have missed the new process when stopping, so it
+        // is stopped here instead. Once the process is in the process table,
+        // a supervision that ends finds it there.
+        let started =
+            self.processes.start_logged(executable, self.logs.writer(name));
+        let ended = self
+            .supervisions()
+            .get(name)
+            .is_none_or(|s| s.generation != generation);
+        if ended {
+            if started.is_ok() {
+                let (processes, name) =
+                    (self.processes.clone(), name.to_string());
+                tokio::spawn(async move {
+                    processes
+                        .stop(&name, libc::SIGTERM, DEFAULT_STOP_GRACE_PERIOD)
+                        .await
+                });
+            }
+            return None;
+        }
+        if started.is_ok() {
+            self.probe(name, generation, executable);
+        }
+        self.changed();
+        Some(started)
+    }
+
+    /// Starts probing the process just started for the executable `name`,
+    /// if it has probes.
+    fn probe(&self, name: &str, generation: u64, executable: &Executable) {
+        let liveness = Probe::new(name, &executable.liveness_probe);
+        let readiness = Probe::new(name, &executable.readiness_probe);
+        if liveness.is_none() && readiness.is_none() {
+            return;
+        }
+        let process = match self.processes.get(name) {
+            Ok(process) => process,
+            Err(_) => return,
+        };
+        let scheduler = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let probes = async {
+                tokio::join!(
+                    scheduler
+                        .prober(&name, generation, &process, liveness, true),
+                    scheduler
+                        .prober(&name, generation, &process, readiness, false),
+                )
+            };
+            tokio::select! {
+                _ = probes => {}
+                _ = process.wait() => {}
+            }
+        });
+    }
+
+    /// Probes a running process with `probe` until the process exits or is
+    /// failed by the probe.
+    async fn prober(
+        &self,
+        name: &str,
+        generation: u64,
+        process: &Process,
+        probe: Option<Probe>,
+        liveness: bool,
+    ) {
+        let probe = match probe {
+            Some(probe) => probe,
+            None => return,
+        };
+        let kind = if liveness { "liveness" } else { "readiness" };
+        tokio::time::sleep(probe.initial_delay).await;
+        let mut failures = 0;
+        loop {
+            let result = probe.check(process.pid).await;
+            let (ready, failure) = match result {
+                Ok(()) => {
+                    failures = 0;
+                    (true, None)
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(
+                        "The {} probe of executable {} failed ({}/{}): {}",
+                        kind, name, failures, probe.failure_threshold, e
+                    );
+                    let failed = ProbeFailed {
+                        liveness,
+                        message: e.to_string(),
+                        failures,
+                        failure_threshold: probe.failure_threshold,
+                    };
+                    self.events
+                        .publish(name, event::Event::ProbeFailed(failed));
+                    if failures < probe.failure_threshold {
+                        tokio::time::sleep(probe.period).await;
+                        continue;
+                    }
+                    (false, Some(format!("{} probe failed: {}", kind, e)))
+                }
+            };
+            if liveness {
+                if let Some(failure) = failure {
+                    if self.probed(name, generation, None, Some(failure)) {
+                        warn!("Stopping executable {}, it is not alive", name);
+                        if let Err(e) = process
+                            .stop(libc::SIGTERM, DEFAULT_STOP_GRACE_PERIOD)
+                            .await
+                        {
+                            error!("Failed to stop executable {}: {}", name, e);
+                        }
+                    }
+                    return;
+                }
+            } else if !self.probed(name, generation, Some(ready), failure) {
+                return;
+            }
+            tokio::time::sleep(probe.period).await;
+        }
+    }
+
+    /// Records the outcome of a probe of a supervised executable, and
+    /// returns whether the supervision is still going on.
+    fn probed(
+        &self,
+        name: &str,
+        generation: u64,
+        ready: Option<bool>,
+        failure: Option<String>,
+    ) -> bool {
+        let changed = match self
+            .supervisions()
+            .get_mut(name)
+            .filter(|s| s.generation == generation)
+        {
+            Some(supervision) => {
+                let changed = ready.is_some_and(|r| r != supervision.ready);
+                if let Some(ready) = ready {
+                    supervision.ready = ready;
+                }
+                supervision.probe_failure = failure;
+                changed
+            }
+            None => return false,
+        };
+        if changed {
+            self.changed();
+        }
+        true
+    }
+
+    /// Waits for the run `id` of the executable `name` to exit, and records
+    /// its exit.
+    async fn wait_run(&self, name: &str, id: i64) -> ExecutableStatus {
+        let status = match self.processes.wait(name).await {
+            Ok(status) => status,
+            Err(e) => failure_status(name, e.to_string()),
+        };
+        if let Err(e) = self.store.record_exit(id, &status).await {
+            error!("Failed to record exit of executable {}: {}", name, e);
+        }
+        self.changed();
+        status
+    }
+
+    fn finish(&self, name: &str, generation: u64, reason: String) {
+        if let Some(supervision) = self
+            .supervisions()
+            .get_mut(name)
+            .filter(|s| s.generation == generation)
+        {
+            supervision.finished = Some(reason);
+        }
+    }
+
+    fn supervisions(&self) -> MutexGuard<'_, HashMap<String, Supervision>> {
+        // A panic while holding the lock cannot leave the map half updated.
+        self.supervisions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The status of a supervised executable that is not running yet.
+fn standby_status(executable: &Executable, message: &str) -> ExecutableStatus {
+    ExecutableStatus {
+        meta: Some(meta::AuraeMeta {
+            name: executable.name().to_string(),
+            message: message.to_string(),
+        }),
+        status: meta::Status::Standby as i32,
+        exit_code: -1,
+        ..Default::default()
+    }
+}
+
+/// The status of a run that could not be started or waited for.
+fn failure_status(name: &str, message: String) -> ExecutableStatus {
+    ExecutableStatus {
+        meta: Some(meta::AuraeMeta { name: name.to_string(), message }),
+        status: meta::Status::Error as i32,
+        exit_code: -1,
+        ..Default::default()
+    }
+}
+
+/// The status of a run recorded before auraed last stopped.
+fn run_status(run: executable_run::Model) -> ExecutableStatus {
+    let (status, message) = match run.exited_at {
+        Some(_) => (run.status, run.message),
+        None => (meta::Status::Unknown as i32, INTERRUPTED.to_string()),
+    };
+    ExecutableStatus {
+        meta: Some(meta::AuraeMeta { name: run.name, message }),
+        proc: Some(meta::ProcessMeta {
+            pid: run.pid,
+            start_time: run.started_at,
+        }),
+        status,
+        exit_code: run.exit_code,
+        signal: run.signal,
+        usage: run
+            .usage
+            .and_then(|usage| ExecutableUsage::decode(usage.as_slice()).ok()),
+        ..Default::default()
+    }
+}
+
+/// What disabling or destroying a scheduled executable came to.
+#[derive(Debug)]
+pub(crate) struct Removal {
+    /// Scheduled is set when the executable was enabled before, or, for a
+    /// destroyed executable, when it was on record at all.
+    pub scheduled: bool,
+    /// Stopped is the status of the executable if it had to be stopped.
+    pub stopped: Option<ExecutableStatus>,
+}
+
+impl Removal {
+    /// A message for clients describing the removal, `action` being what
+    /// was done to the executable.
+    pub fn message(&self, action: &str) -> String {
+        let state = match (self.scheduled, action) {
+            (true, _) => action.to_string(),
+            (false, "destroyed") => "not scheduled".to_string(),
+            (false, _) => format!("already {}", action),
+        };
+        match &self.stopped {
+            Some(status) => format!(
+                "{}, stopped pid {}",
+                state,
+                status.proc.as_ref().map(|p| p.pid).unwrap_or_default()
+            ),
+            None => format!("{}, not running", state),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::logs::tests::logs;
+    use crate::runtime::LogQuery;
+    use crate::runtime::{
+        executable_probe, ExecutableDependency, ExecutableProbe,
+        ExecutableRestart, ExecutableSchedule, RestartPolicy, TcpProbe,
+    };
+    use crate::schedule::store::tests::{executable, store};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    fn restarted(
+        name: &str,
+        command: &str,
+        policy: RestartPolicy,
+    ) -> Executable {
+        Executable {
+            restart: Some(ExecutableRestart {
+                policy: policy as i32,
+                backoff_ms: 10,
+                max_restarts: 2,
+                ..Default::default()
+            }),
+            ..executable(name, command)
+        }
+    }
+
+    /// Waits until the executable `name` is no longer restarted.
+    async fn finished(scheduler: &Scheduler, name: &str) -> ExecutableStatus {
+        for _ in 0..100 {
+            let stored = scheduler.store().get(name).await.expect("get");
+            let status = scheduler.status(&stored).await.expect("status");
+            if scheduler
+                .supervisions()
+                .get(name)
+                .is_some_and(|s| s.finished.is_some())
+            {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("executable {} is still supervised", name);
+    }
+
+    #[tokio::test]
+    async fn test_enable_records_runs() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        let (_, mut watcher) = scheduler.events().watch(None);
+        let status = scheduler
+            .enable(&restarted(
+                "once",
+                "sh -c 'echo hi; exit 3'",
+                RestartPolicy::Never,
+            ))
+            .await
+            .expect("enable");
+        assert_eq!(status.status, meta::Status::Active as i32);
+
+        let status = finished(&scheduler, "once").await;
+        assert_eq!(status.restarts, 0);
+        let runs = scheduler.store().runs("once").await.expect("runs");
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].exit_code, 3);
+        assert_eq!(runs[0].status, meta::Status::Complete as i32);
+        let logs = scheduler.logs().read("once", LogQuery::default());
+        assert_eq!(logs.expect("logs")[0].line, "hi");
+        let mut seen = Vec::new();
+        let timeout = Duration::from_secs(1);
+        while let Ok(Ok(e)) =
+            tokio::time::timeout(timeout, watcher.recv()).await
+        {
+            match e.event.filter(|_| e.name == "once") {
+                Some(event::Event::Enabled(_)) => seen.push("enabled"),
+                Some(event::Event::Started(_)) => seen.push("started"),
+                Some(event::Event::Exited(exited)) => {
+                    assert_eq!(exited.exit_code, 3);
+                    seen.push("exited");
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(seen, ["enabled", "started", "exited"]);
+
+        // A restarted daemon runs the executable again.
+        let restored = Scheduler::new(
+            scheduler.store().clone(),
+            scheduler.logs().clone(),
+            scheduler.events().clone(),
+        );
+        restored.hydrate().await.expect("hydrate");
+        assert_eq!(restored.store().runs("once").await.expect("runs").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_restart_policies() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+
+        // A crash looping executable is given up on.
+        let _ = scheduler
+            .enable(&restarted("crashy", "false", RestartPolicy::Always))
+            .await
+            .expect("enable");
+        let status = finished(&scheduler, "crashy").await;
+        assert_eq!(status.restarts, 2);
+        assert!(status
+            .meta
+            .expect("meta")
+            .message
+            .starts_with("crash looping"));
+        assert_eq!(
+            scheduler.store().runs("crashy").await.expect("runs").len(),
+            3
+        );
+
+        // A clean exit is not a failure.
+        let _ = scheduler
+            .enable(&restarted("clean", "true", RestartPolicy::OnFailure))
+            .await
+            .expect("enable");
+        let status = finished(&scheduler, "clean").await;
+        assert_eq!(status.restarts, 0);
+        assert_eq!(
+            scheduler.store().runs("clean").await.expect("runs").len(),
+            1
+        );
+
+        // Enabling an executable that was given up on supervises it anew.
+        let _ = scheduler
+            .enable(&restarted("crashy", "false", RestartPolicy::Never))
+            .await
+            .expect("enable");
+        let status = finished(&scheduler, "crashy").await;
+        assert_eq!(status.restarts, 0);
+    }
+
+    fn timed(
+        name: &str,
+        command: &str,
+        concurrency: ConcurrencyPolicy,
+    ) -> Executable {
+        Executable {
+            schedule: Some(ExecutableSchedule {
+                interval_ms: 1000,
+                concurrency: concurrency as i32,
+                ..Default::default()
+            }),
+            ..executable(name, command)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timed_runs() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        let status = scheduler
+            .enable(&timed("tick", "true", ConcurrencyPolicy::Allow))
+            .await
+            .expect("enable");
+        assert_eq!(status.status, meta::Status::Standby as i32);
+        let _ = scheduler
+            .enable(&timed("slow", "sleep 10", ConcurrencyPolicy::Forbid))
+            .await
+            .expect("enable");
+        tokio::time::sleep(Duration::from_millis(3500)).await;
+
+        let _ = scheduler.disable("tick").await.expect("disable");
+        let ticks = scheduler.store().runs("tick").await.expect("runs");
+        assert!(ticks.len() >= 3, "{} runs", ticks.len());
+        assert!(ticks.iter().skip(1).all(|r| r.exit_code == 0));
+
+        // The first slow run is still going on, and is stopped on disable.
+        let removal = scheduler.disable("slow").await.expect("disable");
+        assert_eq!(removal.stopped.expect("stopped").signal, libc::SIGTERM);
+        assert_eq!(
+            scheduler.store().runs("slow").await.expect("runs").len(),
+            1
+        );
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let runs = scheduler.store().runs("tick").await.expect("runs");
+        assert_eq!(runs.len(), ticks.len());
+    }
+
+    fn dependent(
+        name: &str,
+        command: &str,
+        requires: &str,
+        condition: DependencyCondition,
+    ) -> Executable {
+        Executable {
+            requires: vec![ExecutableDependency {
+                name: requires.to_string(),
+                condition: condition as i32,
+            }],
+            ..executable(name, command)
+        }
+    }
+
+    async fn wait_for(scheduler: &Scheduler, name: &str, status: meta::Status) {
+        for _ in 0..100 {
+            let stored = scheduler.store().get(name).await.expect("get");
+            let current = scheduler.status(&stored).await.expect("status");
+            if current.status == status as i32 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("executable {} never reached {:?}", name, status);
+    }
+
+    #[tokio::test]
+    async fn test_dependencies() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        let web =
+            dependent("web", "sleep 30", "db", DependencyCondition::Started);
+        let status = scheduler.enable(&web).await.expect("enable");
+        assert_eq!(status.status, meta::Status::Standby as i32);
+        let stored = scheduler.store().get("web").await.expect("get");
+        let status = scheduler.status(&stored).await.expect("status");
+        assert_eq!(
+            status.meta.expect("meta").message,
+            "waiting for db to be enabled"
+        );
+
+        let db = dependent(
+            "db",
+            "sleep 30",
+            "migrate",
+            DependencyCondition::Completed,
+        );
+        let _ = scheduler.enable(&db).await.expect("enable");
+        let migrate = restarted("migrate", "true", RestartPolicy::Never);
+        let _ = scheduler.enable(&migrate).await.expect("enable");
+        wait_for(&scheduler, "db", meta::Status::Active).await;
+        wait_for(&scheduler, "web", meta::Status::Active).await;
+
+        // Enabling an executable that closes a cycle fails.
+        let cycle =
+            dependent("migrate", "true", "web", DependencyCondition::Started);
+        assert!(matches!(
+            scheduler.enable(&cycle).await,
+            Err(ScheduleError::DependencyCycle(_))
+        ));
+
+        scheduler.shutdown().await.expect("shutdown");
+        assert!(!scheduler.is_running("web") && !scheduler.is_running("db"));
+        // Stopped executables stay enabled for the next start.
+        assert!(scheduler.store().get("web").await.expect("get").enabled);
+    }
+
+    #[tokio::test]
+    async fn test_probes() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        let probe = |check| ExecutableProbe {
+            probe: Some(check),
+            period_ms: 20,
+            failure_threshold: 2,
+            ..Default::default()
+        };
+
+        // An executable that is not alive is restarted.
+        let _ = scheduler
+            .enable(&Executable {
+                liveness_probe: Some(probe(executable_probe::Probe::Command(
+                    "false".to_string(),
+                ))),
+                ..restarted("wedged", "sleep 30", RestartPolicy::Always)
+            })
+            .await
+            .expect("enable");
+
+        // An executable is ready once something listens on its port.
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("addr").port();
+        drop(listener);
+        let _ = scheduler
+            .enable(&Executable {
+                readiness_probe: Some(probe(executable_probe::Probe::Tcp(
+                    TcpProbe { port: u32::from(port), ..Default::default() },
+                ))),
+                ..executable("web", "sleep 30")
+            })
+            .await
+            .expect("enable");
+        wait_for(&scheduler, "web", meta::Status::Error).await;
+        let stored = scheduler.store().get("web").await.expect("get");
+        let status = scheduler.status(&stored).await.expect("status");
+        let message = status.meta.expect("meta").message;
+        assert!(message.starts_with("readiness probe failed"), "{}", message);
+        let _listener =
+            TcpListener::bind(("127.0.0.1", port)).await.expect("bind");
+        wait_for(&scheduler, "web", meta::Status::Active).await;
+
+        let stored = scheduler.store().get("wedged").await.expect("get");
+        for _ in 0..100 {
+            let status = scheduler.status(&stored).await.expect("status");
+            if status.restarts >= 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let runs = scheduler.store().runs("wedged").await.expect("runs");
+        assert!(runs.iter().any(|r| r.signal == libc::SIGTERM));
+
+        scheduler.shutdown().await.expect("shutdown");
+    }
+
+    #[tokio::test]
+    async fn test_disable_and_destroy() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        let _ = scheduler
+            .enable(&executable("sleepy", "sh -c 'echo up; exec sleep 30'"))
+            .await
+            .expect("enable");
+
+        let removal = scheduler.disable("sleepy").await.expect("disable");
+        assert!(removal.scheduled);
+        let stopped = removal.stopped.as_ref().expect("stopped");
+        assert_eq!(stopped.signal, libc::SIGTERM);
+        assert_eq!(
+            removal.message("disabled").split(',').next(),
+            Some("disabled")
+        );
+        assert!(!scheduler.store().get("sleepy").await.expect("get").enabled);
+
+        let again = scheduler.disable("sleepy").await.expect("disable again");
+        assert_eq!(again.message("disabled"), "already disabled, not running");
+
+        let removal = scheduler.destroy("sleepy").await.expect("destroy");
+        assert!(removal.scheduled && removal.stopped.is_none());
+        assert!(scheduler.logs().read("sleepy", LogQuery::default()).is_err());
+        assert!(scheduler.store().get("sleepy").await.is_err());
+        assert!(scheduler
+            .store()
+            .runs("sleepy")
+            .await
+            .expect("runs")
+            .is_empty());
+
+        let again = scheduler.destroy("sleepy").await.expect("destroy again");
+        assert_eq!(again.message("destroyed"), "not scheduled, not running");
+        let unknown = scheduler.disable("unknown").await.expect("disable");
+        assert!(unknown.stopped.is_none());
+        assert_eq!(
+            unknown.message("disabled"),
+            "already disabled, not running"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_show() {
+        let scheduler =
+            Scheduler::new(store().await, logs(), Events::default());
+        let store = scheduler.store();
+        for (name, enabled) in
+            [("web-a", true), ("web-b", false), ("db", true), ("web-c", true)]
+        {
+            store
+                .save(&executable(name, "sleep 30"), enabled)
+                .await
+                .expect("save");
+        }
+        let _ = scheduler
+            .supervise(&executable("web-a", "sleep 30"))
+            .await
+            .expect("supervise");
+
+        let enabled = scheduler.show(true, "web-").await.expect("show");
+        let shown: Vec<(&str, i32)> =
+            enabled.iter().map(|(e, s)| (e.name(), s.status)).collect();
+        assert_eq!(
+            shown,
+            [
+                ("web-a", meta::Status::Active as i32),
+                ("web-c", meta::Status::Standby as i32)
+            ]
+        );
+        let disabled = scheduler.show(false, "").await.expect("show");
+        assert_eq!(disabled.len(), 1);
+        assert_eq!(disabled[0].1.status, meta::Status::Passive as i32);
+
+        let _ = scheduler.destroy("web-a").await.expect("destroy");
+    }
+}
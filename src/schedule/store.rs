@@ -0,0 +1,549 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::meta;
+use crate::runtime::{
+    unix_timestamp, Executable, ExecutableStatus, ProcessError,
+};
+use crate::schedule::entities::{executable_run, scheduled_executable};
+use log::info;
+use prost::Message;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectOptions, ConnectionTrait, Database,
+    DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder, Set,
+    Statement, TransactionTrait,
+};
+use std::path::Path;
+use tonic::Status;
+
+/// Migrations of the database schema, applied in order. The number of
+/// migrations applied to a database is kept in its `user_version`.
+/// Migrations are never edited once released, only appended.
+const MIGRATIONS: &[&[&str]] = &[
+    &[
+        "CREATE TABLE scheduled_executables (
+        name TEXT NOT NULL PRIMARY KEY,
+        executable BLOB NOT NULL,
+        enabled BOOLEAN NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    )",
+        "CREATE TABLE executable_runs (
+        id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL
+            REFERENCES scheduled_executables (name) ON DELETE CASCADE,
+        pid INTEGER NOT NULL,
+        started_at INTEGER NOT NULL,
+        exited_at INTEGER,
+        status INTEGER NOT NULL,
+        exit_code INTEGER NOT NULL,
+        signal INTEGER NOT NULL,
+        message TEXT NOT NULL
+    )",
+        "CREATE INDEX executable_runs_name ON executable_runs (name, id)",
+    ],
+    &["ALTER TABLE scheduled_executables ADD COLUMN manifest TEXT"],
+    &["ALTER TABLE executable_runs ADD COLUMN usage BLOB"],
+    &["CREATE TABLE event_sequence (
+        id INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+        reserved INTEGER NOT NULL
+    )"],
+];
+
+/// Message of runs that were interrupted by auraed stopping.
+pub(crate) const INTERRUPTED: &str = "auraed stopped while it was running";
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ScheduleError {
+    #[error("executable has no name, set meta.name to schedule it")]
+    MissingName,
+    #[error("invalid schedule: {0}")]
+    InvalidSchedule(String),
+    #[error("dependency cycle: {0}")]
+    DependencyCycle(String),
+    #[error("executable {name} is not scheduled")]
+    NotFound { name: String },
+    #[error("database failure: {0}")]
+    Database(#[from] DbErr),
+    #[error("stored executable {name} is corrupt: {source}")]
+    Corrupt { name: String, source: prost::DecodeError },
+    #[error(transparent)]
+    Process(#[from] ProcessError),
+}
+
+impl From<ScheduleError> for Status {
+    fn from(e: ScheduleError) -> Self {
+        match e {
+            ScheduleError::MissingName
+            | ScheduleError::InvalidSchedule(_)
+            | ScheduleError::DependencyCycle(_) => {
+                Status::invalid_argument(e.to_string())
+            }
+            ScheduleError::NotFound { .. } => Status::not_found(e.to_string()),
+            ScheduleError::Database(_) | ScheduleError::Corrupt { .. } => {
+                Status::internal(e.to_string())
+            }
+            ScheduleError::Process(e) => e.into(),
+        }
+    }
+}
+
+/// Opens the database of auraed at `path`, creating it if needed, and
+/// brings its schema up to date.
+///
+/// The database is plain SQLite, and is not encrypted. It is only as private
+/// as the permissions of the data directory of auraed make it.
+pub(crate) async fn connect(path: &Path) -> Result<DatabaseConnection, DbErr> {
+    let mut opt =
+        ConnectOptions::new(format!("sqlite:{}?mode=rwc", path.display()));
+    opt.sqlx_logging(false);
+    let db = Database::connect(opt).await?;
+    migrate(&db).await?;
+    Ok(db)
+}
+
+/// Applies the migrations a database has not seen yet.
+pub(crate) async fn migrate(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let backend = db.get_database_backend();
+    let version = db
+        .query_one(Statement::from_string(
+            backend,
+            "PRAGMA user_version".to_string(),
+        ))
+        .await?
+        .map(|row| row.try_get::<i32>("", "user_version"))
+        .transpose()?
+        .unwrap_or_default() as usize;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        info!("Migrating database to version {}", i + 1);
+        let txn = db.begin().await?;
+        for sql in migration.iter() {
+            let _ = txn
+                .execute(Statement::from_string(backend, sql.to_string()))
+                .await?;
+        }
+        let _ = txn
+            .execute(Statement::from_string(
+                backend,
+                format!("PRAGMA user_version = {}", i + 1),
+            ))
+            .await?;
+        txn.commit().await?;
+    }
+    Ok(())
+}
+
+/// A scheduled executable as it is kept in the database.
+#[derive(Debug, Clone)]
+pub(crate) struct StoredExecutable {
+    pub executable: Executable,
+    pub enabled: bool,
+    /// Manifest is the manifest file the executable was taken from, if it
+    /// is managed by manifests.
+    pub manifest: Option<String>,
+}
+
+/// The executables handed to the schedule subsystem and the history of
+/// their runs.
+#[derive(Debug, Clone)]
+pub(crate) struct ScheduleStore {
+    db: DatabaseConnection,
+}
+
+impl ScheduleStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Stores `executable`, replacing any executable of the same name. A
+    /// replaced executable keeps the manifest it is managed by.
+    pub async fn save(
+        &self,
+        executable: &Executable,
+        enabled: bool,
+    ) -> Result<(), ScheduleError> {
+        let name = executable.name();
+        if name.is_empty() {
+            return Err(ScheduleError::MissingName);
+        }
+        let now = unix_timestamp();
+        let model = scheduled_executable::ActiveModel {
+            name: Set(name.to_string()),
+            executable: Set(executable.encode_to_vec()),
+            enabled: Set(enabled),
+            created_at: Set(now),
+            updated_at: Set(now),
+            manifest: Set(None),
+        };
+        let _ = scheduled_executable::Entity::insert(model)
+            .on_conflict(
+                OnConflict::column(scheduled_executable::Column::Name)
+                    .update_columns([
+                        scheduled_executable::Column::Executable,
+                        scheduled_executable::Column::Enabled,
+                        scheduled_executable::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks the executable `name` enabled or disabled, and returns whether
+    /// it was enabled before.
+    pub async fn set_enabled(
+        &self,
+        name: &str,
+        enabled: bool,
+    ) -> Result<bool, ScheduleError> {
+        let model = scheduled_executable::Entity::find_by_id(name.to_string())
+            .one(&self.db)
+            .await?
+            .ok_or_else(|| ScheduleError::NotFound {
+                name: name.to_string(),
+            })?;
+        let was_enabled = model.enabled;
+        if was_enabled != enabled {
+            let mut model: scheduled_executable::ActiveModel = model.into();
+            model.enabled = Set(enabled);
+            model.updated_at = Set(unix_timestamp());
+            let _ = model.update(&self.db).await?;
+        }
+        Ok(was_enabled)
+    }
+
+    /// Records the manifest file the executable `name` is taken from, or
+    /// that it is not managed by manifests.
+    pub async fn set_manifest(
+        &self,
+        name: &str,
+        manifest: Option<String>,
+    ) -> Result<(), ScheduleError> {
+        let model = scheduled_executable::ActiveModel {
+            name: Set(name.to_string()),
+            manifest: Set(manifest),
+            ..Default::default()
+        };
+        let _ = model.update(&self.db).await?;
+        Ok(())
+    }
+
+    /// Deletes the executable `name` along with the history of its runs,
+    /// and returns whether there was anything to delete.
+    pub async fn delete(&self, name: &str) -> Result<bool, ScheduleError> {
+        let txn = self.db.begin().await?;
+        let _ = executable_run::Entity::delete_many()
+            .filter(executable_run::Column::Name.eq(name))
+            .exec(&txn)
+            .await?;
+        let deleted =
+            scheduled_executable::Entity::delete_by_id(name.to_string())
+                .exec(&txn)
+                .await?;
+        txn.commit().await?;
+        Ok(deleted.rows_affected > 0)
+    }
+
+    pub async fn get(
+        &self,
+        name: &str,
+    ) -> Result<StoredExecutable, ScheduleError> {
+        scheduled_executable::Entity::find_by_id(name.to_string())
+            .one(&self.db)
+            .await?
+            .ok_or_else(|| ScheduleError::NotFound { name: name.to_string() })
+            .and_then(decode)
+    }
+
+    /// Lists the scheduled executables ordered by name, only those that are
+    /// enabled or disabled if `enabled` is given.
+    pub async fn list(
+        &self,
+        enabled: Option<bool>,
+    ) -> Result<Vec<StoredExecutable>, ScheduleError> {
+        let mut query = scheduled_executable::Entity::find()
+            .order_by_asc(scheduled_executable::Column::Name);
+        if let Some(enabled) = enabled {
+            query =
+                query.filter(scheduled_executable::Column::Enabled.eq(enabled));
+        }
+        query.all(&self.db).await?.into_iter().map(decode).collect()
+    }
+
+    /// Records that a run of the executable `name` has started, and returns
+    /// the id of the run.
+    pub async fn record_start(
+        &self,
+        name: &str,
+        status: &ExecutableStatus,
+    ) -> Result<i64, ScheduleError> {
+        let proc = status.proc.clone().unwrap_or_default();
+        let run = executable_run::ActiveModel {
+            name: Set(name.to_string()),
+            pid: Set(proc.pid),
+            started_at: Set(proc.start_time),
+            exited_at: Set(None),
+            status: Set(status.status),
+            exit_code: Set(status.exit_code),
+            signal: Set(status.signal),
+            message: Set(String::new()),
+            usage: Set(None),
+            ..Default::default()
+        };
+        Ok(run.insert(&self.db).await?.id)
+    }
+
+    /// Records the exit of the run `id`. Runs that were deleted in the
+    /// meantime are left deleted.
+    pub async fn record_exit(
+        &self,
+        id: i64,
+        status: &ExecutableStatus,
+    ) -> Result<(), ScheduleError> {
+        let run = executable_run::ActiveModel {
+            exited_at: Set(Some(unix_timestamp())),
+            status: Set(status.status),
+            exit_code: Set(status.exit_code),
+            signal: Set(status.signal),
+            message: Set(status
+                .meta
+                .as_ref()
+                .map(|m| m.message.clone())
+                .unwrap_or_default()),
+            usage: Set(status.usage.as_ref().map(Message::encode_to_vec)),
+            ..Default::default()
+        };
+        let _ = executable_run::Entity::update_many()
+            .set(run)
+            .filter(executable_run::Column::Id.eq(id))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Records the runs started before `before` that have not exited, because
+    /// auraed stopped while they ran, as exited with an unknown status.
+    /// Returns how many runs there were.
+    pub async fn close_interrupted_runs(
+        &self,
+        before: i64,
+    ) -> Result<u64, ScheduleError> {
+        let run = executable_run::ActiveModel {
+            exited_at: Set(Some(unix_timestamp())),
+            status: Set(meta::Status::Unknown as i32),
+            message: Set(INTERRUPTED.to_string()),
+            ..Default::default()
+        };
+        Ok(executable_run::Entity::update_many()
+            .set(run)
+            .filter(executable_run::Column::ExitedAt.is_null())
+            .filter(executable_run::Column::StartedAt.lt(before))
+            .exec(&self.db)
+            .await?
+            .rows_affected)
+    }
+
+    /// Returns the most recent run of the executable `name`, if it ever ran.
+    pub async fn last_run(
+        &self,
+        name: &str,
+    ) -> Result<Option<executable_run::Model>, ScheduleError> {
+        Ok(executable_run::Entity::find()
+            .filter(executable_run::Column::Name.eq(name))
+            .order_by_desc(executable_run::Column::Id)
+            .one(&self.db)
+            .await?)
+    }
+
+    /// The first sequence number of events that has not been reserved yet,
+    /// or 0 if none ever were.
+    pub async fn reserved_events(&self) -> Result<u64, ScheduleError> {
+        let backend = self.db.get_database_backend();
+        let reserved = self
+            .db
+            .query_one(Statement::from_string(
+                backend,
+                "SELECT reserved FROM event_sequence WHERE id = 0".to_string(),
+            ))
+            .await?
+            .map(|row| row.try_get::<i64>("", "reserved"))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(reserved as u64)
+    }
+
+    /// Records that the sequence numbers of events below `reserved` may have
+    /// been handed out.
+    pub async fn reserve_events(
+        &self,
+        reserved: u64,
+    ) -> Result<(), ScheduleError> {
+        let backend = self.db.get_database_backend();
+        let _ = self
+            .db
+            .execute(Statement::from_sql_and_values(
+                backend,
+                "INSERT INTO event_sequence (id, reserved) VALUES (0, ?)
+                ON CONFLICT (id) DO UPDATE SET reserved = excluded.reserved",
+                [(reserved as i64).into()],
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Lists the runs of the executable `name`, most recent first.
+    pub async fn runs(
+        &self,
+        name: &str,
+    ) -> Result<Vec<executable_run::Model>, ScheduleError> {
+        Ok(executable_run::Entity::find()
+            .filter(executable_run::Column::Name.eq(name))
+            .order_by_desc(executable_run::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+}
+
+fn decode(
+    model: scheduled_executable::Model,
+) -> Result<StoredExecutable, ScheduleError> {
+    let executable = Executable::decode(model.executable.as_slice())
+        .map_err(|e| ScheduleError::Corrupt { name: model.name, source: e })?;
+    Ok(StoredExecutable {
+        executable,
+        enabled: model.enabled,
+        manifest: model.manifest,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Opens a fresh database in memory. The pool is limited to a single
+    /// connection, every connection would see a database of its own.
+    pub(crate) async fn store() -> ScheduleStore {
+        let mut opt = ConnectOptions::new("sqlite::memory:".to_owned());
+        opt.sqlx_logging(false).max_connections(1);
+        let db = Database::connect(opt).await.expect("connect");
+        migrate(&db).await.expect("migrate");
+        ScheduleStore::new(db)
+    }
+
+    pub(crate) fn executable(name: &str, command: &str) -> Executable {
+        Executable {
+            meta: Some(meta::AuraeMeta {
+                name: name.to_string(),
+                message: String::new(),
+            }),
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_list_and_runs() {
+        let store = store().await;
+        // Migrating twice leaves the schema as it is.
+        migrate(&store.db).await.expect("migrate again");
+
+        store.save(&executable("b", "true"), true).await.expect("save b");
+        store.save(&executable("a", "true"), false).await.expect("save a");
+        store.save(&executable("a", "false"), true).await.expect("update a");
+        assert!(matches!(
+            store.save(&executable("", "true"), true).await,
+            Err(ScheduleError::MissingName)
+        ));
+
+        let all = store.list(None).await.expect("list");
+        let names: Vec<&str> =
+            all.iter().map(|s| s.executable.name()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(all[0].executable.command, "false");
+        assert!(all[0].enabled);
+        assert!(store.list(Some(false)).await.expect("list").is_empty());
+
+        let status = ExecutableStatus {
+            proc: Some(meta::ProcessMeta { pid: 42, start_time: 1 }),
+            status: meta::Status::Active as i32,
+            exit_code: -1,
+            ..Default::default()
+        };
+        let id = store.record_start("a", &status).await.expect("start");
+        let exited = ExecutableStatus {
+            status: meta::Status::Complete as i32,
+            exit_code: 1,
+            ..status.clone()
+        };
+        store.record_exit(id, &exited).await.expect("exit");
+        let runs = store.runs("a").await.expect("runs");
+        assert_eq!(runs.len(), 1);
+        assert_eq!((runs[0].pid, runs[0].exit_code), (42, 1));
+        assert!(runs[0].exited_at.is_some());
+
+        // Runs left open by auraed stopping are closed, those that started
+        // since are not.
+        let interrupted = ExecutableStatus {
+            proc: Some(meta::ProcessMeta { pid: 43, start_time: 2 }),
+            ..status.clone()
+        };
+        let _ = store.record_start("a", &interrupted).await.expect("start");
+        let running = ExecutableStatus {
+            proc: Some(meta::ProcessMeta { pid: 44, start_time: 4 }),
+            ..status
+        };
+        let _ = store.record_start("a", &running).await.expect("start");
+        assert_eq!(store.close_interrupted_runs(3).await.expect("close"), 1);
+        let runs = store.runs("a").await.expect("runs");
+        assert_eq!(runs[1].status, meta::Status::Unknown as i32);
+        assert_eq!(runs[1].message, INTERRUPTED);
+        assert!(runs[1].exited_at.is_some());
+        assert!(runs[0].exited_at.is_none());
+
+        assert!(matches!(
+            store.get("missing").await,
+            Err(ScheduleError::NotFound { .. })
+        ));
+
+        assert!(store.set_enabled("a", false).await.expect("disable"));
+        assert!(!store.set_enabled("a", false).await.expect("disable again"));
+        assert!(!store.get("a").await.expect("get").enabled);
+
+        assert!(store.delete("a").await.expect("delete"));
+        assert!(!store.delete("a").await.expect("delete again"));
+        assert!(store.runs("a").await.expect("runs").is_empty());
+        // The exit of a run that was deleted is not recorded.
+        store.record_exit(id, &exited).await.expect("exit after delete");
+        assert!(store.runs("a").await.expect("runs").is_empty());
+    }
+}
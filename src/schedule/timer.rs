@@ -0,0 +1,259 @@
+/* -------------------------------------------------------------------------- *\
+ *             Apache 2.0 License Copyright © 2022 The Aurae Authors          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ *                                                                            *
+ *   Licensed under the Apache License, Version 2.0 (the "License");          *
+ *   you may not use this file except in compliance with the License.         *
+ *   You may obtain a copy of the License at                                  *
+ *                                                                            *
+ *       http://www.apache.org/licenses/LICENSE-2.0                           *
+ *                                                                            *
+ *   Unless required by applicable law or agreed to in writing, software      *
+ *   distributed under the License is distributed on an "AS IS" BASIS,        *
+ *   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. *
+ *   See the License for the specific language governing permissions and      *
+ *   limitations under the License.                                           *
+ *                                                                            *
+\* -------------------------------------------------------------------------- */
+
+use crate::runtime::{unix_timestamp, ConcurrencyPolicy, Executable};
+use crate::schedule::cron::Cron;
+use crate::schedule::store::ScheduleError;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+
+/// The shortest interval executables can run at.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the runs of an executable on a schedule are due.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Timer {
+    due: Due,
+    jitter: Duration,
+    pub concurrency: ConcurrencyPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Due {
+    Cron(Cron),
+    Interval(Duration),
+}
+
+impl Timer {
+    /// The timer of `executable`, if it runs on a schedule at all.
+    pub fn new(executable: &Executable) -> Result<Option<Self>, ScheduleError> {
+        let schedule = match &executable.schedule {
+            Some(schedule) => schedule,
+            None => return Ok(None),
+        };
+        let due =
+            match (schedule.cron.as_str(), schedule.interval_ms) {
+                ("", 0) => return Ok(None),
+                ("", ms) if Duration::from_millis(ms) < MIN_INTERVAL => {
+                    return Err(ScheduleError::InvalidSchedule(format!(
+                        "interval_ms must be at least {}",
+                        MIN_INTERVAL.as_millis()
+                    )))
+                }
+                ("", ms) => Due::Interval(Duration::from_millis(ms)),
+                (cron, 0) => Due::Cron(Cron::parse(cron).map_err(|e| {
+                    ScheduleError::InvalidSchedule(e.to_string())
+                })?),
+                _ => {
+                    return Err(ScheduleError::InvalidSchedule(
+                        "set either cron or interval_ms, not both".to_string(),
+                    ))
+                }
+            };
+        Ok(Some(Self {
+            due,
+            jitter: Duration::from_millis(schedule.jitter_ms),
+            concurrency: schedule.concurrency(),
+        }))
+    }
+
+    /// The times runs are due from now on, the first one interval from now
+    /// for an interval.
+    pub fn ticks(&self) -> Ticks {
+        match &self.due {
+            Due::Interval(interval) => {
+                let mut ticks = tokio::time::interval_at(
+                    Instant::now() + *interval,
+                    *interval,
+                );
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                Ticks::Interval(ticks)
+            }
+            Due::Cron(cron) => {
+                Ticks::Cron { cron: cron.clone(), due: unix_timestamp() }
+            }
+        }
+    }
+
+    /// A random delay of up to the jitter of the timer, to delay a run by.
+    pub fn jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        // Randomly keyed hashers are random enough to spread runs.
+        let random = RandomState::new().build_hasher().finish();
+        Duration::from_nanos(random % (self.jitter.as_nanos() as u64 + 1))
+    }
+}
+
+/// Ticks are the times the runs of a timer are due. Every time is computed
+/// from the one before, so that the time runs take does not delay the runs
+/// after them. Runs that were due while the one before was still being
+/// started are skipped.
+#[derive(Debug)]
+pub(crate) enum Ticks {
+    Interval(Interval),
+    /// Due is the time the last run was due, in nanoseconds since the Unix
+    /// epoch.
+    Cron {
+        cron: Cron,
+        due: i64,
+    },
+}
+
+impl Ticks {
+    /// Waits until the next run is due. Returns false once no run is due
+    /// ever again.
+    pub async fn tick(&mut self) -> bool {
+        match self {
+            Ticks::Interval(ticks) => {
+                let _ = ticks.tick().await;
+                true
+            }
+            Ticks::Cron { cron, due } => {
+                let now = unix_timestamp();
+                let next = match next_cron(cron, *due, now) {
+                    Some(next) => next,
+                    None => return false,
+                };
+                tokio::time::sleep(Duration::from_nanos((next - now) as u64))
+                    .await;
+                *due = next;
+                true
+            }
+        }
+    }
+}
+
+/// When the run of `cron` after the one that was due at `due` is due, both in
+/// nanoseconds since the Unix epoch. Runs that were due before `now` are
+/// skipped.
+fn next_cron(cron: &Cron, due: i64, now: i64) -> Option<i64> {
+    let after = due.max(now).div_euclid(1_000_000_000);
+    Some(cron.next(after)? * 1_000_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::ExecutableSchedule;
+
+    fn timer(
+        schedule: ExecutableSchedule,
+    ) -> Result<Option<Timer>, ScheduleError> {
+        Timer::new(&Executable {
+            schedule: Some(schedule),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_timer() {
+        assert_eq!(Timer::new(&Executable::default()).expect("timer"), None);
+        assert!(matches!(
+            timer(ExecutableSchedule {
+                cron: "@daily".to_string(),
+                interval_ms: 1000,
+                ..Default::default()
+            }),
+            Err(ScheduleError::InvalidSchedule(_))
+        ));
+        assert!(matches!(
+            timer(ExecutableSchedule {
+                cron: "* *".to_string(),
+                ..Default::default()
+            }),
+            Err(ScheduleError::InvalidSchedule(_))
+        ));
+
+        assert!(matches!(
+            timer(ExecutableSchedule { interval_ms: 10, ..Default::default() }),
+            Err(ScheduleError::InvalidSchedule(_))
+        ));
+
+        let hourly = Cron::parse("@hourly").expect("cron");
+        let hour = 1_667_260_800 * 1_000_000_000;
+        // 30 minutes and a half second past the hour.
+        let now = hour + 1_800_500_000_000;
+        let next = hour + 3_600_000_000_000;
+        assert_eq!(next_cron(&hourly, hour, now), Some(next));
+        // The next run follows the one that was due, even if the clock is
+        // behind it.
+        assert_eq!(
+            next_cron(&hourly, next, next - 1),
+            Some(next + 3_600_000_000_000)
+        );
+        // Runs that were missed are skipped.
+        assert_eq!(
+            next_cron(&hourly, hour, next + 1),
+            Some(next + 3_600_000_000_000)
+        );
+
+        let jittered = timer(ExecutableSchedule {
+            interval_ms: 1000,
+            jitter_ms: 100,
+            concurrency: ConcurrencyPolicy::Forbid as i32,
+            ..Default::default()
+        })
+        .expect("timer")
+        .expect("scheduled");
+        assert_eq!(jittered.concurrency, ConcurrencyPolicy::Forbid);
+        for _ in 0..20 {
+            assert!(jittered.jitter() <= Duration::from_millis(100));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interval_ticks_do_not_drift() {
+        let timer = timer(ExecutableSchedule {
+            interval_ms: 1000,
+            ..Default::default()
+        })
+        .expect("timer")
+        .expect("scheduled");
+        let start = Instant::now();
+        let mut ticks = timer.ticks();
+        assert!(ticks.tick().await);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        // A run that takes a while does not delay the next one.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(ticks.tick().await);
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+        // Runs missed by a run that takes longer than the interval are
+        // skipped.
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert!(ticks.tick().await);
+        assert_eq!(start.elapsed(), Duration::from_millis(4500));
+        assert!(ticks.tick().await);
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+}